@@ -1,8 +1,10 @@
-# 1: pkgbuild name to enter
+# 1: pkgbuild name to enter, 2: target arch
 LIBRARY="${LIBRARY:-/usr/share/makepkg}"
 source "${LIBRARY}/"util.sh
 source "${LIBRARY}/"source.sh
 source_makepkg_config
+# Otherwise always the host arch, even for a foreign target
+CARCH="$2"
 source "$1"/PKGBUILD
 SRCDEST="$1"
 HOLDVER=1
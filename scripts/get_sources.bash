@@ -1,8 +1,10 @@
-# 1: pkgbuild
+# 1: pkgbuild, 2: target arch
 LIBRARY="${LIBRARY:-/usr/share/makepkg}"
 source "${LIBRARY}/"util.sh
 source "${LIBRARY}/"source.sh
 source_makepkg_config
+# Otherwise always the host arch, even for a foreign target
+CARCH="$2"
 source $1
 get_all_sources_for_arch 'all_sources'
 get_all_vars_for_arch() { # 1: arrayname, 2: varname
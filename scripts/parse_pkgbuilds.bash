@@ -2,6 +2,8 @@ LIBRARY="${LIBRARY:-/usr/share/makepkg}"
 source "${LIBRARY}/"util.sh
 source "${LIBRARY}/"source.sh
 source_makepkg_config
+# 1: target arch, otherwise always the host one
+CARCH="$1"
 dump_array_with_optional_arch() { #1: var name, 2: report name
   declare -n array="$1"
   declare -n array_arch="$1_${CARCH}"
@@ -0,0 +1,270 @@
+// Target architecture of a run, foreign ones are run through qemu-user
+use std::{
+        fs::{
+            create_dir_all,
+            read_to_string,
+            OpenOptions,
+        },
+        io::Write,
+        path::{
+            Path,
+            PathBuf,
+        },
+        process::Command,
+    };
+
+use crate::error::{
+        Error,
+        Result
+    };
+
+struct Qemu {
+    /// Arch name used by pacman and makepkg
+    arch: &'static str,
+    /// Arch name used by qemu
+    qemu: &'static str,
+    /// Name of the same arch in std::env::consts::ARCH
+    rust: &'static str,
+    magic: &'static str,
+    mask: &'static str,
+}
+
+/// Magics and masks are the same as those in qemu's qemu-binfmt-conf.sh
+const QEMUS: &[Qemu] = &[
+    Qemu {
+        arch: "x86_64",
+        qemu: "x86_64",
+        rust: "x86_64",
+        magic: "\\x7fELF\\x02\\x01\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\
+            \\x00\\x02\\x00\\x3e\\x00",
+        mask: "\\xff\\xff\\xff\\xff\\xff\\xfe\\xfe\\x00\\xff\\xff\\xff\\xff\\xff\
+            \\xff\\xff\\xff\\xfe\\xff\\xff\\xff",
+    },
+    Qemu {
+        arch: "i686",
+        qemu: "i386",
+        rust: "x86",
+        magic: "\\x7fELF\\x01\\x01\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\
+            \\x00\\x02\\x00\\x03\\x00",
+        mask: "\\xff\\xff\\xff\\xff\\xff\\xfe\\xfe\\x00\\xff\\xff\\xff\\xff\\xff\
+            \\xff\\xff\\xff\\xfe\\xff\\xff\\xff",
+    },
+    Qemu {
+        arch: "aarch64",
+        qemu: "aarch64",
+        rust: "aarch64",
+        magic: "\\x7fELF\\x02\\x01\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\
+            \\x00\\x02\\x00\\xb7\\x00",
+        mask: "\\xff\\xff\\xff\\xff\\xff\\xff\\xff\\x00\\xff\\xff\\xff\\xff\\xff\
+            \\xff\\xff\\xff\\xfe\\xff\\xff\\xff",
+    },
+    Qemu {
+        arch: "armv7h",
+        qemu: "arm",
+        rust: "arm",
+        magic: "\\x7fELF\\x01\\x01\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\
+            \\x00\\x02\\x00\\x28\\x00",
+        mask: "\\xff\\xff\\xff\\xff\\xff\\xff\\xff\\x00\\xff\\xff\\xff\\xff\\xff\
+            \\xff\\xff\\xff\\xfe\\xff\\xff\\xff",
+    },
+    Qemu {
+        arch: "riscv64",
+        qemu: "riscv64",
+        rust: "riscv64",
+        magic: "\\x7fELF\\x02\\x01\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\
+            \\x00\\x02\\x00\\xf3\\x00",
+        mask: "\\xff\\xff\\xff\\xff\\xff\\xff\\xff\\x00\\xff\\xff\\xff\\xff\\xff\
+            \\xff\\xff\\xff\\xfe\\xff\\xff\\xff",
+    },
+    Qemu {
+        arch: "loong64",
+        qemu: "loongarch64",
+        rust: "loongarch64",
+        magic: "\\x7fELF\\x02\\x01\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\
+            \\x00\\x02\\x00\\x02\\x01",
+        mask: "\\xff\\xff\\xff\\xff\\xff\\xff\\xff\\x00\\xff\\xff\\xff\\xff\\xff\
+            \\xff\\xff\\xff\\xfe\\xff\\xff\\xff",
+    },
+];
+
+const BINFMT_MISC: &str = "/proc/sys/fs/binfmt_misc";
+
+/// All arch names supported as targets
+pub(crate) fn supported() -> impl Iterator<Item = &'static str> {
+    QEMUS.iter().map(|qemu| qemu.arch)
+}
+
+fn host() -> Option<&'static str> {
+    QEMUS.iter().find(|qemu| qemu.rust == std::env::consts::ARCH)
+        .map(|qemu| qemu.arch)
+}
+
+pub(crate) struct Target {
+    /// Only set if explicitly asked, pkgs then go into pkgs/[arch]
+    pub(crate) arch: Option<String>,
+    qemu: Option<&'static Qemu>,
+    pacman_conf: PathBuf,
+}
+
+impl Target {
+    pub(crate) fn new(arch: Option<&str>, pacman_conf: Option<&str>)
+        -> Result<Self>
+    {
+        let pacman_conf = PathBuf::from(
+            pacman_conf.unwrap_or("/etc/pacman.conf"));
+        let arch = match arch {
+            Some(arch) => arch,
+            None => return Ok(Self { arch: None, qemu: None, pacman_conf }),
+        };
+        let qemu = match QEMUS.iter().find(|qemu| qemu.arch == arch) {
+            Some(qemu) => qemu,
+            None => {
+                log::error!("Unsupported arch '{}', supported: {:?}", arch,
+                    supported().collect::<Vec<&str>>());
+                return Err(Error::InvalidConfig)
+            },
+        };
+        let qemu = if host() == Some(arch) {
+            None
+        } else if pacman_conf == Path::new("/etc/pacman.conf") {
+            log::error!("Foreign arch '{}' needs pacman_conf pointing to a \
+                pacman config with that arch's repos", arch);
+            return Err(Error::InvalidConfig)
+        } else {
+            log::info!("Building for foreign arch '{}' through qemu-{}",
+                arch, qemu.qemu);
+            Some(qemu)
+        };
+        Ok(Self {
+            arch: Some(arch.to_string()),
+            qemu,
+            pacman_conf,
+        })
+    }
+
+    /// The target arch name, the host one if not set
+    pub(crate) fn name(&self) -> &str {
+        match &self.arch {
+            Some(arch) => arch,
+            None => host().unwrap_or(std::env::consts::ARCH),
+        }
+    }
+
+    pub(crate) fn is_foreign(&self) -> bool {
+        self.qemu.is_some()
+    }
+
+    pub(crate) fn pacman_conf(&self) -> &Path {
+        &self.pacman_conf
+    }
+
+    /// The dir containing all pkgdirs, and updated/latest links
+    pub(crate) fn pkgs_dir(&self) -> PathBuf {
+        match &self.arch {
+            Some(arch) => PathBuf::from("pkgs").join(arch),
+            None => PathBuf::from("pkgs"),
+        }
+    }
+
+    pub(crate) fn set_pacman_command<'a>(&self, command: &'a mut Command)
+        -> &'a mut Command
+    {
+        command.arg("--config").arg(&self.pacman_conf);
+        if let Some(arch) = &self.arch {
+            command.arg("--arch").arg(arch);
+        }
+        command
+    }
+
+    /// Make sure foreign binaries could run in the root: the qemu-user
+    /// interpreter is registered in binfmt_misc (registered with fix-binary
+    /// if not yet), and copied into the same path in the root in case it's
+    /// not registered with fix-binary. Root is expected
+    pub(crate) fn setup_qemu(&self, root: &Path) -> Result<()> {
+        let qemu = match self.qemu {
+            Some(qemu) => qemu,
+            None => return Ok(()),
+        };
+        let binfmt_misc = Path::new(BINFMT_MISC);
+        if ! binfmt_misc.join("register").exists() {
+            log::error!("binfmt_misc is not mounted at '{}', could not run \
+                foreign binaries", BINFMT_MISC);
+            return Err(Error::BrokenEnvironment)
+        }
+        let entry = binfmt_misc.join(format!("qemu-{}", qemu.qemu));
+        let interpreter = if entry.exists() {
+            let content = match read_to_string(&entry) {
+                Ok(content) => content,
+                Err(e) => {
+                    log::error!("Failed to read binfmt entry '{}': {}",
+                        entry.display(), e);
+                    return Err(e.into())
+                },
+            };
+            if content.lines().next() != Some("enabled") {
+                log::error!("binfmt entry '{}' is disabled", entry.display());
+                return Err(Error::BrokenEnvironment)
+            }
+            match content.lines().find_map(|line|
+                line.strip_prefix("interpreter "))
+            {
+                Some(interpreter) => PathBuf::from(interpreter),
+                None => {
+                    log::error!("binfmt entry '{}' has no interpreter",
+                        entry.display());
+                    return Err(Error::BrokenEnvironment)
+                },
+            }
+        } else {
+            let interpreter = PathBuf::from(
+                format!("/usr/bin/qemu-{}-static", qemu.qemu));
+            if ! interpreter.exists() {
+                log::error!("Static qemu-user interpreter '{}' does not exist",
+                    interpreter.display());
+                return Err(Error::BrokenEnvironment)
+            }
+            Self::register(qemu, &interpreter)?;
+            interpreter
+        };
+        if ! interpreter.exists() {
+            log::error!("qemu-user interpreter '{}' does not exist on host",
+                interpreter.display());
+            return Err(Error::BrokenEnvironment)
+        }
+        let target = root.join(
+            interpreter.strip_prefix("/").unwrap_or(&interpreter));
+        if let Some(parent) = target.parent() {
+            if let Err(e) = create_dir_all(parent) {
+                log::error!("Failed to create dir '{}': {}",
+                    parent.display(), e);
+                return Err(e.into())
+            }
+        }
+        if let Err(e) = std::fs::copy(&interpreter, &target) {
+            log::error!("Failed to copy qemu-user interpreter '{}' into '{}': \
+                {}", interpreter.display(), target.display(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    fn register(qemu: &Qemu, interpreter: &Path) -> Result<()> {
+        log::info!("Registering '{}' into binfmt_misc", interpreter.display());
+        let rule = format!(":qemu-{}:M::{}:{}:{}:F", qemu.qemu,
+            qemu.magic, qemu.mask, interpreter.display());
+        let register = Path::new(BINFMT_MISC).join("register");
+        let mut file = match OpenOptions::new().write(true).open(&register) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open '{}': {}", register.display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = file.write_all(rule.as_bytes()) {
+            log::error!("Failed to register qemu-{} into binfmt_misc: {}",
+                qemu.qemu, e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 mod builder;
 mod dir;
+mod history;
 
 use crate::error::Result;
 
@@ -9,24 +10,43 @@ pub(crate) fn maybe_build(
     actual_identity: &crate::identity::IdentityActual,
     nobuild: bool,
     nonet: bool,
-    sign: Option<&str>
+    sign: Option<&str>,
+    target: &crate::arch::Target,
+    ccache: Option<&crate::ccache::Ccache>,
+    distcc: Option<&crate::distcc::Distcc>,
+    scheduler: &crate::scheduler::Scheduler,
+    dephash_strategy: &crate::config::DepHashStrategy,
+    lint: Option<&crate::config::Lint>,
+    report: &mut crate::report::Report
 ) -> Result<()>
 {
     if let Some(_root) = root {
         if nobuild {
             return Ok(())
         }
-        match crate::depend::split_pkgbuilds(pkgbuilds) {
-            Ok(layers) => {
-                for layer in layers {
-                    builder::build_any_needed_layer(
-                        &layer, &actual_identity, nonet, sign)?
-
-                }
-            },
-            Err(_) => builder::build_any_needed(
-                        &pkgbuilds, &actual_identity, nonet, sign)?,
+        // Without a valid graph, everything is built at once as before
+        let graph = crate::depend::graph_pkgbuilds(pkgbuilds).ok();
+        let mut history =
+            history::History::from_file_or_default(history::HISTORYFILE);
+        let mut builds = crate::changelog::Builds::from_file_or_default(
+            crate::changelog::BUILDSFILE);
+        builds.fill_from_report(crate::report::REPORTFILE);
+        let mut sonames = match dephash_strategy {
+            crate::config::DepHashStrategy::Soname => Some(
+                crate::soname::Sonames::from_file_or_default(
+                    crate::soname::SONAMESFILE)),
+            _ => None,
+        };
+        let r = builder::build_any_needed(
+                    &pkgbuilds, graph.as_ref(), &mut history, &mut builds,
+                    sonames.as_mut(), &actual_identity, nonet, sign, target,
+                    ccache, distcc, scheduler, lint, report);
+        let _ = history.to_file(history::HISTORYFILE);
+        let _ = builds.to_file(crate::changelog::BUILDSFILE);
+        if let Some(sonames) = &sonames {
+            let _ = sonames.to_file(crate::soname::SONAMESFILE);
         }
+        r?
     }
     Ok(())
 }
\ No newline at end of file
@@ -1,21 +1,46 @@
 use std::{
+        cmp::Reverse,
         path::PathBuf,
         process::{
             Child,
             Command,
         },
         thread::sleep,
-        time::Duration
+        time::{
+            Duration,
+            Instant,
+        },
     };
 
 use crate::{
-        build::dir::BuildDir,
+        arch::Target,
+        build::{
+            dir::BuildDir,
+            history::History,
+        },
+        ccache::Ccache,
+        changelog::{
+            Built,
+            Builds,
+        },
+        config::Lint,
+        depend::DepGraph,
+        distcc::Distcc,
         error::{
             Error,
             Result
         },
+        failure::{
+            Failure,
+            FailureKind,
+        },
         filesystem::remove_dir_all_try_best,
         identity::IdentityActual,
+        lint::{
+            self,
+            Finding,
+        },
+        logger,
         logfile::{
             LogFile,
             LogType,
@@ -24,10 +49,20 @@ use crate::{
             PKGBUILD,
             PKGBUILDs,
         },
+        report::{
+            BuildStatus,
+            CheckReport,
+            CheckStatus,
+            PackageReport,
+            Report,
+        },
         root::{
             OverlayRoot,
             BootstrappingOverlayRoot,
         },
+        metrics,
+        scheduler::Scheduler,
+        soname::Sonames,
     };
 
 enum RootState {
@@ -55,6 +90,11 @@ enum BuildState {
     Building {
         child: Child
     },
+    /// Built with check allow-failure, check() not run yet
+    Packaged,
+    Checking {
+        child: Child
+    },
     Built,
 }
 
@@ -69,21 +109,44 @@ struct Builder<'a> {
     builddir: BuildDir,
     temp_pkgdir: PathBuf,
     command: Command,
+    check_command: Option<Command>,
     tries: usize,
+    tries_max: usize,
+    /// Jobs this counts as while building
+    weight: usize,
+    /// PKGBUILDs this wants that're not built yet, it only starts after them
+    wants: Vec<&'a PKGBUILD>,
+    /// Length of the critical path from this, longer ones start first
+    priority: u64,
+    build_started: Option<Instant>,
+    duration: Option<Duration>,
     root_state: RootState,
     build_state: BuildState,
     log_path: PathBuf,
+    check: Option<CheckReport>,
+    lints: Vec<Finding>,
+    /// The last build, the changelog starts from it
+    previous: Option<Built>,
+    extract_log: Option<PathBuf>,
+    /// Of the last failed step, only reported if it's failed in the end
+    failure: Option<Failure>,
 }
 
 impl <'a> Builder<'a> {
-    const BUILD_MAX_TRIES: usize = 3;
-    fn from_pkgbuild(pkgbuild: &'a PKGBUILD, actual_identity: &IdentityActual)
+    fn from_pkgbuild(
+        pkgbuild: &'a PKGBUILD, actual_identity: &IdentityActual,
+        target: &Target, ccache: Option<&Ccache>, scheduler: &Scheduler,
+        builds: &Builds
+    )
         -> Result<Self>
     {
+        logger::aggregate(&pkgbuild.base);
         let builddir = BuildDir::new(&pkgbuild.base)?;
         let temp_pkgdir = pkgbuild.get_temp_pkgdir()?;
         let command = pkgbuild.get_build_command(
-            actual_identity, &temp_pkgdir)?;
+            actual_identity, target, ccache, &temp_pkgdir)?;
+        let check_command = pkgbuild.get_check_command(
+            actual_identity, target, ccache)?;
         let build_state = if pkgbuild.extracted {
             BuildState::Extracted
         } else {
@@ -94,18 +157,119 @@ impl <'a> Builder<'a> {
             builddir,
             temp_pkgdir,
             command,
+            check_command,
             tries: 0,
+            tries_max: scheduler.build_tries,
+            weight: scheduler.weight(&pkgbuild.base),
+            wants: vec![],
+            priority: 0,
+            build_started: None,
+            duration: None,
             root_state: RootState::default(),
             build_state,
             log_path: PathBuf::new(),
+            check: None,
+            lints: vec![],
+            previous: builds.get(&pkgbuild.base).cloned(),
+            extract_log: None,
+            failure: None,
         })
     }
 
+    /// What it's doing, or about to do, for logs
+    fn phase(&self) -> &'static str {
+        match (&self.root_state, &self.build_state) {
+            (RootState::None, _) | (RootState::Boostrapping { .. }, _) =>
+                "bootstrap",
+            (_, BuildState::None) | (_, BuildState::Extracting { .. }) =>
+                "extract",
+            (_, BuildState::Extracted) | (_, BuildState::Building { .. }) =>
+                "build",
+            (_, BuildState::Packaged) | (_, BuildState::Checking { .. }) =>
+                "check",
+            (_, BuildState::Built) => "finish",
+        }
+    }
+
+    /// Where it is in the chroot and build states, for metrics
+    fn state(&self) -> &'static str {
+        if ! self.wants.is_empty() {
+            return "waiting"
+        }
+        match (&self.root_state, &self.build_state) {
+            (RootState::None, _) => "pending",
+            (RootState::Boostrapping { .. }, _) => "bootstrapping",
+            (_, BuildState::None) => "bootstrapped",
+            (_, BuildState::Extracting { .. }) => "extracting",
+            (_, BuildState::Extracted) => "extracted",
+            (_, BuildState::Building { .. }) => "building",
+            (_, BuildState::Packaged) => "packaged",
+            (_, BuildState::Checking { .. }) => "checking",
+            (_, BuildState::Built) => "built",
+        }
+    }
+
+    fn into_report(self, status: BuildStatus, ccache: bool) -> PackageReport {
+        let failure = match status {
+            BuildStatus::Failed => self.failure,
+            _ => None,
+        };
+        let built = self.pkgbuild.to_built();
+        PackageReport {
+            base: self.pkgbuild.base.clone(),
+            pkgid: built.pkgid,
+            commit: built.commit,
+            version: built.version,
+            status,
+            tries: self.tries,
+            duration: self.duration.map(|duration| duration.as_secs()),
+            log: match self.log_path.as_os_str().is_empty() {
+                true => None,
+                false => Some(self.log_path),
+            },
+            check: self.check,
+            lints: self.lints,
+            failure,
+            ccache: match ccache {
+                true => Ccache::stats(&self.builddir.path),
+                false => None,
+            },
+        }
+    }
+
+    fn start_check(&mut self) -> Result<Child> {
+        let command = match &mut self.check_command {
+            Some(command) => command,
+            None => {
+                log::error!("Check command missing for '{}'",
+                    &self.pkgbuild.base);
+                return Err(Error::ImpossibleLogic)
+            },
+        };
+        let log_file = LogFile::new(LogType::Check, &self.pkgbuild.pkgid)?;
+        self.check = Some(CheckReport {
+            status: CheckStatus::Failed,
+            log: log_file.path.clone(),
+        });
+        match log_file.set_command(command)?.spawn() {
+            Ok(child) => {
+                log::info!("Start checking '{}'", &self.pkgbuild.base);
+                Ok(child)
+            },
+            Err(e) => {
+                log::error!("Failed to spawn checker for '{}': {}",
+                    &self.pkgbuild.base, e);
+                Err(e.into())
+            },
+        }
+    }
+
     fn start_extract(&mut self, actual_identity: &IdentityActual) -> Result<()> {
         match self.pkgbuild.extractor_source(actual_identity) {
-            Ok(child) => {
+            Ok((child, log_path)) => {
                 log::info!("Start extracting for pkgbuild '{}'",
                     &self.pkgbuild.base);
+                self.extract_log = Some(log_path);
                 self.build_state = BuildState::Extracting { child };
                 Ok(())
             },
@@ -117,8 +281,37 @@ impl <'a> Builder<'a> {
         }
     }
 
+    /// Check, lint and sign the built packages, then move them into the pkgdir
+    fn finish_packages(&mut self, actual_identity: &IdentityActual,
+        target: &Target, lint: Option<&Lint>, sign: Option<&str>
+    ) -> Result<()>
+    {
+        let packages = match self.pkgbuild.check_packages(&self.temp_pkgdir) {
+            Ok(packages) => packages,
+            Err(e) => {
+                if let Error::BadPackages(bad) = &e {
+                    self.failure = Some(Failure::bad_packages(
+                        &self.log_path, bad))
+                }
+                return Err(e)
+            },
+        };
+        self.lints = self.pkgbuild.lint_packages(&packages, lint, target)?;
+        if lint::fails(&self.lints) {
+            log::error!("Packages built for '{}' failed lint",
+                &self.pkgbuild.pkgid);
+            let bad: Vec<String> = packages.into_iter()
+                .map(|package| package.file).collect();
+            self.failure = Some(Failure::bad_packages(&self.log_path, &bad));
+            return Err(Error::BadPackages(bad))
+        }
+        self.pkgbuild.finish_build(actual_identity, &self.temp_pkgdir,
+            &packages, sign, self.previous.as_ref())
+    }
+
     fn step_build(&mut self,  heavy_load: bool, actual_identity: &IdentityActual,
-        sign: Option<&str>, jobs: &mut usize ) -> Result<()>
+        target: &Target, lint: Option<&Lint>, sign: Option<&str>,
+        jobs: &mut usize ) -> Result<()>
     {
         match &mut self.build_state {
             BuildState::None =>
@@ -140,6 +333,10 @@ impl <'a> Builder<'a> {
                             } else {
                                 log::error!("Failed to extract source for \
                                     pkgbuild '{}'", &self.pkgbuild.base);
+                                self.failure = Some(Failure::from_log(
+                                    &self.pkgbuild.pkgid,
+                                    self.extract_log.as_deref(),
+                                    FailureKind::Source));
                                 return Err(Error::BadChild { pid: None, code })
                             }
                         },
@@ -167,29 +364,49 @@ impl <'a> Builder<'a> {
                         },
                     };
                     self.build_state = BuildState::Building { child };
+                    self.build_started = Some(Instant::now());
                     self.tries += 1;
-                    *jobs += 1;
+                    // Only the failure of the last try is reported
+                    self.failure = None;
+                    *jobs += self.weight;
                     log::info!("Start building '{}', try {} of {}",
-                        &self.pkgbuild.base, self.tries, Self::BUILD_MAX_TRIES);
+                        &self.pkgbuild.base, self.tries, self.tries_max);
                 },
             BuildState::Building { child } =>
                 match child.try_wait() {
                     Ok(r) => match r {
                         Some(r) => {
-                            *jobs -= 1;
+                            *jobs -= self.weight;
                             log::info!(
                                 "Log of building '{}' was written to '{}'",
                                 &self.pkgbuild.pkgid, self.log_path.display());
                             if let Some(0) = r.code() {
-                                self.pkgbuild.finish_build(actual_identity,
-                                    &self.temp_pkgdir, sign)?;
+                                self.duration = self.build_started.map(
+                                    |started| started.elapsed());
+                                if let Err(e) = self.finish_packages(
+                                    actual_identity, target, lint, sign)
+                                {
+                                    if self.failure.is_none() {
+                                        self.failure = Some(Failure::packaging(
+                                            &self.log_path, &e))
+                                    }
+                                    return Err(e)
+                                }
                                 log::info!("Successfully built '{}'",
                                     &self.pkgbuild.base);
-                                self.build_state = BuildState::Built;
+                                self.build_state =
+                                    match self.check_command.is_some() {
+                                        true => BuildState::Packaged,
+                                        false => BuildState::Built,
+                                    };
                             } else {
                                 log::error!("Failed to build '{}'",
                                     &self.pkgbuild.base);
-                                if self.tries >= Self::BUILD_MAX_TRIES {
+                                self.failure = Some(Failure::from_log(
+                                    &self.pkgbuild.pkgid,
+                                    Some(&self.log_path),
+                                    FailureKind::Compile));
+                                if self.tries >= self.tries_max {
                                     log::error!("Max retries exceeded for '{}'",
                                         &self.pkgbuild.base);
                                     return Err(Error::BuildFailure)
@@ -216,10 +433,44 @@ impl <'a> Builder<'a> {
                     },
                     Err(e) => {
                         log::error!("Failed to wait for builder: {}", e);
-                        *jobs -= 1;
+                        *jobs -= self.weight;
                         return Err(e.into())
                     },
                 }
+            BuildState::Packaged =>
+                if ! heavy_load {
+                    match self.start_check() {
+                        Ok(child) => {
+                            self.build_state = BuildState::Checking { child };
+                            *jobs += self.weight;
+                        },
+                        // Check failure is only recorded, never fatal
+                        Err(_) => self.build_state = BuildState::Built,
+                    }
+                },
+            BuildState::Checking { child } =>
+                match child.try_wait() {
+                    Ok(r) => if let Some(r) = r {
+                        *jobs -= self.weight;
+                        if let Some(check) = &mut self.check {
+                            if let Some(0) = r.code() {
+                                log::info!("Check passed for '{}'",
+                                    &self.pkgbuild.base);
+                                check.status = CheckStatus::Passed
+                            } else {
+                                log::warn!("Check failed for '{}', allowed, \
+                                    log was written to '{}'",
+                                    &self.pkgbuild.base, check.log.display());
+                            }
+                        }
+                        self.build_state = BuildState::Built;
+                    },
+                    Err(e) => {
+                        log::error!("Failed to wait for checker: {}", e);
+                        *jobs -= self.weight;
+                        return Err(e.into())
+                    },
+                },
             BuildState::Built => {
                 log::error!("Built status should not be met by state machine");
                 return Err(Error::ImpossibleLogic)
@@ -229,12 +480,15 @@ impl <'a> Builder<'a> {
     }
 
     fn step(&mut self, heavy_load: bool, actual_identity: &IdentityActual,
-            nonet: bool, sign: Option<&str>, jobs: &mut usize ) -> Result<()>
+            target: &Target, ccache: Option<&Ccache>, distcc: Option<&Distcc>,
+            nonet: bool, lint: Option<&Lint>, sign: Option<&str>,
+            jobs: &mut usize
+    ) -> Result<()>
     {
         match &mut self.root_state {
             RootState::None => if ! heavy_load {
                 match self.pkgbuild.get_bootstrapping_overlay_root(
-                    actual_identity, nonet)
+                    actual_identity, target, ccache, distcc, nonet)
                 {
                     Ok(bootstrapping_root) => {
                         log::info!("Start chroot bootstrapping for pkgbuild '{}'",
@@ -296,115 +550,120 @@ impl <'a> Builder<'a> {
             },
             RootState::Bootstrapped { root } => {
                 let _ = root;
-                self.step_build(heavy_load, actual_identity, sign, jobs)?
+                self.step_build(heavy_load, actual_identity, target, lint,
+                    sign, jobs)?
             },
         }
         Ok(())
     }
 }
 
-fn check_heavy_load(jobs: usize, cores: usize) -> bool {
-    if jobs >= cores {
-        return true
-    }
-    if match procfs::CpuPressure::new() {
-        Ok(cpu_pressure) => {
-            let some = cpu_pressure.some;
-            some.avg10 > 10.00 || some.avg60 > 10.00 || some.avg300 > 10.00
-        },
-        Err(e) => {
-            log::error!("Failed to get CPU pressure: {}", e);
-            false
-        },
-    } {
-        return true
-    }
-    match procfs::LoadAverage::new() {
-        Ok(load_avg) => {
-            let max_load = (cores + 2) as f32;
-            load_avg.one >= max_load ||
-            load_avg.five >= max_load ||
-            load_avg.fifteen >= max_load
-        },
-        Err(e) => {
-            log::error!("Failed to get load avg: {}", e);
-            true
-        },
-    }
-}
-
 struct Builders<'a> {
     builders: Vec<Builder<'a>>,
     actual_identity: &'a IdentityActual,
     nonet: bool,
-    sign: Option<&'a str>
+    sign: Option<&'a str>,
+    target: &'a Target,
+    ccache: Option<&'a Ccache>,
+    distcc: Option<&'a Distcc>,
+    scheduler: &'a Scheduler,
+    lint: Option<&'a Lint>,
 }
 
 impl<'a> Builders<'a> {
+    /// Without graph all builders start as soon as possible in config order,
+    /// otherwise each only starts after what it wants, by critical path
     fn from_pkgbuilds(
-        pkgbuilds: &'a PKGBUILDs, actual_identity: &'a IdentityActual,
-        nonet: bool, sign: Option<&'a str>
+        pkgbuilds: &'a PKGBUILDs, graph: Option<&DepGraph<'a>>,
+        history: &History, builds: &Builds,
+        actual_identity: &'a IdentityActual, nonet: bool,
+        sign: Option<&'a str>, target: &'a Target,
+        ccache: Option<&'a Ccache>, distcc: Option<&'a Distcc>,
+        scheduler: &'a Scheduler, lint: Option<&'a Lint>
     ) -> Result<Self>
     {
         BuildDir::prepare()?;
+        let paths = match graph {
+            Some(graph) => Some(graph.critical_paths(|pkgbuild|
+                match pkgbuild.need_build {
+                    true => history.estimate(&pkgbuild.base),
+                    false => 0,
+                })?),
+            None => None,
+        };
         let mut builders = vec![];
-        for pkgbuild in pkgbuilds.0.iter() {
+        for (id, pkgbuild) in pkgbuilds.0.iter().enumerate() {
             if ! pkgbuild.need_build {
                 continue
             }
-            match Builder::from_pkgbuild(pkgbuild, actual_identity) {
-                Ok(builder) => builders.push(builder),
+            let mut builder = match Builder::from_pkgbuild(
+                pkgbuild, actual_identity, target, ccache, scheduler, builds)
+            {
+                Ok(builder) => builder,
                 Err(e) => {
                     log::error!("Failed to create builder for pkgbuild");
                     return Err(e)
                 },
+            };
+            if let (Some(graph), Some(paths)) = (graph, &paths) {
+                builder.wants = graph.nodes[id].wants.iter().filter_map(
+                    |want| {
+                        let want = graph.nodes[want.id].pkgbuild;
+                        want.need_build.then_some(want)
+                    }).collect();
+                builder.priority = paths[id];
+            }
+            builders.push(builder)
+        }
+        // Stable, so config order is kept for the same priority
+        builders.sort_by_key(|builder| Reverse(builder.priority));
+        if paths.is_some() {
+            let mut line = String::from("Build order by critical path:");
+            for builder in builders.iter() {
+                line.push_str(&format!(" '{}'({}s)",
+                    builder.pkgbuild.base, builder.priority));
             }
+            log::info!("{}", line);
         }
         Ok(Self {
             builders,
             actual_identity,
             nonet,
             sign,
+            target,
+            ccache,
+            distcc,
+            scheduler,
+            lint,
         })
     }
 
-    fn from_pkgbuild_layer(
-        pkgbuild_layer: &Vec<&'a PKGBUILD>, actual_identity: &'a IdentityActual,
-        nonet: bool, sign: Option<&'a str>
-    ) -> Result<Self>
-    {
-        BuildDir::prepare()?;
-        let mut builders = vec![];
-        for pkgbuild in pkgbuild_layer.iter() {
-            if ! pkgbuild.need_build {
-                continue
-            }
-            match Builder::from_pkgbuild(pkgbuild, actual_identity) {
-                Ok(builder) => builders.push(builder),
-                Err(e) => {
-                    log::error!("Failed to create builder for pkgbuild: {}", e);
-                    return Err(e)
-                },
+    /// Drop builders wanting a failed PKGBUILD, transitively
+    fn skip_wanting(&mut self, failed: &PKGBUILD, report: &mut Report) {
+        let mut failed = vec![failed];
+        while let Some(pkgbuild) = failed.pop() {
+            let (skipped, builders): (Vec<Builder>, Vec<Builder>) =
+                std::mem::take(&mut self.builders).into_iter().partition(
+                    |builder| builder.wants.iter().any(|want|
+                        std::ptr::eq(*want, pkgbuild)));
+            self.builders = builders;
+            for builder in skipped {
+                let _scope = logger::package(&builder.pkgbuild.base, "skip");
+                log::error!("Skipped building '{}' as it wants '{}' which \
+                    failed", &builder.pkgbuild.base, &pkgbuild.base);
+                failed.push(builder.pkgbuild);
+                metrics::record_build(&builder.pkgbuild.base, "skipped", None);
+                report.add(builder.into_report(BuildStatus::Skipped, false))
             }
         }
-        Ok(Self {
-            builders,
-            actual_identity,
-            nonet,
-            sign,
-        })
     }
 
-    fn work(&mut self)  -> Result<()>
+    /// Sonames are only recorded with the soname strategy
+    fn work(
+        &mut self, history: &mut History, builds: &mut Builds,
+        mut sonames: Option<&mut Sonames>, report: &mut Report
+    ) -> Result<()>
     {
-        let cpuinfo = match procfs::CpuInfo::new() {
-            Ok(cpuinfo) => cpuinfo,
-            Err(e) => {
-                log::error!("Failed to get cpuinfo: {}", e);
-                return Err(Error::ProcError(e))
-            },
-        };
-        let cores = cpuinfo.num_cores();
         let mut r = Ok(());
         let mut jobs = 0;
         loop {
@@ -413,32 +672,78 @@ impl<'a> Builders<'a> {
             for (id, builder) in
                 self.builders.iter_mut().enumerate()
             {
-                let heavy_load = check_heavy_load(jobs, cores);
-                match builder.step(heavy_load, self.actual_identity, self.nonet,
-                                    self.sign, &mut jobs)
+                if ! builder.wants.is_empty() {
+                    continue
+                }
+                // Remote slots are only extra capacity for builds using
+                // distcc, the load checks still only look at local load
+                let extra = match builder.pkgbuild.distcc(self.distcc) {
+                    Some(distcc) => distcc.slots(),
+                    None => 0,
+                };
+                let heavy_load = self.scheduler.heavy_load(jobs, extra);
+                let _scope = logger::package(&builder.pkgbuild.base,
+                    builder.phase());
+                match builder.step(heavy_load, self.actual_identity,
+                                    self.target, self.ccache, self.distcc,
+                                    self.nonet, self.lint, self.sign, &mut jobs)
                 {
                     Ok(_) => if let BuildState::Built = builder.build_state {
-                        finished = Some(id);
+                        finished = Some((id, false));
                         break
                     },
                     Err(e) => {
                         r = Err(e);
-                        finished = Some(id);
+                        finished = Some((id, true));
+                        break
                     },
                 }
                 if heavy_load {
-                    sleep(Duration::from_secs(1))
+                    sleep(self.scheduler.busy_interval)
                 }
             }
-            if let Some(id) = finished {
-                let builder = self.builders.swap_remove(id);
+            if let Some((id, failed)) = finished {
+                // Not swap_remove, to keep the order by priority
+                let builder = self.builders.remove(id);
+                let _scope = logger::package(&builder.pkgbuild.base, "finish");
                 log::info!("Finished builder for PKGBUILD '{}'",
                     &builder.pkgbuild.base);
+                let pkgbuild = builder.pkgbuild;
+                if let Some(duration) = builder.duration {
+                    history.record(&pkgbuild.base, duration)
+                }
+                metrics::record_build(&pkgbuild.base, match failed {
+                    true => "failed",
+                    false => "built",
+                }, builder.duration);
+                report.add(builder.into_report(match failed {
+                    true => BuildStatus::Failed,
+                    false => BuildStatus::Built,
+                }, self.ccache.is_some()));
+                if failed {
+                    self.skip_wanting(pkgbuild, report)
+                } else {
+                    builds.record(&pkgbuild.base, pkgbuild.to_built());
+                    if let Some(sonames) = sonames.as_mut() {
+                        match pkgbuild.read_linked() {
+                            Ok(linked) => sonames.record(&pkgbuild.base, linked),
+                            Err(_) => log::warn!("Failed to read sonames \
+                                linked by '{}', its next build would hash all \
+                                sonames of its deps", pkgbuild.pkgid),
+                        }
+                    }
+                    for builder in self.builders.iter_mut() {
+                        builder.wants.retain(|want|
+                            ! std::ptr::eq(*want, pkgbuild))
+                    }
+                }
             }
             if self.builders.is_empty() {
                 break
             }
-            sleep(Duration::from_millis(100))
+            metrics::record_builders(
+                self.builders.iter().map(|builder| builder.state()), jobs);
+            sleep(self.scheduler.poll_interval)
             // if jobs > jobs_last && jobs - jobs_last > 1 {
             //     sleep(Duration::from_secs(5))
             // } else if check_heavy_load(jobs, cores) {
@@ -455,22 +760,17 @@ impl<'a> Builders<'a> {
     }
 }
 
-pub(super) fn build_any_needed(
-    pkgbuilds: &PKGBUILDs,  actual_identity: &IdentityActual,
-    nonet: bool, sign: Option<&str>
+pub(super) fn build_any_needed<'a>(
+    pkgbuilds: &'a PKGBUILDs, graph: Option<&DepGraph<'a>>,
+    history: &mut History, builds: &mut Builds, sonames: Option<&mut Sonames>,
+    actual_identity: &IdentityActual,
+    nonet: bool, sign: Option<&str>, target: &Target, ccache: Option<&Ccache>,
+    distcc: Option<&Distcc>, scheduler: &Scheduler, lint: Option<&Lint>,
+    report: &mut Report
 ) -> Result<()>
 {
-    Builders::from_pkgbuilds(pkgbuilds, actual_identity, nonet, sign)?
-        .work()?;
+    Builders::from_pkgbuilds(pkgbuilds, graph, history, builds,
+        actual_identity, nonet, sign, target, ccache, distcc, scheduler, lint)?
+        .work(history, builds, sonames, report)?;
     Ok(())
 }
-
-pub(super) fn build_any_needed_layer(
-    pkgbuild_layer: &Vec<&PKGBUILD>,  actual_identity: &IdentityActual,
-    nonet: bool, sign: Option<&str>
-) -> Result<()>
-{
-    Builders::from_pkgbuild_layer(pkgbuild_layer, actual_identity, nonet, sign)?
-        .work()?;
-    Ok(())
-}
\ No newline at end of file
@@ -0,0 +1,107 @@
+// Durations of past builds, to estimate how long the next ones would take
+use std::{
+        collections::BTreeMap,
+        fs::File,
+        path::Path,
+        time::Duration,
+    };
+
+use serde::{
+        Deserialize,
+        Serialize,
+    };
+
+use crate::error::{
+        Error,
+        Result,
+    };
+
+pub(super) const HISTORYFILE: &str = "history.yaml";
+
+/// Seconds of the last builds, by pkgbase
+#[derive(Default, Deserialize, Serialize)]
+pub(super) struct History {
+    durations: BTreeMap<String, u64>,
+}
+
+impl History {
+    fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open build history '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        match serde_yaml::from_reader(file) {
+            Ok(history) => Ok(history),
+            Err(e) => {
+                log::error!("Failed to parse build history '{}': {}",
+                    path.as_ref().display(), e);
+                Err(Error::InvalidConfig)
+            },
+        }
+    }
+
+    /// Read the existing history if there's one, otherwise an empty one
+    pub(super) fn from_file_or_default<P: AsRef<Path>>(path: P) -> Self {
+        if ! path.as_ref().exists() {
+            return Self::default()
+        }
+        match Self::from_file(&path) {
+            Ok(history) => history,
+            Err(_) => {
+                log::warn!("Existing build history '{}' unusable, would \
+                    overwrite it", path.as_ref().display());
+                Self::default()
+            },
+        }
+    }
+
+    pub(super) fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut temp_name = path.as_ref().as_os_str().to_owned();
+        temp_name.push(".temp");
+        let file = match File::create(&temp_name) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to create build history '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = serde_yaml::to_writer(file, self) {
+            log::error!("Failed to write build history '{}': {}",
+                path.as_ref().display(), e);
+            return Err(std::io::Error::other(e).into())
+        }
+        if let Err(e) = std::fs::rename(&temp_name, &path) {
+            log::error!("Failed to move build history into '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    /// Averaged with the last one, so a single odd build does not swing it
+    pub(super) fn record(&mut self, base: &str, duration: Duration) {
+        let secs = duration.as_secs();
+        let secs = match self.durations.get(base) {
+            Some(last) => (last + secs) / 2,
+            None => secs,
+        };
+        self.durations.insert(base.to_string(), secs);
+    }
+
+    /// Estimated seconds to build, the average of all known ones if this one
+    /// was never built, so never-built ones are neither favored nor starved
+    pub(super) fn estimate(&self, base: &str) -> u64 {
+        if let Some(secs) = self.durations.get(base) {
+            return *secs
+        }
+        if self.durations.is_empty() {
+            return 1
+        }
+        self.durations.values().sum::<u64>() / self.durations.len() as u64
+    }
+}
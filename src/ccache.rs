@@ -0,0 +1,167 @@
+// Compiler cache shared by all builds, scoped per arch and toolchain, bound
+// into every overlay root
+use std::{
+        fs::{
+            read_dir,
+            read_to_string,
+            symlink_metadata,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        process::Command,
+    };
+
+use crate::{
+        arch::Target,
+        config::Ccache as CcacheConfig,
+        error::Result,
+        filesystem::{
+            create_dir_all_under_owned_by,
+            remove_dir_all_try_best,
+        },
+        identity::{
+            Identity,
+            IdentityActual,
+        },
+        report::{
+            CcacheReport,
+            CcacheStats,
+        },
+    };
+
+/// Where the cache is bound in the overlay roots
+pub(crate) const CHROOT_DIR: &str = "var/cache/ccache";
+/// Per-build stats log, relative to the build dir
+const STATS_LOG: &str = "ccache-stats.log";
+
+/// The toolchain in the base root, to scope the cache, so caches from
+/// different compilers never mix
+fn toolchain(root: &Path) -> String {
+    let entries = match read_dir(root.join("var/lib/pacman/local")) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Failed to read local DB of base root: {}", e);
+            return String::from("unknown")
+        },
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(version) = name.strip_prefix("gcc-") {
+            if version.starts_with(|c: char| c.is_ascii_digit()) {
+                return name
+            }
+        }
+    }
+    String::from("unknown")
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut size = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if let Ok(metadata) = symlink_metadata(&path) {
+            if metadata.is_dir() {
+                size += dir_size(&path)
+            } else {
+                size += metadata.len()
+            }
+        }
+    }
+    size
+}
+
+/// The cache dir for this run
+pub(crate) struct Ccache {
+    /// Absolute path on host
+    dir: PathBuf,
+    max_size: Option<String>,
+}
+
+impl Ccache {
+    /// Prepare the cache dir for the toolchain in the base root, caches for
+    /// other toolchains of the same arch are pruned unless noclean
+    pub(crate) fn new(config: &CcacheConfig, root: &Path, target: &Target,
+        actual_identity: &IdentityActual, noclean: bool
+    ) -> Result<Self>
+    {
+        let prefix = format!("{}-", target.name());
+        let scope = format!("{}{}", prefix, toolchain(root));
+        let parent = actual_identity.cwd().join("ccache");
+        create_dir_all_under_owned_by(Path::new("ccache").join(&scope),
+            actual_identity.cwd(), actual_identity.uid().into(),
+            actual_identity.gid().into())?;
+        if ! noclean {
+            if let Ok(entries) = read_dir(&parent) {
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if name.starts_with(&prefix) && name != scope {
+                        log::info!("Pruning compiler cache '{}' of another \
+                            toolchain", name);
+                        let _ = remove_dir_all_try_best(entry.path());
+                    }
+                }
+            }
+        }
+        let dir = parent.join(&scope);
+        log::info!("Using compiler cache at '{}'", dir.display());
+        Ok(Self {
+            dir,
+            max_size: config.max_size.clone(),
+        })
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// makepkg.conf lines enabling ccache
+    pub(crate) fn conf_append(&self) -> &'static str {
+        "\n# Compiler cache managed by arb\nBUILDENV+=(ccache)\n"
+    }
+
+    pub(crate) fn set_command<'a>(&self, command: &'a mut Command,
+        build: &Path
+    ) -> &'a mut Command
+    {
+        command
+            .env("CCACHE_DIR", Path::new("/").join(CHROOT_DIR))
+            .env("CCACHE_STATSLOG", build.join(STATS_LOG));
+        if let Some(max_size) = &self.max_size {
+            command.env("CCACHE_MAXSIZE", max_size);
+        }
+        command
+    }
+
+    /// Hits and misses of a single build, from the stats log ccache wrote
+    /// into its build dir
+    pub(crate) fn stats(build: &Path) -> Option<CcacheStats> {
+        let content = read_to_string(build.join(STATS_LOG)).ok()?;
+        let mut stats = CcacheStats::default();
+        for line in content.lines() {
+            match line.trim() {
+                "direct_cache_hit" | "preprocessed_cache_hit" =>
+                    stats.hits += 1,
+                "cache_miss" => stats.misses += 1,
+                _ => (),
+            }
+        }
+        if stats.hits + stats.misses > 0 {
+            stats.hit_rate =
+                stats.hits as f64 / (stats.hits + stats.misses) as f64
+        }
+        Some(stats)
+    }
+
+    pub(crate) fn to_report(&self) -> CcacheReport {
+        CcacheReport {
+            dir: self.dir.clone(),
+            size: dir_size(&self.dir),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+// Changes of PKGBUILDs between their last builds and the current ones, from
+// the git history of their bare repos under sources/PKGBUILD
+use std::{
+        collections::BTreeMap,
+        fs::File,
+        path::Path,
+    };
+
+use git2::Oid;
+
+use serde::{
+        Deserialize,
+        Serialize,
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result,
+        },
+        source::git,
+    };
+
+pub(crate) const BUILDSFILE: &str = "builds.yaml";
+/// Commits listed at most in a changelog
+const CHANGES_MAX: usize = 100;
+
+/// What the last build of a PKGBUILD was from
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Built {
+    pub(crate) pkgid: String,
+    /// The commit PKGBUILD was taken from, never the subtree id
+    pub(crate) commit: String,
+    pub(crate) version: String,
+}
+
+/// What's needed from a package in the run report
+#[derive(Deserialize)]
+struct ReportedPackage {
+    base: String,
+    pkgid: String,
+    #[serde(default)]
+    commit: Option<String>,
+    #[serde(default)]
+    version: Option<String>,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct Reported {
+    packages: Vec<ReportedPackage>,
+}
+
+/// By pkgbase
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct Builds {
+    built: BTreeMap<String, Built>,
+}
+
+impl Builds {
+    fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open builds record '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        match serde_yaml::from_reader(file) {
+            Ok(builds) => Ok(builds),
+            Err(e) => {
+                log::error!("Failed to parse builds record '{}': {}",
+                    path.as_ref().display(), e);
+                Err(Error::InvalidConfig)
+            },
+        }
+    }
+
+    /// Read the existing record if there's one, otherwise an empty one
+    pub(crate) fn from_file_or_default<P: AsRef<Path>>(path: P) -> Self {
+        if ! path.as_ref().exists() {
+            return Self::default()
+        }
+        match Self::from_file(&path) {
+            Ok(builds) => builds,
+            Err(_) => {
+                log::warn!("Existing builds record '{}' unusable, would \
+                    overwrite it", path.as_ref().display());
+                Self::default()
+            },
+        }
+    }
+
+    pub(crate) fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut temp_name = path.as_ref().as_os_str().to_owned();
+        temp_name.push(".temp");
+        let file = match File::create(&temp_name) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to create builds record '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = serde_yaml::to_writer(file, self) {
+            log::error!("Failed to write builds record '{}': {}",
+                path.as_ref().display(), e);
+            return Err(std::io::Error::other(e).into())
+        }
+        if let Err(e) = std::fs::rename(&temp_name, &path) {
+            log::error!("Failed to move builds record into '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    /// PKGBUILDs without a record, e.g. last built before it's recorded, get
+    /// theirs from the packages built in the last run, if its report says so
+    pub(crate) fn fill_from_report<P: AsRef<Path>>(&mut self, path: P) {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+        let reported: Reported = match serde_json::from_reader(file) {
+            Ok(reported) => reported,
+            Err(e) => {
+                log::warn!("Failed to parse last report '{}' for the last \
+                    builds: {}", path.as_ref().display(), e);
+                return
+            },
+        };
+        for package in reported.packages {
+            if package.status != "built" ||
+                self.built.contains_key(&package.base)
+            {
+                continue
+            }
+            let (commit, version) = match (package.commit, package.version) {
+                (Some(commit), Some(version)) => (commit, version),
+                _ => continue,
+            };
+            log::info!("Last build of '{}' taken from the last report",
+                package.base);
+            self.built.insert(package.base, Built {
+                pkgid: package.pkgid,
+                commit,
+                version,
+            });
+        }
+    }
+
+    pub(crate) fn get(&self, base: &str) -> Option<&Built> {
+        self.built.get(base)
+    }
+
+    pub(crate) fn record(&mut self, base: &str, built: Built) {
+        self.built.insert(base.to_string(), built);
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Change {
+    pub(crate) id: String,
+    /// First line of the message
+    pub(crate) summary: String,
+    pub(crate) author: String,
+    /// Commit time, seconds since epoch
+    pub(crate) time: i64,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Changelog {
+    pub(crate) pkgbase: String,
+    pub(crate) pkgid: String,
+    pub(crate) commit: String,
+    pub(crate) version: String,
+    /// The last build, none if this is the first one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) previous: Option<Built>,
+    /// Commits since the last build changing the PKGBUILD, newest first, none
+    /// for the first build, or a rebuild from the same commit
+    pub(crate) changes: Vec<Change>,
+    /// Whether there're more changes than listed
+    #[serde(default)]
+    pub(crate) truncated: bool,
+}
+
+/// The changelog stored in the pkgdir, beside the packages
+pub(crate) fn file_name(pkgbase: &str) -> String {
+    format!("{}.changelog.json", pkgbase)
+}
+
+impl Changelog {
+    pub(crate) fn new(
+        repo: &git::Repo, pkgbase: &str, pkgid: &str, commit: Oid,
+        version: &str, subtree: Option<&Path>, previous: Option<&Built>
+    ) -> Result<Self>
+    {
+        let (changes, truncated) = match previous {
+            Some(previous) => {
+                let from = Oid::from_str(&previous.commit).ok();
+                let (commits, mut truncated) = repo.commits_between(
+                    from, commit, subtree, CHANGES_MAX)?;
+                if from.is_none() {
+                    log::warn!("Illegal commit '{}' recorded for the last \
+                        build of '{}'", previous.commit, pkgbase);
+                    truncated = true
+                }
+                (commits.iter().map(|commit| Change {
+                    id: commit.id().to_string(),
+                    summary: commit.summary().unwrap_or_default().to_string(),
+                    author: commit.author().name().unwrap_or_default()
+                        .to_string(),
+                    time: commit.time().seconds(),
+                }).collect(), truncated)
+            },
+            None => (vec![], false),
+        };
+        Ok(Self {
+            pkgbase: pkgbase.into(),
+            pkgid: pkgid.into(),
+            commit: commit.to_string(),
+            version: version.into(),
+            previous: previous.cloned(),
+            changes,
+            truncated,
+        })
+    }
+
+    /// One line, e.g. '1.0-1 -> 1.1-1, 3 commits'
+    pub(crate) fn describe(&self) -> String {
+        let previous = match &self.previous {
+            Some(previous) => previous,
+            None => return format!("{}, first build", self.version),
+        };
+        let version = match previous.version == self.version {
+            true => self.version.clone(),
+            false => format!("{} -> {}", previous.version, self.version),
+        };
+        match (self.changes.len(), self.truncated) {
+            (0, false) => format!("{}, rebuilt without PKGBUILD changes",
+                version),
+            (1, false) => format!("{}, 1 commit", version),
+            (count, false) => format!("{}, {} commits", version, count),
+            (count, true) => format!("{}, {}+ commits", version, count),
+        }
+    }
+
+    pub(crate) fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open changelog '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        match serde_json::from_reader(file) {
+            Ok(changelog) => Ok(changelog),
+            Err(e) => {
+                log::error!("Failed to parse changelog '{}': {}",
+                    path.as_ref().display(), e);
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+                    .into())
+            },
+        }
+    }
+
+    pub(crate) fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut temp_name = path.as_ref().as_os_str().to_owned();
+        temp_name.push(".temp");
+        let file = match File::create(&temp_name) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to create changelog '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = serde_json::to_writer_pretty(file, self) {
+            log::error!("Failed to write changelog '{}': {}",
+                path.as_ref().display(), e);
+            return Err(std::io::Error::other(e).into())
+        }
+        if let Err(e) = std::fs::rename(&temp_name, &path) {
+            log::error!("Failed to move changelog into '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,27 @@
 mod arg;
+mod check;
 mod pacman;
 mod file;
+mod include;
 
-pub(crate) use arg::Arg;
+pub(crate) use arg::{
+    Arg,
+    Command,
+    GraphFormat,
+    LogFormat,
+};
+pub(crate) use include::check_file;
 pub(crate) use pacman::Config as PacmanConfig;
+pub(crate) use file::Ccache;
+pub(crate) use file::Distcc;
+pub(crate) use file::Scheduler;
+pub(crate) use file::CheckMode;
 pub(crate) use file::Config;
 pub(crate) use file::DepHashStrategy;
-pub(crate) use file::Pkgbuild;
\ No newline at end of file
+pub(crate) use file::Lint;
+pub(crate) use file::LintLevel;
+pub(crate) use file::Pkgbuild;
+pub(crate) use file::PkgbuildOrigin;
+pub(crate) use file::Publish;
+pub(crate) use file::PublishGithub;
+pub(crate) use file::PublishS3;
\ No newline at end of file
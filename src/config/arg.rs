@@ -1,4 +1,8 @@
-use clap::Parser;
+use clap::{
+        Parser,
+        Subcommand,
+        ValueEnum,
+    };
 
 
 #[derive(Parser, Debug)]
@@ -30,6 +34,11 @@ pub(crate) struct Arg {
     #[arg(short='G', long, default_value_t = false)]
     pub(crate) holdgit: bool,
 
+    /// Build exactly the PKGBUILDs and git sources recorded in arb.lock,
+    /// refuse to update them
+    #[arg(short='L', long, default_value_t = false)]
+    pub(crate) locked: bool,
+
     /// Skip integrity check for netfile sources if they're found
     #[arg(short='I', long, default_value_t = false)]
     pub(crate) skipint: bool,
@@ -46,6 +55,10 @@ pub(crate) struct Arg {
     #[arg(short='N', long, default_value_t = false)]
     pub(crate) nonet: bool,
 
+    /// Do not publish the packages even if publish is configured
+    #[arg(long, default_value_t = false)]
+    pub(crate) nopublish: bool,
+
     /// Drop to the specific uid:gid pair, instead of getting from SUDO_UID/GID
     #[arg(short='d', long)]
     pub(crate) drop: Option<String>,
@@ -57,5 +70,51 @@ pub(crate) struct Arg {
 
     /// The GnuPG key ID used to sign packages
     #[arg(short, long)]
-    pub(crate) sign: Option<String>
-}
\ No newline at end of file
+    pub(crate) sign: Option<String>,
+
+    /// The arch to build packages for, e.g. aarch64, foreign ones are built
+    /// through qemu-user, packages are then stored under pkgs/[arch]
+    #[arg(short, long)]
+    pub(crate) arch: Option<String>,
+
+    /// Format of logs to stdout, json for one object per line with the
+    /// package, phase and domain each line is about
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub(crate) log_format: LogFormat,
+
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Only check the config and all files it includes, report every problem
+    /// with its location
+    CheckConfig {
+        /// Optional config.yaml file, overrides the one before the command
+        config: Option<String>,
+    },
+    /// Only output the dependency graph among PKGBUILDs, fail if they want
+    /// each other in cycles
+    Graph {
+        /// Format of the graph
+        #[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+
+        /// File to write the graph into [default: graph.dot or graph.json]
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum GraphFormat {
+    Dot,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
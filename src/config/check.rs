@@ -0,0 +1,736 @@
+// Validation of config files, every problem is reported with its location
+use std::{
+        collections::HashMap,
+        fmt::Display,
+        path::{
+            Component,
+            Path,
+            PathBuf,
+        },
+        process::{
+            Command,
+            Stdio,
+        },
+        str::FromStr,
+    };
+
+use serde_yaml::{
+        Mapping,
+        Value,
+    };
+use url::Url;
+
+use crate::identity::IdentityActual;
+
+use super::file::{
+        Ccache,
+        DepHashStrategy,
+        Distcc,
+        Lint,
+        Publish,
+        Scheduler,
+        Pkgbuild,
+        PkgbuildOrigin,
+    };
+
+const KEYS_TOP: &[&str] = &[
+    "arch", "basepkgs", "buildenv", "ccache", "cflags", "cxxflags",
+    "dephash_strategy", "distcc", "env", "gmr", "holdgit", "holdpkg",
+    "home_binds", "include", "lint", "makeflags", "makepkg_conf", "metrics",
+    "nobuild", "noclean", "nonet", "nopublish", "options", "pacman_conf",
+    "pkgbuilds", "proxy", "proxy_after", "publish", "scheduler", "sign",
+    "skipint"];
+
+const KEYS_PKGBUILD: &[&str] = &[
+    "binds", "branch", "buildenv", "cflags", "check", "commit", "cxxflags", "deps", "env",
+    "home_binds", "lint", "makedeps", "makeflags", "makepkg_conf", "options", "origin",
+    "patches", "rebuild_on_dep_change", "subtree", "tag", "url"];
+
+const KEYS_CCACHE: &[&str] = &["max_size"];
+
+const KEYS_DISTCC: &[&str] = &["hosts", "jobs"];
+
+const KEYS_LINT: &[&str] = &[
+    "arch", "build_paths", "license", "linking", "usr_local",
+    "world_writable"];
+
+const KEYS_PUBLISH: &[&str] = &["github", "repo", "rsync", "s3", "sftp"];
+
+const KEYS_PUBLISH_GITHUB: &[&str] = &[
+    "api_url", "repository", "tag", "token_env", "upload_url"];
+
+const KEYS_PUBLISH_S3: &[&str] = &["bucket", "endpoint", "prefix", "region"];
+
+const KEYS_SCHEDULER: &[&str] = &[
+    "build_tries", "busy_interval", "domains", "jobs", "load_margin",
+    "poll_interval", "pressure", "threads", "weights"];
+
+pub(crate) struct Problem {
+    pub(crate) file: PathBuf,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    pub(crate) message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}: {}",
+            self.file.display(), self.line, self.column, self.message)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(super) enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+struct LocatorLine {
+    line: usize,
+    indent: usize,
+    item: bool,
+    /// Column (0-based) and name of the key on this line
+    key: Option<(usize, String)>,
+}
+
+/// Maps YAML paths back to lines and columns in the original text, only
+/// block style is understood, anything else falls back to the closest parent
+pub(super) struct Locator {
+    lines: Vec<LocatorLine>,
+}
+
+impl Locator {
+    pub(super) fn new(text: &str) -> Self {
+        let mut lines = vec![];
+        for (id, raw) in text.lines().enumerate() {
+            let trimmed = raw.trim_start_matches(' ');
+            if trimmed.is_empty() || trimmed.starts_with('#') ||
+                trimmed.starts_with("---")
+            {
+                continue
+            }
+            let indent = raw.len() - trimmed.len();
+            let (item, rest) = if trimmed == "-" || trimmed.starts_with("- ") {
+                (true, trimmed[1..].trim_start_matches(' '))
+            } else {
+                (false, trimmed)
+            };
+            let rest_indent = indent + trimmed.len() - rest.len();
+            let end = match rest.find(": ") {
+                Some(end) => Some(end),
+                None => if rest.ends_with(':') {
+                    Some(rest.len() - 1)
+                } else {
+                    None
+                },
+            };
+            let key = match end {
+                Some(end) => {
+                    let key = rest[..end].trim().trim_matches('"')
+                        .trim_matches('\'');
+                    if key.is_empty() || key.starts_with('{') ||
+                        key.starts_with('[')
+                    {
+                        None
+                    } else {
+                        Some((rest_indent, key.to_string()))
+                    }
+                },
+                None => None,
+            };
+            lines.push(LocatorLine { line: id + 1, indent, item, key })
+        }
+        Self { lines }
+    }
+
+    /// 1-based line and column
+    pub(super) fn locate(&self, path: &[Segment]) -> (usize, usize) {
+        let mut found = (1, 1);
+        let mut start = 0;
+        let mut parent: Option<usize> = None;
+        for segment in path.iter() {
+            let mut hit = None;
+            let mut child_indent = None;
+            let mut count = 0;
+            for (id, line) in self.lines.iter().enumerate().skip(start) {
+                if let Some(parent) = parent {
+                    if line.indent < parent ||
+                        (line.indent == parent && ! line.item)
+                    {
+                        break
+                    }
+                }
+                match segment {
+                    Segment::Key(key) => if let Some((column, name)) =
+                        &line.key
+                    {
+                        let child_indent =
+                            *child_indent.get_or_insert(*column);
+                        if *column == child_indent && name == key {
+                            hit = Some((id, *column));
+                            break
+                        }
+                    },
+                    Segment::Index(index) => if line.item {
+                        let child_indent =
+                            *child_indent.get_or_insert(line.indent);
+                        if line.indent == child_indent {
+                            if count == *index {
+                                hit = Some((id, line.indent));
+                                break
+                            }
+                            count += 1
+                        }
+                    },
+                }
+            }
+            match hit {
+                Some((id, column)) => {
+                    found = (self.lines[id].line, column + 1);
+                    start = id + 1;
+                    parent = Some(column);
+                },
+                None => break,
+            }
+        }
+        found
+    }
+}
+
+pub(super) struct Checker<'a> {
+    file: &'a Path,
+    locator: &'a Locator,
+    pub(super) problems: Vec<Problem>,
+}
+
+impl<'a> Checker<'a> {
+    pub(super) fn new(file: &'a Path, locator: &'a Locator) -> Self {
+        Self { file, locator, problems: vec![] }
+    }
+
+    pub(super) fn locate(&self, path: &[Segment]) -> (PathBuf, usize, usize) {
+        let (line, column) = self.locator.locate(path);
+        (self.file.to_owned(), line, column)
+    }
+
+    pub(super) fn report<S: Into<String>>(&mut self, path: &[Segment], message: S) {
+        let (line, column) = self.locator.locate(path);
+        self.problems.push(Problem {
+            file: self.file.to_owned(),
+            line,
+            column,
+            message: message.into(),
+        })
+    }
+
+    fn check_type<T>(&mut self, path: &[Segment], value: &Value) -> Option<T>
+    where
+        T: serde::de::DeserializeOwned
+    {
+        match serde_yaml::from_value(value.clone()) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.report(path, format!("invalid value: {}", e));
+                None
+            },
+        }
+    }
+
+    /// Report fields of a section not in the keys, as they'd be silently
+    /// ignored, anything not a map is left to check_type
+    fn check_keys(&mut self, path: &[Segment], value: &Value, keys: &[&str]) {
+        let mapping = match value {
+            Value::Mapping(mapping) => mapping,
+            _ => return,
+        };
+        for key in mapping.keys() {
+            match key.as_str() {
+                Some(key) if keys.contains(&key) => (),
+                Some(key) => {
+                    let mut path_key = path.to_vec();
+                    path_key.push(Segment::Key(key));
+                    self.report(&path_key, format!("unknown field '{}', \
+                        expected one of {:?}", key, keys))
+                },
+                None => self.report(path, "non-string field"),
+            }
+        }
+    }
+
+    fn check_home_binds(&mut self, path: &[Segment], home_binds: &[String]) {
+        for (id, home_bind) in home_binds.iter().enumerate() {
+            let bad = home_bind.is_empty() ||
+                Path::new(home_bind).components().any(|component|
+                    ! matches!(component, Component::Normal(_)));
+            if bad {
+                let mut path_item = path.to_vec();
+                path_item.push(Segment::Index(id));
+                self.report(&path_item, format!("home bind '{}' should be a \
+                    plain path relative to home, without '.' or '..'",
+                    home_bind));
+            }
+        }
+    }
+
+    fn check_env(&mut self, path: &[Segment],
+        env: &HashMap<String, String>
+    ) {
+        for name in env.keys() {
+            let good = name.starts_with(|c: char|
+                    c.is_ascii_alphabetic() || c == '_') &&
+                name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if ! good {
+                let mut path_name = path.to_vec();
+                path_name.push(Segment::Key(name));
+                self.report(&path_name, format!("'{}' is not a legal \
+                    environment variable name", name))
+            } else if name == "PATH" {
+                let mut path_name = path.to_vec();
+                path_name.push(Segment::Key(name));
+                self.report(&path_name, "'PATH' could not be set, makepkg \
+                    always runs with that of the login shell in the chroot")
+            }
+        }
+    }
+
+    fn check_distcc(&mut self, path: &[Segment], distcc: &Distcc) {
+        let mut path_hosts = path.to_vec();
+        path_hosts.push(Segment::Key("hosts"));
+        if distcc.hosts.is_empty() {
+            self.report(&path_hosts, "no distcc host")
+        }
+        for (id, host) in distcc.hosts.iter().enumerate() {
+            if crate::distcc::Host::parse(host).is_err() {
+                let mut path_host = path_hosts.clone();
+                path_host.push(Segment::Index(id));
+                self.report(&path_host, format!("'{}' is not a legal distcc \
+                    host", host))
+            }
+        }
+        if distcc.jobs == Some(0) {
+            let mut path_jobs = path.to_vec();
+            path_jobs.push(Segment::Key("jobs"));
+            self.report(&path_jobs, "jobs could not be 0")
+        }
+    }
+
+    fn check_publish(&mut self, path: &[Segment], publish: &Publish) {
+        let path_field = |fields: &[&'static str]| {
+            let mut path = path.to_vec();
+            for field in fields {
+                path.push(Segment::Key(field))
+            }
+            path
+        };
+        if publish.repo.is_empty() || publish.repo.contains('/') {
+            self.report(&path_field(&["repo"]), format!("'{}' is not a legal \
+                repo name", publish.repo))
+        }
+        if publish.github.is_none() && publish.s3.is_none() &&
+            publish.rsync.is_none() && publish.sftp.is_none()
+        {
+            self.report(path, "no backend to publish to, expected any of \
+                github, s3, rsync and sftp")
+        }
+        let mut urls = vec![];
+        if let Some(github) = &publish.github {
+            if github.repository.split('/').filter(
+                |part| ! part.is_empty()).count() != 2
+            {
+                self.report(&path_field(&["github", "repository"]), format!(
+                    "'{}' is not in the form of owner/name", github.repository))
+            }
+            for (field, url) in [("api_url", &github.api_url),
+                ("upload_url", &github.upload_url)]
+            {
+                if let Some(url) = url {
+                    urls.push((path_field(&["github", field]), url))
+                }
+            }
+        }
+        if let Some(s3) = &publish.s3 {
+            urls.push((path_field(&["s3", "endpoint"]), &s3.endpoint))
+        }
+        for (path, url) in urls {
+            if let Err(e) = Url::from_str(url) {
+                self.report(&path, format!("illegal URL '{}': {}", url, e))
+            }
+        }
+    }
+
+    fn check_scheduler(&mut self, path: &[Segment], scheduler: &Scheduler) {
+        let path_field = |field| {
+            let mut path = path.to_vec();
+            path.push(Segment::Key(field));
+            path
+        };
+        for (field, value) in [("build_tries", scheduler.build_tries),
+            ("threads", scheduler.threads), ("jobs", scheduler.jobs)]
+        {
+            if value == Some(0) {
+                self.report(&path_field(field),
+                    format!("{} could not be 0", field))
+            }
+        }
+        for (field, map) in [("domains", &scheduler.domains),
+            ("weights", &scheduler.weights)]
+        {
+            if let Some(map) = map {
+                for (key, value) in map.iter() {
+                    if *value == 0 {
+                        let mut path = path_field(field);
+                        path.push(Segment::Key(key));
+                        self.report(&path, format!("{} of '{}' could not \
+                            be 0", field, key))
+                    }
+                }
+            }
+        }
+        if let Some(pressure) = scheduler.pressure {
+            if ! (0.0..=100.0).contains(&pressure) {
+                self.report(&path_field("pressure"),
+                    "pressure should be a percentage between 0 and 100")
+            }
+        }
+    }
+
+    fn check_makepkg_conf(&mut self, path: &[Segment], makepkg_conf: &str) {
+        if ! Path::new(makepkg_conf).is_file() {
+            self.report(path, format!("makepkg.conf fragment '{}' does not \
+                exist", makepkg_conf))
+        }
+    }
+
+    fn check_url_alias(&mut self, path: &[Segment], url: &str) {
+        let rest = if let Some(rest) = url.strip_prefix("GITHUB/") {
+            rest
+        } else if let Some(rest) = url.strip_prefix("GH/") {
+            rest
+        } else if url == "AUR" {
+            return
+        } else {
+            match Url::from_str(url) {
+                Ok(parsed) => if parsed.domain().is_none() {
+                    self.report(path, format!("git URL '{}' does not have a \
+                        domain name", url))
+                },
+                Err(e) => self.report(path,
+                    format!("illegal git URL '{}': {}", url, e)),
+            }
+            return
+        };
+        let good = match rest.strip_suffix('/') {
+            Some(owner) => ! owner.is_empty() && ! owner.contains('/'),
+            None => match rest.split_once('/') {
+                Some((owner, repo)) => ! owner.is_empty() &&
+                    ! repo.is_empty() && ! repo.contains('/'),
+                None => false,
+            },
+        };
+        if ! good {
+            self.report(path, format!("URL alias '{}' should be either \
+                '[prefix][owner]/' or '[prefix][owner]/[repo]'", url))
+        }
+    }
+
+    fn check_url(&mut self, path: &[Segment], url: &str, origin: &PkgbuildOrigin) {
+        match origin {
+            PkgbuildOrigin::Git => self.check_url_alias(path, url),
+            PkgbuildOrigin::Dir => if ! Path::new(url).is_dir() {
+                self.report(path, format!("PKGBUILD dir '{}' does not exist",
+                    url))
+            },
+            PkgbuildOrigin::Tarball => if ! (url.starts_with("file://") ||
+                url.starts_with("http://") || url.starts_with("https://"))
+            {
+                self.report(path, format!("tarball URL '{}' is neither file, \
+                    http nor https", url))
+            },
+        }
+    }
+
+    fn check_subtree(&mut self, path: &[Segment], subtree: &str) {
+        let stripped = subtree.trim_start_matches('/');
+        let bad = stripped.is_empty() || stripped.contains("//") ||
+            Path::new(stripped).components().any(|component|
+                ! matches!(component, Component::Normal(_)));
+        if bad {
+            self.report(path, format!("subtree '{}' should be a relative \
+                path without '.', '..' or empty components", subtree))
+        }
+    }
+
+    fn check_pkgbuild(&mut self, name: &str, value: &Value) {
+        let path_pkgbuild = [Segment::Key("pkgbuilds"), Segment::Key(name)];
+        if let Value::Mapping(mapping) = value {
+            for key in mapping.keys() {
+                match key.as_str() {
+                    Some(key) if KEYS_PKGBUILD.contains(&key) => (),
+                    Some(key) => self.report(&[Segment::Key("pkgbuilds"),
+                        Segment::Key(name), Segment::Key(key)],
+                        format!("unknown field '{}' for PKGBUILD '{}', \
+                            expected one of {:?}", key, name, KEYS_PKGBUILD)),
+                    None => self.report(&path_pkgbuild,
+                        format!("non-string field for PKGBUILD '{}'", name)),
+                }
+            }
+            if let Some(lint) = mapping.get("lint") {
+                self.check_keys(&[Segment::Key("pkgbuilds"),
+                    Segment::Key(name), Segment::Key("lint")], lint, KEYS_LINT)
+            }
+            if ! mapping.contains_key("url") {
+                self.report(&path_pkgbuild,
+                    format!("PKGBUILD '{}' does not have url", name));
+                return
+            }
+        }
+        let pkgbuild: Pkgbuild = match self.check_type(&path_pkgbuild, value) {
+            Some(pkgbuild) => pkgbuild,
+            None => return,
+        };
+        let path_field = |field| [Segment::Key("pkgbuilds"),
+            Segment::Key(name), Segment::Key(field)];
+        let (url, origin, branch, tag, commit, subtree, patches,
+            home_binds, binds, env, makepkg_conf) = match &pkgbuild
+        {
+            Pkgbuild::Simple(url) => {
+                self.check_url(&path_pkgbuild, url,
+                    &PkgbuildOrigin::from_url(url));
+                return
+            },
+            Pkgbuild::Complex { url, origin, branch,
+                tag, commit, subtree, patches,
+                deps: _, makedeps: _, home_binds, binds, env, makeflags: _,
+                cflags: _, cxxflags: _, options: _, buildenv: _, makepkg_conf, check: _,
+                rebuild_on_dep_change: _, lint: _
+            } => (url, origin, branch, tag, commit, subtree, patches,
+                home_binds, binds, env, makepkg_conf)
+        };
+        let origin = match origin {
+            Some(origin) => origin.clone(),
+            None => PkgbuildOrigin::from_url(url),
+        };
+        self.check_url(&path_field("url"), url, &origin);
+        let refs: Vec<&str> = [("branch", branch), ("tag", tag),
+            ("commit", commit)].iter().filter_map(|(key, value)|
+                value.as_ref().and(Some(*key))).collect();
+        if origin != PkgbuildOrigin::Git {
+            for key in refs.iter() {
+                self.report(&path_field(key), format!("PKGBUILD '{}' is not \
+                    from git, could not set {}", name, key))
+            }
+        } else if refs.len() > 1 {
+            self.report(&path_field(refs[1]), format!("PKGBUILD '{}' could \
+                only set one of branch, tag and commit", name))
+        }
+        if let Some(commit) = commit {
+            if commit.len() < 4 || commit.len() > 40 ||
+                ! commit.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                self.report(&path_field("commit"), format!("commit '{}' is not \
+                    a full or abbreviated commit id", commit))
+            }
+        }
+        if let Some(subtree) = subtree {
+            self.check_subtree(&path_field("subtree"), subtree)
+        }
+        if let Some(patches) = patches {
+            for (id, patch) in patches.iter().enumerate() {
+                if ! Path::new(patch).exists() {
+                    self.report(&[Segment::Key("pkgbuilds"),
+                        Segment::Key(name), Segment::Key("patches"),
+                        Segment::Index(id)],
+                        format!("patch '{}' does not exist", patch))
+                }
+            }
+        }
+        if let Some(home_binds) = home_binds {
+            self.check_home_binds(&path_field("home_binds"), home_binds)
+        }
+        if let Some(binds) = binds {
+            for (host, chroot) in binds.iter() {
+                if ! Path::new(host).is_absolute() ||
+                    ! Path::new(chroot).is_absolute()
+                {
+                    self.report(&[Segment::Key("pkgbuilds"),
+                        Segment::Key(name), Segment::Key("binds"),
+                        Segment::Key(host)], format!("bind '{}' => '{}' \
+                            should use absolute paths", host, chroot))
+                }
+            }
+        }
+        if let Some(env) = env {
+            self.check_env(&path_field("env"), env)
+        }
+        if let Some(makepkg_conf) = makepkg_conf {
+            self.check_makepkg_conf(&path_field("makepkg_conf"), makepkg_conf)
+        }
+    }
+
+    fn check_sign(&mut self, key: &str, actual_identity: &IdentityActual) {
+        let status = actual_identity.set_root_drop_command(
+            Command::new("/usr/bin/gpg")
+                .arg("--list-secret-keys")
+                .arg(key)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null()))
+            .status();
+        match status {
+            Ok(status) => if ! status.success() {
+                self.report(&[Segment::Key("sign")], format!("GnuPG secret \
+                    key '{}' does not exist", key))
+            },
+            Err(e) => self.report(&[Segment::Key("sign")],
+                format!("failed to run gpg to look for key '{}': {}", key, e)),
+        }
+    }
+
+    /// Check a single config file, before it's merged
+    pub(super) fn check_mapping(&mut self, mapping: &Mapping,
+        actual_identity: &IdentityActual
+    ) {
+        for (key, value) in mapping.iter() {
+            let key = match key.as_str() {
+                Some(key) => key,
+                None => {
+                    self.report(&[], "non-string top-level key");
+                    continue
+                },
+            };
+            let path = [Segment::Key(key)];
+            match key {
+                "holdpkg" | "holdgit" | "skipint" | "nobuild" | "noclean" |
+                "nonet" | "nopublish" => { self.check_type::<bool>(&path, value); },
+                "proxy_after" => { self.check_type::<usize>(&path, value); },
+                "dephash_strategy" => {
+                    self.check_type::<DepHashStrategy>(&path, value);
+                },
+                "basepkgs" | "options" | "buildenv" => {
+                    self.check_type::<Vec<String>>(&path, value);
+                },
+                "makeflags" | "cflags" | "cxxflags" => {
+                    self.check_type::<String>(&path, value);
+                },
+                "env" => if let Some(env) = self.check_type::<HashMap<String, String>>(&path, value)
+                {
+                    self.check_env(&path, &env)
+                },
+                "ccache" => {
+                    self.check_keys(&path, value, KEYS_CCACHE);
+                    self.check_type::<Ccache>(&path, value);
+                },
+                "lint" => {
+                    self.check_keys(&path, value, KEYS_LINT);
+                    self.check_type::<Lint>(&path, value);
+                },
+                "publish" => {
+                    self.check_keys(&path, value, KEYS_PUBLISH);
+                    for (backend, keys) in [("github", KEYS_PUBLISH_GITHUB),
+                        ("s3", KEYS_PUBLISH_S3)]
+                    {
+                        if let Some(backend_value) = value.get(backend) {
+                            self.check_keys(&[Segment::Key(key),
+                                Segment::Key(backend)], backend_value, keys)
+                        }
+                    }
+                    if let Some(publish) =
+                        self.check_type::<Publish>(&path, value)
+                    {
+                        self.check_publish(&path, &publish)
+                    }
+                },
+                "scheduler" => {
+                    self.check_keys(&path, value, KEYS_SCHEDULER);
+                    if let Some(scheduler) =
+                        self.check_type::<Scheduler>(&path, value)
+                    {
+                        self.check_scheduler(&path, &scheduler)
+                    }
+                },
+                "distcc" => {
+                    self.check_keys(&path, value, KEYS_DISTCC);
+                    if let Some(distcc) =
+                        self.check_type::<Distcc>(&path, value)
+                    {
+                        self.check_distcc(&path, &distcc)
+                    }
+                },
+                "arch" => if let Some(arch) =
+                    self.check_type::<String>(&path, value)
+                {
+                    if ! crate::arch::supported().any(|supported|
+                        supported == arch)
+                    {
+                        self.report(&path, format!("unsupported arch '{}', \
+                            expected one of {:?}", arch,
+                            crate::arch::supported().collect::<Vec<&str>>()))
+                    }
+                },
+                "pacman_conf" => if let Some(pacman_conf) =
+                    self.check_type::<String>(&path, value)
+                {
+                    if ! Path::new(&pacman_conf).is_file() {
+                        self.report(&path, format!("pacman config '{}' does \
+                            not exist", pacman_conf))
+                    }
+                },
+                "makepkg_conf" => if let Some(makepkg_conf) =
+                    self.check_type::<String>(&path, value)
+                {
+                    self.check_makepkg_conf(&path, &makepkg_conf)
+                },
+                "metrics" => if let Some(metrics) =
+                    self.check_type::<String>(&path, value)
+                {
+                    match Path::new(&metrics).parent() {
+                        Some(parent) if parent.as_os_str().is_empty() ||
+                            parent.is_dir() => (),
+                        _ => self.report(&path, format!("parent dir of \
+                            metrics '{}' does not exist", metrics)),
+                    }
+                },
+                "home_binds" => if let Some(home_binds) =
+                    self.check_type::<Vec<String>>(&path, value)
+                {
+                    self.check_home_binds(&path, &home_binds)
+                },
+                "gmr" | "proxy" => if let Some(url) =
+                    self.check_type::<String>(&path, value)
+                {
+                    if let Err(e) = Url::from_str(&url) {
+                        self.report(&path,
+                            format!("illegal URL '{}': {}", url, e))
+                    }
+                },
+                "sign" => if let Some(sign) =
+                    self.check_type::<String>(&path, value)
+                {
+                    self.check_sign(&sign, actual_identity)
+                },
+                "include" => match value {
+                    Value::String(_) => (),
+                    Value::Sequence(sequence) if sequence.iter().all(
+                        |value| value.is_string()) => (),
+                    _ => self.report(&path, "include should be either a \
+                        string or a list of strings"),
+                },
+                "pkgbuilds" => match value {
+                    Value::Mapping(pkgbuilds) => {
+                        for (name, pkgbuild) in pkgbuilds.iter() {
+                            match name.as_str() {
+                                Some(name) => self.check_pkgbuild(name, pkgbuild),
+                                None => self.report(&path,
+                                    "non-string PKGBUILD name"),
+                            }
+                        }
+                    },
+                    Value::Null => (),
+                    _ => self.report(&path, "pkgbuilds should be a map"),
+                },
+                _ => self.report(&path, format!("unknown field '{}', expected \
+                    one of {:?}", key, KEYS_TOP)),
+            }
+        }
+    }
+}
@@ -1,13 +1,18 @@
 use std::collections::HashMap;
 
-use serde::Deserialize;
+use serde::{
+        Deserialize,
+        Serialize,
+    };
 
 #[derive(Debug, PartialEq, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub(crate) enum DepHashStrategy {
-    Strict, // dep + makedep
-    Loose,  // dep
-    None,   // none
+    Strict,  // dep + makedep
+    Loose,   // dep
+    None,    // none
+    Cascade, // dep + makedep + pkgids of wanted PKGBUILDs
+    Soname,  // sonames of dep + makedep linked against
 }
 
 impl Default for DepHashStrategy {
@@ -16,18 +21,183 @@ impl Default for DepHashStrategy {
     }
 }
 
+/// How check() in PKGBUILD is run
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CheckMode {
+    /// Run it as part of the build, --check
+    Run,
+    /// Do not run it at all, --nocheck
+    Skip,
+    /// Build with --nocheck, then run it as a separate step, whose failure
+    /// is only recorded in the report
+    AllowFailure,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct Ccache {
+    /// Size cap passed to ccache as max_size, e.g. 10G, ccache prunes the
+    /// least recently used entries once it's exceeded
+    pub(crate) max_size: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct Distcc {
+    /// Hosts in DISTCC_HOSTS syntax, e.g. 192.168.1.2/8
+    pub(crate) hosts: Vec<String>,
+    /// The -j in MAKEFLAGS, defaults to local cores plus remote slots
+    pub(crate) jobs: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub(crate) struct Scheduler {
+    /// Tries for each build before it's considered failed
+    pub(crate) build_tries: Option<usize>,
+    /// Sync threads for each domain, unless overriden in domains
+    pub(crate) threads: Option<usize>,
+    /// Sync threads for specific domains, e.g. aur.archlinux.org: 1
+    pub(crate) domains: Option<HashMap<String, usize>>,
+    /// Jobs that could run at the same time, defaults to cores
+    pub(crate) jobs: Option<usize>,
+    /// Per-package weights counted as jobs while building, e.g. chromium: 8
+    pub(crate) weights: Option<HashMap<String, usize>>,
+    /// CPU pressure (PSI some avg, %) above which the load is heavy
+    pub(crate) pressure: Option<f32>,
+    /// Load average above cores plus this is heavy
+    pub(crate) load_margin: Option<usize>,
+    /// Milliseconds to sleep between rounds of the builder loop
+    pub(crate) poll_interval: Option<u64>,
+    /// Milliseconds to sleep after each builder stepped under heavy load
+    pub(crate) busy_interval: Option<u64>,
+}
+
+/// How a lint rule treats what it finds in built packages
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LintLevel {
+    Off,
+    /// Only logged and recorded in the report
+    Warn,
+    /// Also fails the build, the packages are never moved into the pkgdir
+    Error,
+}
+
+/// Levels of the lint rules run over built packages, unset ones fall back to
+/// the global ones, and then to warn
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub(crate) struct Lint {
+    /// Files installed under /usr/local
+    pub(crate) usr_local: Option<LintLevel>,
+    /// $srcdir or $pkgdir paths left in ELF files and scripts
+    pub(crate) build_paths: Option<LintLevel>,
+    /// World-writable files and dirs, sticky dirs excluded
+    pub(crate) world_writable: Option<LintLevel>,
+    /// No license in .PKGINFO
+    pub(crate) license: Option<LintLevel>,
+    /// ELF files linking to sonames no declared depend provides
+    pub(crate) linking: Option<LintLevel>,
+    /// Packages or arch=() not for the target arch
+    pub(crate) arch: Option<LintLevel>,
+}
+
+impl Lint {
+    pub(crate) fn on_top_of(&self, global: Option<&Self>) -> Self {
+        let global = match global {
+            Some(global) => global,
+            None => return self.clone(),
+        };
+        Self {
+            usr_local: self.usr_local.or(global.usr_local),
+            build_paths: self.build_paths.or(global.build_paths),
+            world_writable: self.world_writable.or(global.world_writable),
+            license: self.license.or(global.license),
+            linking: self.linking.or(global.linking),
+            arch: self.arch.or(global.arch),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct PublishGithub {
+    /// owner/name
+    pub(crate) repository: String,
+    /// Tag of the release holding the packages, created if missing
+    pub(crate) tag: String,
+    /// Env var holding the token, GITHUB_TOKEN by default
+    pub(crate) token_env: Option<String>,
+    /// Base URLs of the REST API and uploads, for GitHub Enterprise or mocks
+    pub(crate) api_url: Option<String>,
+    pub(crate) upload_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct PublishS3 {
+    /// Base URL of the service, buckets are addressed path-style under it
+    pub(crate) endpoint: String,
+    pub(crate) bucket: String,
+    /// Prefix of object keys, e.g. archlinux/x86_64/
+    pub(crate) prefix: Option<String>,
+    /// us-east-1 by default
+    pub(crate) region: Option<String>,
+}
+
+/// Where packages are published to after a run, every backend set is used
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct Publish {
+    /// Name of the pacman repo, i.e. the db is [repo].db
+    pub(crate) repo: String,
+    pub(crate) github: Option<PublishGithub>,
+    pub(crate) s3: Option<PublishS3>,
+    /// Destinations of rsync and sftp, e.g. user@host:/srv/repo
+    pub(crate) rsync: Option<String>,
+    pub(crate) sftp: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PkgbuildOrigin {
+    Git,     // bare repo under sources/PKGBUILD
+    Dir,     // plain local dir
+    Tarball, // e.g. cgit snapshot
+}
+
+impl PkgbuildOrigin {
+    /// The origin to use when it's not set explicitly
+    pub(crate) fn from_url(url: &str) -> Self {
+        if url.ends_with(".tar.gz") || url.ends_with(".tgz") {
+            Self::Tarball
+        } else {
+            Self::Git
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Deserialize)]
 #[serde(untagged)]
 pub(crate) enum Pkgbuild {
     Simple (String),
     Complex {
         url: String,
+        origin: Option<PkgbuildOrigin>,
         branch: Option<String>,
+        tag: Option<String>,
+        commit: Option<String>,
         subtree: Option<String>,
+        patches: Option<Vec<String>>,
         deps: Option<Vec<String>>,
         makedeps: Option<Vec<String>>,
         home_binds: Option<Vec<String>>,
-        binds: Option<HashMap<String, String>>
+        binds: Option<HashMap<String, String>>,
+        env: Option<HashMap<String, String>>,
+        makeflags: Option<String>,
+        cflags: Option<String>,
+        cxxflags: Option<String>,
+        options: Option<Vec<String>>,
+        buildenv: Option<Vec<String>>,
+        makepkg_conf: Option<String>,
+        check: Option<CheckMode>,
+        rebuild_on_dep_change: Option<bool>,
+        lint: Option<Lint>,
     },
 }
 
@@ -45,6 +215,8 @@ pub(crate) struct Config {
     pub(crate) noclean: bool,
     #[serde(default)]
     pub(crate) nonet: bool,
+    #[serde(default)]
+    pub(crate) nopublish: bool,
     pub(crate) sign: Option<String>,
     pub(crate) gmr: Option<String>,
     pub(crate) proxy: Option<String>,
@@ -56,6 +228,22 @@ pub(crate) struct Config {
     pub(crate) pkgbuilds: std::collections::HashMap<String, Pkgbuild>,
     #[serde(default = "default_home_binds")]
     pub(crate) home_binds: Vec<String>,
+    pub(crate) env: Option<HashMap<String, String>>,
+    pub(crate) makeflags: Option<String>,
+    pub(crate) cflags: Option<String>,
+    pub(crate) cxxflags: Option<String>,
+    pub(crate) options: Option<Vec<String>>,
+    pub(crate) buildenv: Option<Vec<String>>,
+    pub(crate) makepkg_conf: Option<String>,
+    pub(crate) arch: Option<String>,
+    pub(crate) pacman_conf: Option<String>,
+    pub(crate) ccache: Option<Ccache>,
+    pub(crate) distcc: Option<Distcc>,
+    pub(crate) scheduler: Option<Scheduler>,
+    pub(crate) lint: Option<Lint>,
+    pub(crate) publish: Option<Publish>,
+    /// Textfile-collector file to write metrics into
+    pub(crate) metrics: Option<String>,
 }
 
 fn default_basepkgs() -> Vec<String> {
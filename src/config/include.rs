@@ -0,0 +1,420 @@
+// Config files could include other config files, they're merged as:
+//   - Included files come first in the order they're listed, then the
+//     including file itself
+//   - Scalars and maps other than pkgbuilds: the later one wins
+//   - Lists (basepkgs, home_binds, etc): concatenated and deduplicated
+//   - pkgbuilds: united, a package defined in more than one file is an error
+// Each file is checked on its own before merged, so problems could be
+// reported with their locations. Relative paths (makepkg_conf, pacman_conf,
+// and patches, makepkg_conf and local urls of pkgbuilds) in included files
+// are relative to the dir of that file, those in the top file to the cwd
+use std::{
+        collections::HashMap,
+        fs::{
+            read_dir,
+            read_to_string,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use serde_yaml::{
+        Mapping,
+        Value,
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        identity::IdentityActual,
+    };
+
+use super::{
+        check::{
+            Checker,
+            Locator,
+            Problem,
+            Segment,
+        },
+        file::{
+            Config,
+            PkgbuildOrigin,
+        },
+    };
+
+const KEY_INCLUDE: &str = "include";
+const KEY_PKGBUILDS: &str = "pkgbuilds";
+const KEY_ORIGIN: &str = "origin";
+const KEY_URL: &str = "url";
+/// Top-level keys taking a path, resolved against the dir of included files
+const KEYS_PATH: &[&str] = &["makepkg_conf", "pacman_conf"];
+/// PKGBUILD keys taking a path or a list of paths, resolved the same
+const KEYS_PKGBUILD_PATH: &[&str] = &["makepkg_conf", "patches"];
+
+/// Simple wildcard matching, supporting only `*` and `?`
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) =>
+            wildcard_match(&pattern[1..], name) ||
+            (! name.is_empty() && wildcard_match(pattern, &name[1..])),
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(a), Some(b)) if a == b =>
+            wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Expand an include entry relative to the dir of the including file, globs
+/// are only supported in the last component
+fn expand(parent: &Path, entry: &str) -> Result<Vec<PathBuf>> {
+    let path = parent.join(entry);
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => {
+            log::error!("Include '{}' does not point to a file", entry);
+            return Err(Error::InvalidConfig)
+        },
+    };
+    if ! name.contains(['*', '?']) {
+        return Ok(vec![path])
+    }
+    let dir = match path.parent() {
+        Some(dir) => dir,
+        None => parent,
+    };
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Failed to read dir '{}' to expand include '{}': {}",
+                dir.display(), entry, e);
+            return Err(e.into())
+        },
+    };
+    let mut paths = vec![];
+    for dir_entry in entries {
+        let dir_entry = match dir_entry {
+            Ok(dir_entry) => dir_entry,
+            Err(e) => {
+                log::error!("Failed to read entry from dir '{}': {}",
+                    dir.display(), e);
+                return Err(e.into())
+            },
+        };
+        if wildcard_match(name.as_bytes(),
+            dir_entry.file_name().to_string_lossy().as_bytes())
+        {
+            paths.push(dir_entry.path())
+        }
+    }
+    if paths.is_empty() {
+        log::warn!("Include '{}' matches no file", entry);
+    }
+    paths.sort_unstable();
+    Ok(paths)
+}
+
+/// Read a single config file, YAML syntax errors are reported as problems
+fn read_mapping(path: &Path, problems: &mut Vec<Problem>)
+    -> Result<Option<(Locator, Mapping)>>
+{
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("Failed to read config file '{}': {}",
+                path.display(), e);
+            return Err(e.into())
+        },
+    };
+    let locator = Locator::new(&text);
+    let (line, column, message) = match serde_yaml::from_str(&text) {
+        Ok(Value::Mapping(mapping)) => return Ok(Some((locator, mapping))),
+        Ok(Value::Null) => return Ok(Some((locator, Mapping::new()))),
+        Ok(_) => (1, 1, String::from("config is not a YAML mapping")),
+        Err(e) => match e.location() {
+            Some(location) =>
+                (location.line(), location.column(), e.to_string()),
+            None => (1, 1, e.to_string()),
+        },
+    };
+    problems.push(Problem { file: path.to_owned(), line, column, message });
+    Ok(None)
+}
+
+fn includes_from_value(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(include) => vec![include.clone()],
+        Value::Sequence(sequence) => sequence.iter().filter_map(|value|
+            value.as_str().map(|include| include.to_string())).collect(),
+        _ => vec![],
+    }
+}
+
+/// The url of a dir PKGBUILD is a path, that of a tarball PKGBUILD could be a
+/// file:// url with a relative path
+fn resolve_url(parent: &Path, url: &mut String, origin: Option<&str>) {
+    let is_tarball = match origin {
+        Some(origin) => origin == "tarball",
+        None => PkgbuildOrigin::from_url(url) == PkgbuildOrigin::Tarball,
+    };
+    if origin == Some("dir") {
+        if Path::new(url).is_relative() {
+            *url = parent.join(&url).to_string_lossy().into_owned()
+        }
+    } else if is_tarball {
+        if let Some(path) = url.strip_prefix("file://") {
+            if Path::new(path).is_relative() {
+                *url = format!("file://{}", parent.join(path).display())
+            }
+        }
+    }
+}
+
+fn resolve_path(parent: &Path, value: &mut Value) {
+    match value {
+        Value::String(path) if Path::new(path).is_relative() =>
+            *path = parent.join(&path).to_string_lossy().into_owned(),
+        Value::Sequence(sequence) => for value in sequence.iter_mut() {
+            resolve_path(parent, value)
+        },
+        _ => (),
+    }
+}
+
+/// Relative paths in an included file are relative to the dir of it, turn
+/// them into ones usable from the cwd, as those in the top file are
+fn resolve_paths(parent: &Path, mapping: &mut Mapping) {
+    for key in KEYS_PATH.iter() {
+        if let Some(value) = mapping.get_mut(*key) {
+            resolve_path(parent, value)
+        }
+    }
+    let pkgbuilds = match mapping.get_mut(KEY_PKGBUILDS) {
+        Some(Value::Mapping(pkgbuilds)) => pkgbuilds,
+        _ => return,
+    };
+    for (_, pkgbuild) in pkgbuilds.iter_mut() {
+        let pkgbuild = match pkgbuild {
+            Value::String(url) => {
+                resolve_url(parent, url, None);
+                continue
+            },
+            Value::Mapping(pkgbuild) => pkgbuild,
+            _ => continue,
+        };
+        let origin = match pkgbuild.get(KEY_ORIGIN) {
+            Some(Value::String(origin)) => Some(origin.clone()),
+            _ => None,
+        };
+        if let Some(Value::String(url)) = pkgbuild.get_mut(KEY_URL) {
+            resolve_url(parent, url, origin.as_deref())
+        }
+        for key in KEYS_PKGBUILD_PATH.iter() {
+            if let Some(value) = pkgbuild.get_mut(*key) {
+                resolve_path(parent, value)
+            }
+        }
+    }
+}
+
+struct Loader<'a> {
+    actual_identity: &'a IdentityActual,
+    /// The chain of files being loaded, to catch circular includes
+    stack: Vec<PathBuf>,
+    loaded: Vec<PathBuf>,
+    /// Package name => the file, line and column defining it
+    origins: HashMap<String, (PathBuf, usize, usize)>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Loader<'a> {
+    fn new(actual_identity: &'a IdentityActual) -> Self {
+        Self {
+            actual_identity,
+            stack: vec![],
+            loaded: vec![],
+            origins: HashMap::new(),
+            problems: vec![],
+        }
+    }
+
+    fn merge_pkgbuilds(&mut self, checker: &mut Checker, merged: &mut Mapping,
+        pkgbuilds: Value
+    ) {
+        let pkgbuilds = match pkgbuilds {
+            Value::Mapping(pkgbuilds) => pkgbuilds,
+            _ => return,
+        };
+        let target = merged.entry(Value::from(KEY_PKGBUILDS))
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+        let target = match target {
+            Value::Mapping(target) => target,
+            _ => return,
+        };
+        for (name, pkgbuild) in pkgbuilds {
+            let name_string = match name.as_str() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let path = [Segment::Key(KEY_PKGBUILDS), Segment::Key(&name_string)];
+            if let Some((file, line, column)) = self.origins.get(&name_string) {
+                checker.report(&path, format!("package '{}' is already \
+                    defined at {}:{}:{}", name_string, file.display(), line,
+                    column));
+                continue
+            }
+            let location = checker.locate(&path);
+            self.origins.insert(name_string, location);
+            target.insert(name, pkgbuild);
+        }
+    }
+
+    fn merge(&mut self, checker: &mut Checker, merged: &mut Mapping,
+        mapping: Mapping
+    ) {
+        for (key, value) in mapping {
+            if key.as_str() == Some(KEY_PKGBUILDS) {
+                self.merge_pkgbuilds(checker, merged, value);
+                continue
+            }
+            match (merged.get_mut(&key), value) {
+                (Some(Value::Sequence(existing)), Value::Sequence(sequence)) =>
+                    for item in sequence {
+                        if ! existing.contains(&item) {
+                            existing.push(item)
+                        }
+                    },
+                (_, value) => { merged.insert(key, value); },
+            }
+        }
+    }
+
+    fn load_into(&mut self, path: &Path, merged: &mut Mapping) -> Result<()> {
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(e) => {
+                log::error!("Failed to find config file '{}': {}",
+                    path.display(), e);
+                return Err(e.into())
+            },
+        };
+        if self.loaded.contains(&canonical) {
+            log::info!("Config file '{}' already included, skipping",
+                path.display());
+            return Ok(())
+        }
+        let (locator, mut mapping) =
+            match read_mapping(path, &mut self.problems)?
+        {
+            Some(read) => read,
+            None => return Ok(()),
+        };
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => Path::new("."),
+        };
+        if ! self.stack.is_empty() {
+            resolve_paths(parent, &mut mapping)
+        }
+        let mut checker = Checker::new(path, &locator);
+        checker.check_mapping(&mapping, self.actual_identity);
+        self.stack.push(canonical.clone());
+        if let Some(includes) = mapping.remove(KEY_INCLUDE) {
+            for include in includes_from_value(&includes) {
+                let location = [Segment::Key(KEY_INCLUDE)];
+                let includeds = match expand(parent, &include) {
+                    Ok(includeds) => includeds,
+                    Err(e) => {
+                        checker.report(&location, format!("failed to expand \
+                            include '{}': {}", include, e));
+                        continue
+                    },
+                };
+                for included in includeds {
+                    if ! included.exists() {
+                        checker.report(&location, format!("included file \
+                            '{}' does not exist", included.display()));
+                        continue
+                    }
+                    let is_circular = match included.canonicalize() {
+                        Ok(included) => self.stack.contains(&included),
+                        Err(_) => false,
+                    };
+                    if is_circular {
+                        checker.report(&location, format!("including '{}' \
+                            is circular", included.display()));
+                        continue
+                    }
+                    self.load_into(&included, merged)?
+                }
+            }
+        }
+        self.merge(&mut checker, merged, mapping);
+        self.stack.pop();
+        self.loaded.push(canonical);
+        self.problems.append(&mut checker.problems);
+        Ok(())
+    }
+}
+
+/// Load the config file and all files it includes, merged, with all problems
+/// found
+fn load(path: &Path, actual_identity: &IdentityActual)
+    -> Result<(Mapping, Vec<Problem>)>
+{
+    let mut loader = Loader::new(actual_identity);
+    let mut merged = Mapping::new();
+    loader.load_into(path, &mut merged)?;
+    Ok((merged, loader.problems))
+}
+
+/// Check the config file and all files it includes, an empty list means
+/// the config is good
+pub(crate) fn check_file<P: AsRef<Path>>(path: P,
+    actual_identity: &IdentityActual
+) -> Result<Vec<Problem>>
+{
+    let (merged, mut problems) = load(path.as_ref(), actual_identity)?;
+    if problems.is_empty() {
+        if let Err(e) = serde_yaml::from_value::<Config>(Value::Mapping(merged)) {
+            problems.push(Problem {
+                file: path.as_ref().to_owned(),
+                line: 1,
+                column: 1,
+                message: format!("merged config is invalid: {}", e),
+            })
+        }
+    }
+    Ok(problems)
+}
+
+impl Config {
+    /// Read the config file and all the files it includes, refuse to go on if
+    /// there's any problem
+    pub(crate) fn from_file<P: AsRef<Path>>(path: P,
+        actual_identity: &IdentityActual
+    ) -> Result<Self>
+    {
+        let (merged, problems) = load(path.as_ref(), actual_identity)?;
+        if ! problems.is_empty() {
+            for problem in problems.iter() {
+                log::error!("{}", problem)
+            }
+            log::error!("Config '{}' has {} problems, run check-config for \
+                details", path.as_ref().display(), problems.len());
+            return Err(Error::InvalidConfig)
+        }
+        match serde_yaml::from_value(Value::Mapping(merged)) {
+            Ok(config) => Ok(config),
+            Err(e) => {
+                log::error!("Failed to parse merged config from '{}': {}",
+                    path.as_ref().display(), e);
+                Err(Error::InvalidConfig)
+            },
+        }
+    }
+}
@@ -1,7 +1,12 @@
 mod db;
 mod depends;
 mod interdep;
+mod version;
 
 pub(crate) use db::DbHandle;
 pub(crate) use depends::Depends;
-pub(crate) use interdep::split_pkgbuilds;
\ No newline at end of file
+pub(crate) use interdep::{
+        DepGraph,
+        graph_pkgbuilds,
+    };
+pub(crate) use version::Offer;
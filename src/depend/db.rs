@@ -1,4 +1,5 @@
 use std::{
+        collections::HashSet,
         os::unix::prelude::OsStrExt,
         path::Path,
     };
@@ -19,7 +20,9 @@ pub(crate) struct DbHandle {
 }
 
 impl DbHandle {
-    pub(crate) fn new<P: AsRef<Path>>(root: P) -> Result<Self> {
+    pub(crate) fn new<P: AsRef<Path>>(root: P, pacman_conf: &Path)
+        -> Result<Self>
+    {
         let handle = match Alpm::new(
             root.as_ref().as_os_str().as_bytes(),
             root.as_ref().join("var/lib/pacman")
@@ -32,12 +35,11 @@ impl DbHandle {
                 return Err(Error::AlpmError(e))
             },
         };
-        let content = match std::fs::read_to_string(
-            "/etc/pacman.conf")
-        {
+        let content = match std::fs::read_to_string(pacman_conf) {
             Ok(content) => content,
             Err(e) => {
-                log::error!("Failed to open pacman config: {}", e);
+                log::error!("Failed to open pacman config '{}': {}",
+                    pacman_conf.display(), e);
                 return Err(Error::IoError(e))
             },
         };
@@ -78,6 +80,65 @@ impl DbHandle {
         pkg_satisfier
     }
 
+    /// Provides of the packages satisfying the deps in the sync DBs, and of
+    /// those they depend on in turn
+    pub(super) fn provides_closure<S: AsRef<str>>(&self, deps: &[S])
+        -> Vec<String>
+    {
+        let mut deps: Vec<String> = deps.iter().map(
+            |dep| dep.as_ref().to_string()).collect();
+        let mut seen = HashSet::new();
+        let mut provides = vec![];
+        while let Some(dep) = deps.pop() {
+            let pkg = match self.find_satisfier(&dep) {
+                Some(pkg) => pkg,
+                None => continue,
+            };
+            if ! seen.insert(pkg.name().to_string()) {
+                continue
+            }
+            for provide in pkg.provides() {
+                provides.push(provide.to_string())
+            }
+            for dep in pkg.depends() {
+                deps.push(dep.to_string())
+            }
+        }
+        provides
+    }
+
+    /// Files and provides of the installed packages satisfying the deps, and
+    /// of those they depend on in turn
+    pub(crate) fn installed_closure<S: AsRef<str>>(&self, deps: &[S])
+        -> (Vec<String>, Vec<String>)
+    {
+        let localdb = self.alpm_handle.localdb();
+        let mut deps: Vec<String> = deps.iter().map(
+            |dep| dep.as_ref().to_string()).collect();
+        let mut seen = HashSet::new();
+        let mut files = vec![];
+        let mut provides = vec![];
+        while let Some(dep) = deps.pop() {
+            let pkg = match localdb.pkgs().find_satisfier(dep) {
+                Some(pkg) => pkg,
+                None => continue,
+            };
+            if ! seen.insert(pkg.name().to_string()) {
+                continue
+            }
+            for file in pkg.files().files() {
+                files.push(file.name().to_string())
+            }
+            for provide in pkg.provides() {
+                provides.push(provide.to_string())
+            }
+            for dep in pkg.depends() {
+                deps.push(dep.to_string())
+            }
+        }
+        (files, provides)
+    }
+
     pub(super) fn is_installed<S: AsRef<str>>(&self, pkg: S) -> bool {
         match self.alpm_handle.localdb().pkg(pkg.as_ref()) {
             Ok(_) => true,
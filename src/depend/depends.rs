@@ -9,9 +9,16 @@ use alpm::Package;
 use xxhash_rust::xxh3;
 
 use crate::{
+        arch::Target,
         child::no_output_check,
         config::DepHashStrategy,
-        depend::DbHandle,
+        depend::{
+            DbHandle,
+            version::{
+                DepExpr,
+                Offer,
+            },
+        },
         error::{
             Error,
             Result,
@@ -20,6 +27,7 @@ use crate::{
             Identity,
             IdentityActual,
         },
+        soname,
     };
 
 
@@ -29,6 +37,11 @@ pub(crate) struct Depends {
     pub(crate) makedeps: Vec<String>,
     pub(crate) needs: Vec<String>,
     pub(crate) hash: u64,
+    /// Soname provides of the satisfiers of deps and makedeps, only with the
+    /// soname strategy
+    pub(crate) soname_provides: Vec<String>,
+    /// Sonames the hash was calculated from, none for all soname provides
+    pub(crate) sonames: Option<Vec<String>>,
 }
 
 
@@ -102,6 +115,31 @@ impl Depends {
         Ok(())
     }
 
+    /// Hash all soname provides of the whole dependency closure for now, as
+    /// a library could be reached through a transitive DT_NEEDED; it's
+    /// narrowed down to those really linked against when filling the pkgid
+    fn needed_and_soname_hash(&mut self, db_handle: &DbHandle) -> Result<()>
+    {
+        for dep in self.deps.iter().chain(self.makedeps.iter()) {
+            let dep = match db_handle.find_satisfier(dep) {
+                Some(dep) => dep,
+                None => {
+                    log::error!("Warning: dep {} not found", dep);
+                    return Err(Error::DependencyMissing(vec![dep.as_str().into()]))
+                },
+            };
+            self.needs.push(dep.name().to_string());
+        }
+        self.soname_provides = db_handle.provides_closure(&self.needs)
+            .into_iter().filter(|provide| soname::is_soname_provide(provide))
+            .collect();
+        self.soname_provides.sort_unstable();
+        self.soname_provides.dedup();
+        self.sonames = None;
+        self.hash = soname::hash(&self.soname_provides, None);
+        Ok(())
+    }
+
     fn needed_and_no_hash(&mut self, db_handle: &DbHandle) -> Result<()> {
         for dep in self.deps.iter().chain(self.makedeps.iter()) {
             let dep = match db_handle.find_satisfier(dep) {
@@ -124,9 +162,11 @@ impl Depends {
     {
         self.needs.clear();
         let r = match hash_strategy {
-            DepHashStrategy::Strict => self.needed_and_strict_hash(db_handle),
+            DepHashStrategy::Strict | DepHashStrategy::Cascade =>
+                self.needed_and_strict_hash(db_handle),
             DepHashStrategy::Loose => self.needed_and_loose_hash(db_handle),
             DepHashStrategy::None => self.needed_and_no_hash(db_handle),
+            DepHashStrategy::Soname => self.needed_and_soname_hash(db_handle),
         };
         self.needs.sort_unstable();
         self.needs.dedup();
@@ -140,7 +180,9 @@ impl Depends {
 
     /// Todo: cache package in our own storage, not tainting host, also without
     /// root permission.
-    pub(crate) fn cache_raw<S: AsRef<OsStr>>(deps: &Vec<String>, dbpath: S)
+    pub(crate) fn cache_raw<S: AsRef<OsStr>>(
+        deps: &Vec<String>, dbpath: S, target: &Target
+    )
         -> Result<()>
     {
         if deps.len() == 0 {
@@ -153,7 +195,7 @@ impl Depends {
             crate::logfile::LogFile::new(
                 crate::logfile::LogType::Pacman, "cache-packages"
             )?.set_command(
-                command
+                target.set_pacman_command(&mut command)
                 .env("LANG", "C")
                 .arg("-S")
                 .arg("--dbpath")
@@ -176,12 +218,53 @@ impl Depends {
         }
     }
 
-    pub(crate) fn wants(&self, pkg: &str) -> bool {
+    /// The dep or makedep satisfied by the offer, and the pkgname or provide
+    /// satisfying it
+    pub(crate) fn wants<'b>(&self, offer: &Offer<'b>)
+        -> Option<(&str, &'b str)>
+    {
         for dep in self.deps.iter().chain(self.makedeps.iter()) {
-            if dep == pkg {
-                return true
+            if let Some(pkg) = offer.satisfier(&DepExpr::parse(dep)) {
+                return Some((dep, pkg))
             }
         }
-        false
+        None
     }
-}
\ No newline at end of file
+
+    /// Versioned deps naming something in the offer but not satisfied by it
+    pub(crate) fn unsatisfied_by(&self, offer: &Offer) -> Vec<&str> {
+        self.deps.iter().chain(self.makedeps.iter()).filter(|dep| {
+            let dep = DepExpr::parse(dep);
+            dep.is_versioned() && offer.mentions(&dep) &&
+                offer.satisfier(&dep).is_none()
+        }).map(|dep| dep.as_str()).collect()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn unsatisfied_by() {
+        let depends = Depends {
+            deps: strings(&["foo>=2", "libfoo=2.0", "bar>=1"]),
+            makedeps: strings(&["foo-docs<1.5", "foo-git"]),
+            needs: vec![],
+            hash: 0,
+            soname_provides: vec![],
+            sonames: None,
+        };
+        let names = strings(&["foo", "foo-docs"]);
+        let provides = strings(&["libfoo=2.0", "foo-git"]);
+        let offer = Offer {
+            names: &names,
+            version: "1.5-1",
+            provides: &provides,
+        };
+        assert_eq!(depends.unsatisfied_by(&offer), ["foo>=2", "foo-docs<1.5"]);
+    }
+}
@@ -1,3 +1,13 @@
+use std::{
+        collections::{
+            HashMap,
+            VecDeque,
+        },
+        fmt::Write,
+    };
+
+use serde::Serialize;
+
 use crate::{
         error::{
             Error,
@@ -9,93 +19,311 @@ use crate::{
         },
     };
 
-struct DepNode<'a> {
-    pkgbuild: &'a PKGBUILD,
-    wants: Vec<&'a PKGBUILD>,
-    // deps: Vec<&'a str>,
+pub(crate) struct DepEdge<'a> {
+    /// Id of the wanted PKGBUILD
+    pub(crate) id: usize,
+    /// The dep string in the wanting PKGBUILD
+    pub(crate) dep: &'a str,
+    /// The pkgname or provide of the wanted PKGBUILD satisfying the dep
+    pub(crate) provide: &'a str,
+}
+
+pub(crate) struct DepNode<'a> {
+    pub(crate) pkgbuild: &'a PKGBUILD,
+    pub(crate) wants: Vec<DepEdge<'a>>,
+}
+
+/// PKGBUILDs and their wants among each other, ids are the same as in PKGBUILDs
+pub(crate) struct DepGraph<'a> {
+    pub(crate) nodes: Vec<DepNode<'a>>
+}
+
+#[derive(Serialize)]
+struct JsonNode<'a> {
+    base: &'a str,
+    names: &'a Vec<String>,
+    provides: &'a Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+    dep: &'a str,
+    provide: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+    cycles: Vec<Vec<&'a str>>,
 }
 
-struct DepNodes<'a> {
-    nodes: Vec<DepNode<'a>>
+/// Tarjan's strongly connected components
+struct Components<'b, 'a> {
+    graph: &'b DepGraph<'a>,
+    next: usize,
+    indices: Vec<Option<usize>>,
+    lowlinks: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    components: Vec<Vec<usize>>,
+}
+
+impl<'b, 'a> Components<'b, 'a> {
+    fn new(graph: &'b DepGraph<'a>) -> Self {
+        let len = graph.nodes.len();
+        Self {
+            graph,
+            next: 0,
+            indices: vec![None; len],
+            lowlinks: vec![0; len],
+            on_stack: vec![false; len],
+            stack: vec![],
+            components: vec![],
+        }
+    }
+
+    fn connect(&mut self, id: usize) {
+        self.indices[id] = Some(self.next);
+        self.lowlinks[id] = self.next;
+        self.next += 1;
+        self.stack.push(id);
+        self.on_stack[id] = true;
+        for edge in self.graph.nodes[id].wants.iter() {
+            match self.indices[edge.id] {
+                None => {
+                    self.connect(edge.id);
+                    self.lowlinks[id] =
+                        self.lowlinks[id].min(self.lowlinks[edge.id])
+                },
+                Some(index) => if self.on_stack[edge.id] {
+                    self.lowlinks[id] = self.lowlinks[id].min(index)
+                },
+            }
+        }
+        if Some(self.lowlinks[id]) == self.indices[id] {
+            let mut component = vec![];
+            while let Some(member) = self.stack.pop() {
+                self.on_stack[member] = false;
+                component.push(member);
+                if member == id {
+                    break
+                }
+            }
+            self.components.push(component)
+        }
+    }
+
+    fn split(mut self) -> Vec<Vec<usize>> {
+        for id in 0..self.graph.nodes.len() {
+            if self.indices[id].is_none() {
+                self.connect(id)
+            }
+        }
+        self.components
+    }
 }
 
-impl<'a> DepNodes<'a>  {
-    fn from_pkgbuilds(pkgbuilds: &'a PKGBUILDs) -> Result<Self> {
+impl<'a> DepGraph<'a>  {
+    pub(crate) fn from_pkgbuilds(pkgbuilds: &'a PKGBUILDs) -> Result<Self> {
         let mut nodes = vec![];
         for pkgbuild in pkgbuilds.0.iter() {
-            let mut wants = vec![];
-            let mut deps = vec![];
-            for pkgbuild_target in pkgbuilds.0.iter() {
+            let mut wants: Vec<DepEdge> = vec![];
+            for (id, pkgbuild_target) in pkgbuilds.0.iter().enumerate() {
                 if std::ptr::eq(pkgbuild, pkgbuild_target) {
                     continue
                 }
-                if let Some(dep) = pkgbuild.wants(pkgbuild_target) {
-                    if deps.contains(&dep) {
-                        log::error!("'{}' is provided by multiple PKGBUILDs, \
-                            please check if your PKGBUILDs are valid", dep);
+                if let Some((dep, provide)) = pkgbuild.wants(pkgbuild_target) {
+                    if let Some(edge) = wants.iter().find(
+                        |edge| edge.dep == dep)
+                    {
+                        log::error!("'{}' wanted by '{}' is provided by both \
+                            '{}' and '{}', please check if your PKGBUILDs are \
+                            valid", dep, pkgbuild.base,
+                            pkgbuilds.0[edge.id].base, pkgbuild_target.base);
                         return Err(Error::InvalidConfig)
                     } else {
-                        wants.push(pkgbuild_target);
-                        deps.push(&dep)
+                        wants.push(DepEdge { id, dep, provide })
                     }
                 }
             }
             nodes.push(DepNode{
                 pkgbuild,
                 wants,
-                // deps
             })
         }
         Ok(Self{nodes})
     }
 
-    fn split(mut self) -> Result<Vec<Vec<&'a PKGBUILD>>> {
-        let mut layers: Vec<Vec<DepNode>> = vec![];
-        while ! self.nodes.is_empty() {
-            if let Some(layer) = layers.last() {
-                log::info!("Removing deps in last layer");
-                for node in self.nodes.iter_mut() {
-                    for node_old in layer.iter() {
-                        node.wants.retain(|pkgbuild|
-                            !std::ptr::eq(*pkgbuild, node_old.pkgbuild));
+    /// Ids of the PKGBUILDs wanting each PKGBUILD
+    pub(crate) fn wanted_by(&self) -> Vec<Vec<usize>> {
+        let mut wanted_by = vec![vec![]; self.nodes.len()];
+        for (id, node) in self.nodes.iter().enumerate() {
+            for want in node.wants.iter() {
+                wanted_by[want.id].push(id)
+            }
+        }
+        wanted_by
+    }
+
+    /// The shortest cycle through the first member of a strongly connected
+    /// component
+    fn cycle_in(&self, component: &[usize]) -> Vec<usize> {
+        let start = component[0];
+        let mut parents = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(id) = queue.pop_front() {
+            for edge in self.nodes[id].wants.iter() {
+                if edge.id == start {
+                    let mut cycle = vec![id];
+                    let mut current = id;
+                    while current != start {
+                        current = parents[&current];
+                        cycle.push(current)
                     }
+                    cycle.reverse();
+                    return cycle
+                }
+                if component.contains(&edge.id) &&
+                    ! parents.contains_key(&edge.id)
+                {
+                    parents.insert(edge.id, id);
+                    queue.push_back(edge.id)
                 }
             }
-            let (layer, nodes)
-                : (Vec<DepNode>, Vec<DepNode>)
-                = self.nodes.into_iter().partition(
-                    |node|node.wants.is_empty());
-            if layer.is_empty() {
-                log::error!("Failed to split dep layers more, current layer is \
-                    empty, please check if your PKGBUILDs are valid, remaining \
-                    nodes: {}", nodes.len());
-                return Err(Error::InvalidConfig)
+        }
+        component.to_vec()
+    }
+
+    /// One cycle for each group of PKGBUILDs wanting each other, each as ids
+    /// where every one wants the next one, and the last one wants the first
+    pub(crate) fn cycles(&self) -> Vec<Vec<usize>> {
+        Components::new(self).split().iter().filter(
+            |component| component.len() > 1).map(
+            |component| self.cycle_in(component)).collect()
+    }
+
+    /// e.g. 'a' -[b>=1]-> 'b' -[a]-> 'a'
+    pub(crate) fn describe_cycle(&self, cycle: &[usize]) -> String {
+        let mut line = String::new();
+        for (index, id) in cycle.iter().enumerate() {
+            let next = cycle[(index + 1) % cycle.len()];
+            let node = &self.nodes[*id];
+            let dep = node.wants.iter().find(|edge| edge.id == next)
+                .map_or("?", |edge| edge.dep);
+            let _ = write!(line, "'{}' -[{}]-> ", node.pkgbuild.base, dep);
+        }
+        let _ = write!(line, "'{}'", self.nodes[cycle[0]].pkgbuild.base);
+        line
+    }
+
+    /// Ids in an order that every PKGBUILD comes after all it wants, fails if
+    /// there're cycles
+    pub(crate) fn sorted(&self) -> Result<Vec<usize>> {
+        let wanted_by = self.wanted_by();
+        let mut wants_count: Vec<usize> = self.nodes.iter().map(
+            |node| node.wants.len()).collect();
+        let mut sorted: Vec<usize> = (0..self.nodes.len()).filter(
+            |id| wants_count[*id] == 0).collect();
+        let mut next = 0;
+        while next < sorted.len() {
+            for id in wanted_by[sorted[next]].iter() {
+                wants_count[*id] -= 1;
+                if wants_count[*id] == 0 {
+                    sorted.push(*id)
+                }
             }
-            self.nodes = nodes;
-            layers.push(layer);
+            next += 1
         }
-        log::info!("Split PKGBUILDs into {} layers:", layers.len());
-        let mut pkgbuild_layers = vec![];
-        for (layer_id, layer) in
-            layers.iter().enumerate()
-        {
-            let mut pkgbuild_layer = vec![];
-            let mut line = format!("Layer {}:", layer_id);
-            for node in layer.iter() {
-                line.push_str(
-                    format!(" '{}'", &node.pkgbuild.base).as_str());
-                pkgbuild_layer.push(node.pkgbuild);
+        if sorted.len() < self.nodes.len() {
+            log::error!("PKGBUILDs want each other in cycles, please check if \
+                your PKGBUILDs are valid:");
+            for cycle in self.cycles() {
+                log::error!("Cycle: {}", self.describe_cycle(&cycle))
             }
-            line.push('\n');
-            log::info!("{}", line);
-            pkgbuild_layers.push(pkgbuild_layer)
+            return Err(Error::InvalidConfig)
         }
-        Ok(pkgbuild_layers)
+        Ok(sorted)
+    }
+
+    /// For each PKGBUILD, the longest sum of estimates along any chain from it
+    /// to the PKGBUILDs wanting it transitively, itself included
+    pub(crate) fn critical_paths<F>(&self, estimate: F) -> Result<Vec<u64>>
+    where
+        F: Fn(&PKGBUILD) -> u64
+    {
+        let wanted_by = self.wanted_by();
+        let mut paths = vec![0; self.nodes.len()];
+        for id in self.sorted()?.into_iter().rev() {
+            paths[id] = estimate(self.nodes[id].pkgbuild) +
+                wanted_by[id].iter().map(|by| paths[*by]).max().unwrap_or(0)
+        }
+        Ok(paths)
+    }
+
+    /// Edges point from the wanting PKGBUILD to the wanted one, those in
+    /// cycles are red
+    pub(crate) fn to_dot(&self, cycles: &[Vec<usize>]) -> String {
+        let in_cycle = |from: usize, to: usize| cycles.iter().any(|cycle|
+            (0..cycle.len()).any(|index| cycle[index] == from &&
+                cycle[(index + 1) % cycle.len()] == to));
+        let quote = |value: &str| format!("\"{}\"",
+            value.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph pkgbuilds {\n");
+        for node in self.nodes.iter() {
+            let _ = writeln!(dot, "    {};", quote(&node.pkgbuild.base));
+        }
+        for (id, node) in self.nodes.iter().enumerate() {
+            for edge in node.wants.iter() {
+                let label = match edge.dep == edge.provide {
+                    true => edge.dep.to_string(),
+                    false => format!("{} ({})", edge.dep, edge.provide),
+                };
+                let _ = writeln!(dot, "    {} -> {} [label={}{}];",
+                    quote(&node.pkgbuild.base),
+                    quote(&self.nodes[edge.id].pkgbuild.base),
+                    quote(&label),
+                    match in_cycle(id, edge.id) {
+                        true => ", color=red",
+                        false => "",
+                    });
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub(crate) fn to_json(&self, cycles: &[Vec<usize>]) -> Result<String> {
+        let base = |id: usize| self.nodes[id].pkgbuild.base.as_str();
+        let graph = JsonGraph {
+            nodes: self.nodes.iter().map(|node| JsonNode {
+                base: &node.pkgbuild.base,
+                names: &node.pkgbuild.names,
+                provides: &node.pkgbuild.provides,
+            }).collect(),
+            edges: self.nodes.iter().flat_map(|node|
+                node.wants.iter().map(|edge| JsonEdge {
+                    from: &node.pkgbuild.base,
+                    to: base(edge.id),
+                    dep: edge.dep,
+                    provide: edge.provide,
+                })).collect(),
+            cycles: cycles.iter().map(|cycle|
+                cycle.iter().map(|id| base(*id)).collect()).collect(),
+        };
+        serde_json::to_string_pretty(&graph).map_err(|e| {
+            log::error!("Failed to serialize dependency graph: {}", e);
+            Error::ImpossibleLogic
+        })
     }
 }
 
-pub(crate) fn split_pkgbuilds<'a>(pkgbuilds: &'a PKGBUILDs)
-    -> Result<Vec<Vec<&'a PKGBUILD>>>
+pub(crate) fn graph_pkgbuilds<'a>(pkgbuilds: &'a PKGBUILDs)
+    -> Result<DepGraph<'a>>
 {
-    DepNodes::from_pkgbuilds(pkgbuilds)?.split()
-}
\ No newline at end of file
+    let graph = DepGraph::from_pkgbuilds(pkgbuilds)?;
+    graph.sorted()?;
+    Ok(graph)
+}
@@ -0,0 +1,216 @@
+// Dependency expressions like 'libfoo>=1.2', matched the way pacman does
+use std::cmp::Ordering;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+/// Longer ones first so '>=' is not taken as '>'
+const OPS: &[(&str, Op)] = &[
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+    ("=", Op::Eq),
+];
+
+pub(crate) struct DepExpr<'a> {
+    pub(crate) name: &'a str,
+    constraint: Option<(Op, &'a str)>,
+}
+
+impl<'a> DepExpr<'a> {
+    /// e.g. 'libfoo', 'libfoo>=1.2', 'so:libfoo.so=1-64'
+    pub(crate) fn parse(expr: &'a str) -> Self {
+        let index = match expr.find(['<', '>', '=']) {
+            Some(index) => index,
+            None => return Self { name: expr, constraint: None },
+        };
+        let (name, rest) = expr.split_at(index);
+        for (symbol, op) in OPS.iter() {
+            if let Some(version) = rest.strip_prefix(symbol) {
+                return Self { name, constraint: Some((*op, version)) }
+            }
+        }
+        Self { name: expr, constraint: None }
+    }
+
+    pub(crate) fn is_versioned(&self) -> bool {
+        self.constraint.is_some()
+    }
+
+    /// Whether a package of the name at the version satisfies this, the
+    /// version is None for unversioned provides, which only satisfy
+    /// unversioned deps
+    pub(crate) fn satisfied_by(&self, name: &str, version: Option<&str>)
+        -> bool
+    {
+        if self.name != name {
+            return false
+        }
+        let (op, wanted) = match self.constraint {
+            Some(constraint) => constraint,
+            None => return true,
+        };
+        let version = match version {
+            Some(version) => version,
+            None => return false,
+        };
+        // Same as pacman, pkgrel is only compared when both have it
+        let ordering = alpm::vercmp(version, wanted);
+        match op {
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Gt => ordering == Ordering::Greater,
+        }
+    }
+
+    /// Whether a provide, e.g. 'libfoo' or 'libfoo=1.2', satisfies this
+    pub(crate) fn satisfied_by_provide(&self, provide: &str) -> bool {
+        let provide = DepExpr::parse(provide);
+        match provide.constraint {
+            Some((Op::Eq, version)) =>
+                self.satisfied_by(provide.name, Some(version)),
+            Some(_) => false,
+            None => self.satisfied_by(provide.name, None),
+        }
+    }
+}
+
+/// What a PKGBUILD offers to satisfy deps, with all its split packages
+pub(crate) struct Offer<'b> {
+    pub(crate) names: &'b [String],
+    /// The full version, [epoch:]pkgver-pkgrel
+    pub(crate) version: &'b str,
+    pub(crate) provides: &'b [String],
+}
+
+impl<'b> Offer<'b> {
+    /// The pkgname or provide satisfying the dep
+    pub(crate) fn satisfier(&self, dep: &DepExpr) -> Option<&'b str> {
+        if let Some(name) = self.names.iter().find(
+            |name| dep.satisfied_by(name, Some(self.version)))
+        {
+            return Some(name)
+        }
+        self.provides.iter().find(
+            |provide| dep.satisfied_by_provide(provide)).map(
+            |provide| provide.as_str())
+    }
+
+    pub(crate) fn satisfies(&self, dep: &str) -> bool {
+        self.satisfier(&DepExpr::parse(dep)).is_some()
+    }
+
+    /// Whether the dep names any pkgname or provide, regardless of version
+    pub(crate) fn mentions(&self, dep: &DepExpr) -> bool {
+        self.names.iter().any(|name| name == dep.name) ||
+            self.provides.iter().any(
+                |provide| DepExpr::parse(provide).name == dep.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_ops() {
+        let dep = DepExpr::parse("foo<=1.2");
+        assert_eq!(dep.name, "foo");
+        assert!(matches!(dep.constraint, Some((Op::Le, "1.2"))));
+        let dep = DepExpr::parse("foo<1.2");
+        assert!(matches!(dep.constraint, Some((Op::Lt, "1.2"))));
+        let dep = DepExpr::parse("foo>=1.2");
+        assert!(matches!(dep.constraint, Some((Op::Ge, "1.2"))));
+        let dep = DepExpr::parse("foo>1.2");
+        assert!(matches!(dep.constraint, Some((Op::Gt, "1.2"))));
+        let dep = DepExpr::parse("foo");
+        assert_eq!(dep.name, "foo");
+        assert!(!dep.is_versioned());
+    }
+
+    #[test]
+    fn parse_soname() {
+        let dep = DepExpr::parse("so:libfoo.so=1-64");
+        assert_eq!(dep.name, "so:libfoo.so");
+        assert!(matches!(dep.constraint, Some((Op::Eq, "1-64"))));
+        assert!(DepExpr::parse("so:libfoo.so=1-64").satisfied_by_provide(
+            "so:libfoo.so=1-64"));
+        assert!(!DepExpr::parse("so:libfoo.so=1-64").satisfied_by_provide(
+            "so:libfoo.so=1-32"));
+    }
+
+    #[test]
+    fn le_and_lt_differ() {
+        assert!(DepExpr::parse("foo<=1.2").satisfied_by("foo", Some("1.2")));
+        assert!(!DepExpr::parse("foo<1.2").satisfied_by("foo", Some("1.2")));
+        assert!(DepExpr::parse("foo<1.2").satisfied_by("foo", Some("1.1")));
+        assert!(!DepExpr::parse("foo<1.2").satisfied_by("bar", Some("1.1")));
+    }
+
+    #[test]
+    fn unversioned_provides() {
+        let dep = DepExpr::parse("libfoo>=1");
+        assert!(!dep.satisfied_by_provide("libfoo"));
+        assert!(!dep.satisfied_by("libfoo", None));
+        assert!(dep.satisfied_by_provide("libfoo=1.5"));
+        assert!(!dep.satisfied_by_provide("libfoo>=1.5"));
+        assert!(DepExpr::parse("libfoo").satisfied_by_provide("libfoo"));
+        assert!(DepExpr::parse("libfoo").satisfied_by_provide("libfoo=1.5"));
+    }
+
+    #[test]
+    fn epoch() {
+        let dep = DepExpr::parse("foo>=1:1.0");
+        assert!(!dep.satisfied_by("foo", Some("2.0-1")));
+        assert!(dep.satisfied_by("foo", Some("1:1.0-1")));
+        assert!(dep.satisfied_by("foo", Some("2:0.1-1")));
+        assert!(DepExpr::parse("foo>3.0").satisfied_by("foo", Some("1:1.0-1")));
+    }
+
+    #[test]
+    fn pkgrel() {
+        assert!(DepExpr::parse("foo=1.0").satisfied_by("foo", Some("1.0-2")));
+        assert!(DepExpr::parse("foo=1.0-2").satisfied_by("foo", Some("1.0-2")));
+        assert!(!DepExpr::parse("foo=1.0-1").satisfied_by("foo", Some("1.0-2")));
+        assert!(DepExpr::parse("foo>1.0-1").satisfied_by("foo", Some("1.0-2")));
+        assert!(!DepExpr::parse("foo>1.0").satisfied_by("foo", Some("1.0-2")));
+    }
+
+    #[test]
+    fn split_package_provides() {
+        let names = strings(&["foo", "foo-docs"]);
+        let provides = strings(&["libfoo=2.0", "libfoo.so=2-64", "foo-git"]);
+        let offer = Offer {
+            names: &names,
+            version: "1:1.5-3",
+            provides: &provides,
+        };
+        assert_eq!(offer.satisfier(&DepExpr::parse("foo-docs>=1:1.0")),
+            Some("foo-docs"));
+        assert_eq!(offer.satisfier(&DepExpr::parse("libfoo>=2")),
+            Some("libfoo=2.0"));
+        assert_eq!(offer.satisfier(&DepExpr::parse("libfoo.so=2-64")),
+            Some("libfoo.so=2-64"));
+        assert_eq!(offer.satisfier(&DepExpr::parse("foo-git")),
+            Some("foo-git"));
+        assert_eq!(offer.satisfier(&DepExpr::parse("foo-git>=1")), None);
+        assert_eq!(offer.satisfier(&DepExpr::parse("libfoo>2.0")), None);
+        assert!(offer.mentions(&DepExpr::parse("libfoo>2.0")));
+        assert!(!offer.mentions(&DepExpr::parse("bar")));
+        assert!(offer.satisfies("foo=1:1.5"));
+        assert!(!offer.satisfies("foo=1.5"));
+    }
+}
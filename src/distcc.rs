@@ -0,0 +1,161 @@
+// Distributed compiling through distcc, hosts are probed before builds so dead
+// ones would not stall them
+use std::{
+        net::{
+            TcpStream,
+            ToSocketAddrs,
+        },
+        time::Duration,
+    };
+
+use crate::{
+        config::Distcc as DistccConfig,
+        error::{
+            Error,
+            Result,
+        },
+    };
+
+const PORT_DEFAULT: u16 = 3632;
+/// distcc's own limit for a host without /LIMIT
+const LIMIT_DEFAULT: usize = 4;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A TCP host in DISTCC_HOSTS syntax: HOST[:PORT][/LIMIT][,OPTIONS], ssh
+/// (with @), zeroconf and localhost ones are not probed and not counted as
+/// remote slots
+pub(crate) struct Host<'a> {
+    address: &'a str,
+    port: u16,
+    limit: usize,
+}
+
+impl<'a> Host<'a> {
+    /// None for hosts that're not plain TCP ones, Err for malformed ones
+    pub(crate) fn parse(spec: &'a str) -> std::result::Result<Option<Self>, ()> {
+        if spec.is_empty() || spec.contains(char::is_whitespace) ||
+            spec.contains('\'')
+        {
+            return Err(())
+        }
+        if spec.contains('@') || spec.starts_with('+') ||
+            spec.starts_with("--") ||
+            spec.starts_with("localhost")
+        {
+            return Ok(None)
+        }
+        let spec = match spec.split_once(',') {
+            Some((spec, _)) => spec,
+            None => spec,
+        };
+        let (spec, limit) = match spec.rsplit_once('/') {
+            Some((spec, limit)) => (spec, limit.parse().or(Err(()))?),
+            None => (spec, LIMIT_DEFAULT),
+        };
+        let (address, port) = if let Some(rest) = spec.strip_prefix('[') {
+            match rest.split_once(']') {
+                Some((address, "")) => (address, None),
+                Some((address, port)) => match port.strip_prefix(':') {
+                    Some(port) => (address, Some(port)),
+                    None => return Err(()),
+                },
+                None => return Err(()),
+            }
+        } else {
+            match spec.split_once(':') {
+                Some((address, port)) => (address, Some(port)),
+                None => (spec, None),
+            }
+        };
+        if address.is_empty() || limit == 0 {
+            return Err(())
+        }
+        let port = match port {
+            Some(port) => port.parse().or(Err(()))?,
+            None => PORT_DEFAULT,
+        };
+        Ok(Some(Self { address, port, limit }))
+    }
+
+    fn probe(&self) -> bool {
+        let addrs = match (self.address, self.port).to_socket_addrs() {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                log::warn!("Failed to resolve distcc host '{}': {}",
+                    self.address, e);
+                return false
+            },
+        };
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+                Ok(_) => return true,
+                Err(e) => log::warn!("Failed to connect to distcc host '{}' \
+                    at {}: {}", self.address, addr, e),
+            }
+        }
+        false
+    }
+}
+
+/// The reachable hosts for this run
+pub(crate) struct Distcc {
+    hosts: Vec<String>,
+    /// Sum of limits of the remote TCP hosts
+    slots: usize,
+    jobs: usize,
+}
+
+impl Distcc {
+    /// Probe all hosts, None if no host is usable
+    pub(crate) fn new(config: &DistccConfig) -> Result<Option<Self>> {
+        let mut hosts = vec![];
+        let mut slots = 0;
+        for spec in config.hosts.iter() {
+            match Host::parse(spec) {
+                Ok(Some(host)) => if host.probe() {
+                    log::info!("Using distcc host '{}' with {} slots",
+                        spec, host.limit);
+                    hosts.push(spec.clone());
+                    slots += host.limit
+                } else {
+                    log::warn!("Dropped unreachable distcc host '{}'", spec)
+                },
+                Ok(None) => {
+                    log::info!("Using distcc host '{}' without probing", spec);
+                    hosts.push(spec.clone())
+                },
+                Err(_) => {
+                    log::error!("Malformed distcc host '{}'", spec);
+                    return Err(Error::InvalidConfig)
+                },
+            }
+        }
+        if hosts.is_empty() {
+            log::warn!("No distcc host is usable, building without distcc");
+            return Ok(None)
+        }
+        let cores = match procfs::CpuInfo::new() {
+            Ok(cpuinfo) => cpuinfo.num_cores(),
+            Err(e) => {
+                log::error!("Failed to get cpuinfo: {}", e);
+                return Err(Error::ProcError(e))
+            },
+        };
+        let jobs = config.jobs.unwrap_or(cores + slots);
+        log::info!("Building through distcc with {} remote slots, {} jobs \
+            for each build", slots, jobs);
+        Ok(Some(Self { hosts, slots, jobs }))
+    }
+
+    /// Remote slots that builds using distcc could have on top of local cores
+    pub(crate) fn slots(&self) -> usize {
+        self.slots
+    }
+
+    /// makepkg.conf lines enabling distcc
+    pub(crate) fn conf_append(&self) -> String {
+        format!("\n# Distributed compiling managed by arb\n\
+            BUILDENV+=(distcc)\nDISTCC_HOSTS='{}'\nMAKEFLAGS='-j{}'\n",
+            self.hosts.join(" "), self.jobs)
+    }
+}
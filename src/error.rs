@@ -1,12 +1,14 @@
 #[derive(Debug)]
 pub(crate) enum Error {
     AlpmError (alpm::Error),
+    BadPackages (Vec<String>),
     BadChild {
         pid: Option<nix::unistd::Pid>,
         code: Option<i32>,
     },
     BrokenEnvironment,
     BrokenPKGBUILDs (Vec<String>),
+    BrokenPatches (Vec<String>),
     BuildFailure,
     Collapsed(String),
     DependencyMissing (Vec<String>),
@@ -20,6 +22,7 @@ pub(crate) enum Error {
     IoError (std::io::Error),
     NixErrno (nix::errno::Errno),
     ProcError (procfs::ProcError),
+    ReferenceMissing (Vec<String>),
     ThreadFailure (Option<Box<dyn std::any::Any + Send + 'static>>),
     TimeError (time::Error),
     UreqError (ureq::Error),
@@ -38,9 +41,11 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::AlpmError(e) => write!(f, "Alpm Error: {}", e),
+            Error::BadPackages(packages) => write!(f, "Bad packages: {:?}", packages),
             Error::BadChild { pid, code } => write!(f, "Bad child, pid {:?}, code {:?}", pid, code),
             Error::BrokenEnvironment => write!(f, "Broken Environment"),
             Error::BrokenPKGBUILDs(pkgbuilds) => write!(f, "Broken PKGBUILDs: {:?}", pkgbuilds),
+            Error::BrokenPatches(patches) => write!(f, "Broken patches: {:?}", patches),
             Error::BuildFailure => write!(f, "Build Failure"),
             Error::Collapsed(s) => write!(f, "Collapsed {}", s),
             Error::DependencyMissing( deps ) => write!(f, "Dependency missing: {:?}", deps),
@@ -53,6 +58,7 @@ impl std::fmt::Display for Error {
             Error::IoError(e) => write!(f, "IO Error: {}", e),
             Error::NixErrno(e) => write!(f, "Nix Errno: {}", e),
             Error::ProcError(e) => write!(f, "Proc Error: {}", e),
+            Error::ReferenceMissing(refs) => write!(f, "Reference missing: {:?}", refs),
             Error::ThreadFailure(artifact) => write!(f, "Thread Failure, artifact: {:?}", artifact),
             Error::TimeError(e) => write!(f, "Time Error: {}", e),
             Error::UreqError(e) => write!(f, "Ureq Error: {}", e),
@@ -123,9 +129,11 @@ impl Clone for Error {
     fn clone(&self) -> Self {
         match self {
             Self::AlpmError(arg0) => Self::AlpmError(arg0.clone()),
+            Self::BadPackages(arg0) => Self::BadPackages(arg0.clone()),
             Self::BadChild { pid, code } => Self::BadChild { pid: pid.clone(), code: code.clone() },
             Self::BrokenEnvironment => Self::BrokenEnvironment,
             Self::BrokenPKGBUILDs(arg0) => Self::BrokenPKGBUILDs(arg0.clone()),
+            Self::BrokenPatches(arg0) => Self::BrokenPatches(arg0.clone()),
             Self::BuildFailure => Self::BuildFailure,
             Self::Collapsed(arg0) => Self::Collapsed(arg0.clone()),
             Self::DependencyMissing(arg0) => Self::DependencyMissing(arg0.clone()),
@@ -138,6 +146,7 @@ impl Clone for Error {
             Self::IoError(arg0) => Self::IoError(std::io::Error::from(arg0.kind())),
             Self::NixErrno(arg0) => Self::NixErrno(*arg0),
             Self::ProcError(arg0) => Self::Collapsed(format!("From Proc Error: {}", arg0)),
+            Self::ReferenceMissing(arg0) => Self::ReferenceMissing(arg0.clone()),
             Self::ThreadFailure(arg0) => Self::Collapsed(format!("From Thread Failure: {:?}", arg0)),
             Self::TimeError(arg0) => Self::Collapsed(format!("From Time Error: {}", arg0)),
             Self::UreqError(arg0) => Self::Collapsed(format!("From Ureq Error: {}", arg0)),
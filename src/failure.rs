@@ -0,0 +1,232 @@
+// Why a build failed, guessed from the tail and the likely error lines of its
+// log, so the logs don't need to be opened one by one
+use std::{
+        collections::VecDeque,
+        fs::File,
+        io::{
+            BufRead,
+            BufReader,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use serde::Serialize;
+
+use crate::error::{
+        Error,
+        Result,
+    };
+
+/// Lines from the end of the log printed and kept
+const TAIL_LINES: usize = 30;
+/// Likely error lines kept, the first ones are usually the cause
+const ERRORS_MAX: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FailureKind {
+    /// Fetching, verifying, extracting or preparing sources, or pkgver()
+    Source,
+    /// Something the build wants is missing or broken
+    Dependency,
+    /// build(), also when nothing more specific is found
+    Compile,
+    /// check() run as part of the build
+    Check,
+    /// package(), or the built packages being bad or failing lint
+    Packaging,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Failure {
+    pub(crate) kind: FailureKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) log: Option<PathBuf>,
+    /// Likely error lines, in the order they appear
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) errors: Vec<String>,
+    /// The last lines of the log
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) tail: Vec<String>,
+}
+
+/// Substrings of lines that're likely errors
+const ERROR_PATTERNS: &[&str] = &[
+    "==> ERROR:",
+    "error:",
+    "error[",
+    "Error:",
+    "ERROR:",
+    "fatal:",
+    "FAILED:",
+    "npm ERR!",
+    ": ***",
+    "No such file or directory",
+    "command not found",
+    "undefined reference to",
+    "cannot find",
+    "Could NOT find",
+    "Traceback (most recent call last)",
+];
+
+/// Substrings of lines telling a dependency is missing or broken, over the
+/// step it fails in, as that's where it's noticed not what's wrong
+const DEPENDENCY_PATTERNS: &[&str] = &[
+    "command not found",
+    "Could NOT find",
+    "Could not find a package configuration file",
+    "was not found in the pkg-config search path",
+    "No package '",
+    "error while loading shared libraries",
+    ".h: No such file or directory",
+    "ModuleNotFoundError",
+    "ImportError",
+    "Can't locate ",
+    "cannot find -l",
+    "Cannot find module",
+    "failed to select a version",
+];
+
+/// Substrings of lines telling sources could not be had
+const SOURCE_PATTERNS: &[&str] = &[
+    "==> ERROR: Failure while downloading",
+    "==> ERROR: One or more files did not pass the validity check",
+    "==> ERROR: One or more PGP signatures could not be verified",
+    "==> ERROR: Could not download sources",
+    "==> ERROR: Failed to extract",
+    "==> ERROR: A failure occurred in prepare()",
+    "==> ERROR: A failure occurred in pkgver()",
+];
+
+fn is_error(line: &str) -> bool {
+    ERROR_PATTERNS.iter().any(|pattern| line.contains(pattern))
+}
+
+/// The kind from the step makepkg says failed, then refined by what's said
+/// before it, the default one if nothing tells
+fn classify(errors: &[String], default: FailureKind) -> FailureKind {
+    let contains = |patterns: &[&str]| errors.iter().any(
+        |line| patterns.iter().any(|pattern| line.contains(pattern)));
+    if contains(SOURCE_PATTERNS) {
+        return FailureKind::Source
+    }
+    if contains(DEPENDENCY_PATTERNS) {
+        return FailureKind::Dependency
+    }
+    if contains(&["==> ERROR: A failure occurred in check()"]) {
+        return FailureKind::Check
+    }
+    if contains(&["==> ERROR: A failure occurred in package"]) {
+        return FailureKind::Packaging
+    }
+    if contains(&["==> ERROR: A failure occurred in build()"]) {
+        return FailureKind::Compile
+    }
+    default
+}
+
+/// The tail and the first likely errors of a log, in one pass, as build logs
+/// could be huge
+fn scan_log(path: &Path) -> Result<(Vec<String>, Vec<String>)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open log '{}' to scan: {}",
+                path.display(), e);
+            return Err(e.into())
+        },
+    };
+    let mut reader = BufReader::new(file);
+    let mut tail = VecDeque::with_capacity(TAIL_LINES);
+    let mut errors = vec![];
+    // The last makepkg error is kept even past the limit, it names the step
+    let mut makepkg_error = None;
+    let mut buffer = vec![];
+    loop {
+        buffer.clear();
+        match reader.read_until(b'\n', &mut buffer) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => {
+                log::error!("Failed to read log '{}' to scan: {}",
+                    path.display(), e);
+                return Err(e.into())
+            },
+        }
+        let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+        if is_error(&line) {
+            if line.contains("==> ERROR:") {
+                makepkg_error = Some(line.clone())
+            }
+            if errors.len() < ERRORS_MAX {
+                errors.push(line.clone())
+            }
+        }
+        if tail.len() == TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line)
+    }
+    if let Some(line) = makepkg_error {
+        if ! errors.contains(&line) {
+            errors.push(line)
+        }
+    }
+    Ok((tail.into(), errors))
+}
+
+impl Failure {
+    /// From the log of the failed step, only the default kind if it could not
+    /// be read
+    pub(crate) fn from_log(
+        pkgid: &str, log: Option<&Path>, default: FailureKind
+    ) -> Self
+    {
+        let (tail, errors) = match log {
+            Some(log) => scan_log(log).unwrap_or_default(),
+            None => (vec![], vec![]),
+        };
+        let kind = classify(&errors, default);
+        if let Some(log) = log {
+            if ! tail.is_empty() {
+                log::error!("Last {} lines of log '{}' of '{}':\n{}",
+                    tail.len(), log.display(), pkgid, tail.join("\n"))
+            }
+        }
+        if ! errors.is_empty() {
+            log::error!("Likely errors of '{}':\n{}", pkgid, errors.join("\n"))
+        }
+        log::error!("Failure of '{}' classified as {:?}", pkgid, kind);
+        Self {
+            kind,
+            log: log.map(|log| log.to_path_buf()),
+            errors,
+            tail,
+        }
+    }
+
+    /// Packages built but bad, or failing lint, the log says nothing of it
+    pub(crate) fn bad_packages(log: &Path, packages: &[String]) -> Self {
+        Self {
+            kind: FailureKind::Packaging,
+            log: Some(log.to_path_buf()),
+            errors: packages.iter().map(
+                |package| format!("Bad package '{}'", package)).collect(),
+            tail: vec![],
+        }
+    }
+
+    /// Packages built but failing to be read, linted, signed or moved into
+    /// the pkgdir, the error is all that's known
+    pub(crate) fn packaging(log: &Path, error: &Error) -> Self {
+        Self {
+            kind: FailureKind::Packaging,
+            log: Some(log.to_path_buf()),
+            errors: vec![error.to_string()],
+            tail: vec![],
+        }
+    }
+}
@@ -214,10 +214,12 @@ where
     r
 }
 
-pub(crate) fn create_layout() -> Result<()> {
+pub(crate) fn create_layout(pkgs: &Path) -> Result<()> {
     create_dirs_allow_existing(["build", "logs", "pkgs", "sources"])?;
-    remove_dirs_allow_non_existing(["pkgs/updated", "pkgs/latest"])?;
-    create_dirs_under_allow_existing(["updated", "latest"], "pkgs")?;
+    create_dir_allow_existing(pkgs)?;
+    let links = [pkgs.join("updated"), pkgs.join("latest")];
+    remove_dirs_allow_non_existing(&links)?;
+    create_dirs_allow_existing(&links)?;
     create_dirs_under_allow_existing([
         "file-ck", "file-md5", "file-sha1", "file-sha224", "file-sha256",
         "file-sha384", "file-sha512", "file-b2", "git", "PKGBUILD"], 
@@ -0,0 +1,209 @@
+// Browsable index of the latest packages, written into pkgs/latest
+use std::{
+        fs::File,
+        io::Write,
+        path::Path,
+    };
+
+use serde::Serialize;
+
+use crate::{
+        changelog::{
+            self,
+            Changelog,
+        },
+        error::Result,
+    };
+
+pub(crate) const INDEXJSON: &str = "packages.json";
+pub(crate) const INDEXHTML: &str = "index.html";
+
+#[derive(Serialize)]
+pub(crate) struct IndexEntry {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) arch: String,
+    /// File name of the archive
+    pub(crate) file: String,
+    /// Size of the archive in bytes
+    pub(crate) size: u64,
+    /// Installed size in bytes
+    pub(crate) installed_size: u64,
+    pub(crate) builddate: i64,
+    pub(crate) signed: bool,
+    pub(crate) pkgid: String,
+    pub(crate) pkgbase: String,
+    /// Where the PKGBUILD comes from, and its commit
+    pub(crate) url: String,
+    pub(crate) commit: String,
+    /// The last build log, relative to the work dir
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) log: Option<String>,
+    /// Changes of the PKGBUILD since its last build
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) changelog: Option<Changelog>,
+}
+
+/// The latest build log of the pkgid among the names of files under logs
+pub(crate) fn find_log(logs: &[String], pkgid: &str) -> Option<String> {
+    let suffix = format!("_build_{}.log", pkgid);
+    logs.iter().filter(|log| log.ends_with(&suffix)).max().map(
+        |log| format!("logs/{}", log))
+}
+
+/// A relative link to the path, percent-encoded so e.g. ':' of epochs is not
+/// taken as a scheme, which needs no HTML escaping then
+fn href(path: &str) -> String {
+    let mut href = String::from("./");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' |
+            b'-' | b'.' | b'_' | b'~' | b'/' => href.push(byte as char),
+            _ => href.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    href
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size)
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn format_date(timestamp: i64) -> String {
+    match time::OffsetDateTime::from_unix_timestamp(timestamp) {
+        Ok(date) => format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            date.year(), date.month() as u8, date.day(), date.hour(),
+            date.minute(), date.second()),
+        Err(_) => timestamp.to_string(),
+    }
+}
+
+/// Summary linking to the changelog file, expanding to the commits
+fn changelog_to_html(changelog: &Changelog) -> String {
+    let mut html = format!("<details><summary><a href=\"{}\">{}</a>\
+        </summary>", escape_html(&changelog::file_name(&changelog.pkgbase)),
+        escape_html(&changelog.describe()));
+    if ! changelog.changes.is_empty() {
+        html.push_str("<ul>");
+        for change in changelog.changes.iter() {
+            html.push_str(&format!("<li><code>{}</code> {} ({}, {})</li>",
+                escape_html(change.id.get(..8).unwrap_or(&change.id)),
+                escape_html(&change.summary), escape_html(&change.author),
+                format_date(change.time)))
+        }
+        html.push_str("</ul>")
+    }
+    html.push_str("</details>");
+    html
+}
+
+/// The page, links to logs go up from the dir the index is in, by depth
+fn to_html(entries: &[IndexEntry], depth: usize) -> String {
+    let up = "../".repeat(depth);
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n\
+        <meta charset=\"utf-8\">\n<title>Packages</title>\n<style>\n\
+        body { font-family: sans-serif; }\n\
+        table { border-collapse: collapse; }\n\
+        th, td { border: 1px solid #ccc; padding: 2px 6px; \
+        text-align: left; }\n\
+        </style>\n</head>\n<body>\n");
+    html.push_str(&format!("<h1>Packages</h1>\n<p>{} packages, \
+        <a href=\"{}\">{}</a></p>\n", entries.len(), INDEXJSON, INDEXJSON));
+    html.push_str("<table>\n<tr><th>Name</th><th>Version</th><th>Arch</th>\
+        <th>Size</th><th>Build date</th><th>Signed</th><th>Pkgid</th>\
+        <th>PKGBUILD</th><th>Log</th><th>Changes</th></tr>\n");
+    for entry in entries.iter() {
+        let file = href(&entry.file);
+        html.push_str(&format!("<tr><td><a href=\"{}\">{}</a></td>\
+            <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+            <td>{}</td><td>{} @ {}</td><td>{}</td><td>{}</td></tr>\n",
+            file, escape_html(&entry.name), escape_html(&entry.version),
+            escape_html(&entry.arch), human_size(entry.size),
+            format_date(entry.builddate),
+            match entry.signed {
+                true => format!("<a href=\"{}.sig\">yes</a>", file),
+                false => String::from("no"),
+            },
+            escape_html(&entry.pkgid), escape_html(&entry.url),
+            escape_html(&entry.commit),
+            match &entry.log {
+                Some(log) => format!("<a href=\"{}{}\">build</a>",
+                    up, href(log).trim_start_matches("./")),
+                None => String::new(),
+            },
+            match &entry.changelog {
+                Some(changelog) => changelog_to_html(changelog),
+                None => String::new(),
+            }))
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(".temp");
+    let mut file = match File::create(&temp_name) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to create index '{}': {}", path.display(), e);
+            return Err(e.into())
+        },
+    };
+    if let Err(e) = file.write_all(content) {
+        log::error!("Failed to write index '{}': {}", path.display(), e);
+        return Err(e.into())
+    }
+    if let Err(e) = std::fs::rename(&temp_name, path) {
+        log::error!("Failed to move index into '{}': {}", path.display(), e);
+        return Err(e.into())
+    }
+    Ok(())
+}
+
+/// Write both packages.json and index.html into the dir, sorted by name
+pub(crate) fn write_index<P: AsRef<Path>>(dir: P, mut entries: Vec<IndexEntry>)
+    -> Result<()>
+{
+    let dir = dir.as_ref();
+    entries.sort_unstable_by(|some, other|
+        some.name.cmp(&other.name).then(some.file.cmp(&other.file)));
+    let json = match serde_json::to_vec_pretty(&entries) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Failed to serialize package index: {}", e);
+            return Err(std::io::Error::other(e).into())
+        },
+    };
+    write_file(&dir.join(INDEXJSON), &json)?;
+    let depth = dir.components().count();
+    write_file(&dir.join(INDEXHTML), to_html(&entries, depth).as_bytes())?;
+    log::info!("Wrote index of {} packages into '{}'",
+        entries.len(), dir.display());
+    Ok(())
+}
@@ -0,0 +1,243 @@
+// Lint rules run over built packages before they're moved into the pkgdir
+use std::{
+        collections::HashSet,
+        path::Path,
+    };
+
+use serde::Serialize;
+
+use crate::{
+        arch::Target,
+        config::{
+            Lint,
+            LintLevel,
+        },
+        depend::{
+            DbHandle,
+            Offer,
+        },
+        error::Result,
+        package::{
+            EntryType,
+            Package,
+        },
+        soname,
+    };
+
+/// Paths listed in a finding, the rest are only counted
+const LISTED_MAX: usize = 5;
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Rule {
+    UsrLocal,
+    BuildPaths,
+    WorldWritable,
+    License,
+    Linking,
+    Arch,
+}
+
+impl Rule {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::UsrLocal => "usr_local",
+            Self::BuildPaths => "build_paths",
+            Self::WorldWritable => "world_writable",
+            Self::License => "license",
+            Self::Linking => "linking",
+            Self::Arch => "arch",
+        }
+    }
+
+    fn level(&self, lint: &Lint) -> LintLevel {
+        match self {
+            Self::UsrLocal => lint.usr_local,
+            Self::BuildPaths => lint.build_paths,
+            Self::WorldWritable => lint.world_writable,
+            Self::License => lint.license,
+            Self::Linking => lint.linking,
+            Self::Arch => lint.arch,
+        }.unwrap_or(LintLevel::Warn)
+    }
+}
+
+/// Something a rule found in the packages of a build
+#[derive(Serialize)]
+pub(crate) struct Finding {
+    pub(crate) rule: Rule,
+    pub(crate) level: LintLevel,
+    /// File name of the package archive, or the pkgbase for the PKGBUILD
+    /// itself
+    pub(crate) package: String,
+    pub(crate) message: String,
+}
+
+/// Whether any finding should fail the build
+pub(crate) fn fails(findings: &[Finding]) -> bool {
+    findings.iter().any(|finding| finding.level == LintLevel::Error)
+}
+
+/// The first few paths, and how many more there are
+fn listing(paths: &[&str]) -> String {
+    let mut listing = paths.iter().take(LISTED_MAX).map(
+        |path| format!("'/{}'", path)).collect::<Vec<String>>().join(", ");
+    if paths.len() > LISTED_MAX {
+        listing.push_str(&format!(" and {} more", paths.len() - LISTED_MAX))
+    }
+    listing
+}
+
+/// Whether the package ships a file named as the soname
+fn ships(package: &Package, soname: &str) -> bool {
+    package.entries.iter().any(|entry| ! matches!(
+        entry.entry_type, EntryType::Dir) &&
+        Path::new(&entry.path).file_name().is_some_and(
+            |name| name == soname))
+}
+
+struct Linter<'a> {
+    lint: &'a Lint,
+    pkgid: &'a str,
+    findings: Vec<Finding>,
+}
+
+impl<'a> Linter<'a> {
+    fn enabled(&self, rule: Rule) -> bool {
+        rule.level(self.lint) != LintLevel::Off
+    }
+
+    fn report(&mut self, rule: Rule, package: &str, message: String) {
+        let level = rule.level(self.lint);
+        match level {
+            LintLevel::Off => return,
+            LintLevel::Warn => log::warn!("Lint '{}' on '{}' built for '{}': \
+                {}", rule.name(), package, self.pkgid, message),
+            LintLevel::Error => log::error!("Lint '{}' on '{}' built for '{}': \
+                {}", rule.name(), package, self.pkgid, message),
+        }
+        self.findings.push(Finding {
+            rule,
+            level,
+            package: package.to_string(),
+            message,
+        })
+    }
+
+    fn usr_local(&mut self, package: &Package) {
+        let paths: Vec<&str> = package.entries.iter().filter_map(|entry|
+            (entry.path == "usr/local" || entry.path.starts_with("usr/local/"))
+                .then_some(entry.path.as_str())).collect();
+        if ! paths.is_empty() {
+            self.report(Rule::UsrLocal, &package.file,
+                format!("installs into /usr/local: {}", listing(&paths)))
+        }
+    }
+
+    fn build_paths(&mut self, package: &Package) {
+        let paths: Vec<&str> = package.references.iter().map(
+            |path| path.as_str()).collect();
+        if ! paths.is_empty() {
+            self.report(Rule::BuildPaths, &package.file,
+                format!("$srcdir or $pkgdir left in: {}", listing(&paths)))
+        }
+    }
+
+    fn world_writable(&mut self, package: &Package) {
+        let paths: Vec<&str> = package.entries.iter().filter_map(|entry| {
+            let mode = u32::from_str_radix(&entry.mode, 8).ok()?;
+            let writable = mode & 0o002 != 0 && match entry.entry_type {
+                EntryType::Link => false,
+                // Sticky dirs like /tmp are meant to be
+                EntryType::Dir => mode & 0o1000 == 0,
+                _ => true,
+            };
+            writable.then_some(entry.path.as_str())
+        }).collect();
+        if ! paths.is_empty() {
+            self.report(Rule::WorldWritable, &package.file,
+                format!("world-writable: {}", listing(&paths)))
+        }
+    }
+
+    fn license(&mut self, package: &Package) {
+        if package.pkginfo.license.is_empty() {
+            self.report(Rule::License, &package.file,
+                "no license declared".into())
+        }
+    }
+
+    fn arch(&mut self, package: &Package, target: &Target) {
+        let arch = &package.pkginfo.arch;
+        if arch != "any" && arch != target.name() {
+            self.report(Rule::Arch, &package.file, format!("built for '{}' \
+                but the target is '{}'", arch, target.name()))
+        }
+    }
+
+    /// Sonames could come from the package itself, siblings it depends on, or
+    /// what its depends pull into the build root, with their own depends
+    fn linking(&mut self, package: &Package, packages: &[Package],
+        db_handle: &DbHandle
+    ) {
+        let missing: Vec<&str> = package.sonames.iter().filter(
+            |soname| ! ships(package, soname)).map(
+            |soname| soname.as_str()).collect();
+        if missing.is_empty() {
+            return
+        }
+        let siblings: Vec<&Package> = packages.iter().filter(|sibling| {
+            let offer = Offer {
+                names: std::slice::from_ref(&sibling.pkginfo.pkgname),
+                version: &sibling.pkginfo.pkgver,
+                provides: &sibling.pkginfo.provides,
+            };
+            package.pkginfo.depend.iter().any(|dep| offer.satisfies(dep))
+        }).collect();
+        let (files, provides) =
+            db_handle.installed_closure(&package.pkginfo.depend);
+        let names: HashSet<&str> = files.iter().filter_map(|file|
+            Path::new(file).file_name()?.to_str()).collect();
+        let unprovided: Vec<&str> = missing.into_iter().filter(|soname|
+            ! names.contains(soname) &&
+            ! siblings.iter().any(|sibling| ships(sibling, soname)) &&
+            ! package.pkginfo.provides.iter().chain(provides.iter()).any(
+                |provide| soname::provides_soname(provide, soname))
+        ).collect();
+        if ! unprovided.is_empty() {
+            self.report(Rule::Linking, &package.file, format!("links to {:?} \
+                which no declared depend provides", unprovided))
+        }
+    }
+}
+
+/// Run all enabled rules over the packages of a build, the root is the one
+/// the build ran in, with the depends installed
+pub(crate) fn lint(packages: &[Package], lint: &Lint, base: &str,
+    pkgid: &str, arches: &[String], target: &Target, root: &Path
+) -> Result<Vec<Finding>>
+{
+    let mut linter = Linter { lint, pkgid, findings: vec![] };
+    // Only with --ignorearch could makepkg build for an arch not in arch=()
+    if target.arch.is_none() && ! arches.is_empty() &&
+        ! arches.iter().any(|arch| arch == "any" || arch == target.name())
+    {
+        linter.report(Rule::Arch, base, format!("arch={:?} does not have the \
+            target '{}'", arches, target.name()))
+    }
+    let db_handle = match linter.enabled(Rule::Linking) {
+        true => Some(DbHandle::new(root, target.pacman_conf())?),
+        false => None,
+    };
+    for package in packages.iter() {
+        linter.usr_local(package);
+        linter.build_paths(package);
+        linter.world_writable(package);
+        linter.license(package);
+        linter.arch(package, target);
+        if let Some(db_handle) = &db_handle {
+            linter.linking(package, packages, db_handle)
+        }
+    }
+    Ok(linter.findings)
+}
@@ -0,0 +1,144 @@
+// Lockfile recording the exact inputs of every PKGBUILD, so a build could be
+// reproduced later with --locked
+use std::{
+        collections::BTreeMap,
+        fs::File,
+        path::Path,
+    };
+
+use serde::{
+        Deserialize,
+        Serialize,
+    };
+
+use crate::error::{
+        Error,
+        Result
+    };
+
+pub(crate) const LOCKFILE: &str = "arb.lock";
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct LockedNetfile {
+    pub(crate) name: String,
+    pub(crate) url: String,
+    pub(crate) cksums: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct LockedPkgbuild {
+    pub(crate) url: String,
+    /// branch:[name], tag:[name] or commit:[id], as configured
+    pub(crate) reference: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) subtree: Option<String>,
+    /// The commit the PKGBUILD was taken from
+    pub(crate) commit: String,
+    /// The commit or subtree id used in pkgid
+    pub(crate) tree: String,
+    pub(crate) pkgid: String,
+    #[serde(default)]
+    pub(crate) deps: Vec<String>,
+    #[serde(default)]
+    pub(crate) makedeps: Vec<String>,
+    #[serde(default)]
+    pub(crate) dephash: String,
+    #[serde(default)]
+    pub(crate) netfiles: Vec<LockedNetfile>,
+    #[serde(default)]
+    pub(crate) gits: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct Lock {
+    #[serde(default)]
+    pub(crate) pkgbuilds: BTreeMap<String, LockedPkgbuild>,
+    /// Git sources, url => (ref => commit), HEAD => symbolic target
+    #[serde(default)]
+    pub(crate) gits: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Lock {
+    pub(crate) fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open lockfile '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        match serde_yaml::from_reader(file) {
+            Ok(lock) => Ok(lock),
+            Err(e) => {
+                log::error!("Failed to parse lockfile '{}': {}",
+                    path.as_ref().display(), e);
+                Err(Error::InvalidConfig)
+            },
+        }
+    }
+
+    /// Read the existing lockfile if there's one, otherwise an empty lock
+    pub(crate) fn from_file_or_default<P: AsRef<Path>>(path: P) -> Self {
+        if ! path.as_ref().exists() {
+            return Self::default()
+        }
+        match Self::from_file(&path) {
+            Ok(lock) => lock,
+            Err(_) => {
+                log::warn!("Existing lockfile '{}' unusable, would overwrite it",
+                    path.as_ref().display());
+                Self::default()
+            },
+        }
+    }
+
+    pub(crate) fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut temp_name = path.as_ref().as_os_str().to_owned();
+        temp_name.push(".temp");
+        let file = match File::create(&temp_name) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to create lockfile '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = serde_yaml::to_writer(file, self) {
+            log::error!("Failed to write lockfile '{}': {}",
+                path.as_ref().display(), e);
+            return Err(std::io::Error::other(e).into())
+        }
+        if let Err(e) = std::fs::rename(&temp_name, &path) {
+            log::error!("Failed to move lockfile into '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        }
+        log::info!("Wrote lockfile '{}'", path.as_ref().display());
+        Ok(())
+    }
+
+    pub(crate) fn get_pkgbuild(&self, name: &str) -> Result<&LockedPkgbuild> {
+        match self.pkgbuilds.get(name) {
+            Some(locked) => Ok(locked),
+            None => {
+                log::error!("PKGBUILD '{}' not recorded in lockfile, run \
+                    without --locked to update the lockfile", name);
+                Err(Error::InvalidConfig)
+            },
+        }
+    }
+
+    pub(crate) fn get_git(&self, url: &str)
+        -> Result<&BTreeMap<String, String>>
+    {
+        match self.gits.get(url) {
+            Some(refs) => Ok(refs),
+            None => {
+                log::error!("Git source '{}' not recorded in lockfile, run \
+                    without --locked to update the lockfile", url);
+                Err(Error::InvalidConfig)
+            },
+        }
+    }
+}
@@ -14,9 +14,13 @@ use crate::error::{
         Result,
     };
 
+#[derive(Clone, Copy)]
 pub(crate) enum LogType {
     Build,
+    Check,
     Extract,
+    /// Everything logged about a package, aggregated by the logger
+    Package,
     Pacman,
 }
 
@@ -24,7 +28,9 @@ impl Display for LogType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", match self {
             Self::Build => "build",
+            Self::Check => "check",
             Self::Extract => "extract",
+            Self::Package => "package",
             Self::Pacman => "pacman"
         })
     }
@@ -35,22 +41,34 @@ pub(crate) struct LogFile {
     pub(crate) file: File
 }
 
+const DATE_TIME_FORMAT: &[time::format_description::FormatItem<'_>] =
+    time::macros::format_description!(
+        "[year][month][day]_[hour][minute][second]");
+
+/// The time part of log names, for now
+pub(crate) fn time_formatted() -> std::result::Result<String, time::Error> {
+    Ok(time::OffsetDateTime::now_utc().format(DATE_TIME_FORMAT)?)
+}
+
+/// logs/[time]_[type]_[id].log
+pub(crate) fn path<S: AsRef<str>>(
+    time_formatted: &str, log_type: LogType, id: S
+) -> PathBuf
+{
+    PathBuf::from(format!("logs/{}_{}_{}.log",
+        time_formatted, log_type, id.as_ref()))
+}
+
 impl LogFile {
     pub(crate) fn new<S: AsRef<str>>(log_type: LogType, id: S) -> Result<Self> {
-        const DATE_TIME_FORMAT: &[time::format_description::FormatItem<'_>] = 
-            time::macros::format_description!(
-                "[year][month][day]_[hour][minute][second]");
-        let time_formatted = match 
-            time::OffsetDateTime::now_utc().format(DATE_TIME_FORMAT) 
-        {
+        let time_formatted = match time_formatted() {
             Ok(time_formatted) => time_formatted,
             Err(e) => {
                 log::error!("Failed to format time: {}", e);
-                return Err(Into::<time::Error>::into(e).into())
+                return Err(e.into())
             },
         };
-        let path = PathBuf::from(format!("logs/{}_{}_{}.log", 
-            time_formatted, log_type, id.as_ref()));
+        let path = path(&time_formatted, log_type, id.as_ref());
         let file = File::create(&path).map_err(Error::from)?;
         log::info!("Log for {} '{}' is stored at '{}'", log_type, id.as_ref(), 
                     path.display());
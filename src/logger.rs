@@ -0,0 +1,228 @@
+// Logging to stdout as text or JSON lines, with the package, phase and domain
+// the logging thread is working on, and everything logged about a package
+// that's built also aggregated into its own log under logs/
+use std::{
+        cell::RefCell,
+        collections::{
+            BTreeMap,
+            BTreeSet,
+        },
+        fs::OpenOptions,
+        io::Write,
+        sync::{
+            atomic::{
+                AtomicBool,
+                Ordering,
+            },
+            Mutex,
+        },
+    };
+
+use serde::Serialize;
+
+use time::format_description::well_known::Rfc3339;
+
+use crate::{
+        config::LogFormat,
+        logfile::{
+            self,
+            LogType,
+        },
+    };
+
+/// What the thread is working on, set through scopes
+#[derive(Clone, Default)]
+struct Context {
+    pkgbase: Option<String>,
+    phase: Option<&'static str>,
+    domain: Option<String>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+/// Per-package logs are only written once the logs dir exists
+static PACKAGE_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// Lines kept for a package before it's known whether it would be built
+const PENDING_MAX: usize = 1000;
+
+struct Packages {
+    /// The time part of per-package log names, the same for the whole run
+    run: String,
+    /// Those entering the builder, their lines go into their logs
+    building: BTreeSet<String>,
+    /// Lines of the others, written out if they enter the builder later
+    pending: BTreeMap<String, Vec<String>>,
+}
+
+static PACKAGES: Mutex<Packages> = Mutex::new(Packages {
+    run: String::new(),
+    building: BTreeSet::new(),
+    pending: BTreeMap::new(),
+});
+
+/// Restores the context of the thread when dropped
+pub(crate) struct Scope {
+    previous: Context,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = std::mem::take(&mut self.previous);
+        CONTEXT.with(|context| *context.borrow_mut() = previous)
+    }
+}
+
+fn scope<F: FnOnce(&mut Context)>(update: F) -> Scope {
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        let previous = context.clone();
+        update(&mut context);
+        Scope { previous }
+    })
+}
+
+/// Lines logged by this thread until the scope drops are about the package
+/// in the phase
+pub(crate) fn package(pkgbase: &str, phase: &'static str) -> Scope {
+    scope(|context| {
+        context.pkgbase = Some(pkgbase.to_string());
+        context.phase = Some(phase);
+    })
+}
+
+/// Lines logged by this thread until the scope drops are about the domain
+pub(crate) fn domain(domain: &str) -> Scope {
+    scope(|context| context.domain = Some(domain.to_string()))
+}
+
+/// Start aggregating lines about packages into logs/[time]_package_[pkgbase].log
+pub(crate) fn enable_package_logs() {
+    PACKAGE_LOGS.store(true, Ordering::Relaxed)
+}
+
+/// Opened, appended to and closed for each line, as there could be thousands
+/// of packages. Nothing could be logged from here, failures are silently
+/// ignored
+fn append_package(run: &str, pkgbase: &str, lines: &[String]) {
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(
+        logfile::path(run, LogType::Package, pkgbase))
+    {
+        for line in lines {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// The package enters the builder, lines about it, including those before,
+/// go into its own log from now on
+pub(crate) fn aggregate(pkgbase: &str) {
+    if let Ok(mut packages) = PACKAGES.lock() {
+        packages.building.insert(pkgbase.to_string());
+        if let Some(lines) = packages.pending.remove(pkgbase) {
+            append_package(&packages.run, pkgbase, &lines)
+        }
+    }
+}
+
+fn log_package(pkgbase: &str, line: String) {
+    let mut packages = match PACKAGES.lock() {
+        Ok(packages) => packages,
+        Err(_) => return,
+    };
+    if packages.building.contains(pkgbase) {
+        append_package(&packages.run, pkgbase, &[line])
+    } else {
+        let lines = packages.pending.entry(pkgbase.to_string()).or_default();
+        if lines.len() < PENDING_MAX {
+            lines.push(line)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    time: &'a str,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pkgbase: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phase: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: Option<&'a str>,
+}
+
+struct Logger {
+    /// Filters by ARB_LOG_LEVEL, and writes the text format
+    inner: env_logger::Logger,
+    format: LogFormat,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if ! self.inner.matches(record) {
+            return
+        }
+        let context = CONTEXT.with(|context| context.borrow().clone());
+        let time = time::OffsetDateTime::now_utc().format(&Rfc3339)
+            .unwrap_or_default();
+        match self.format {
+            LogFormat::Text => self.inner.log(record),
+            LogFormat::Json => {
+                let line = JsonLine {
+                    time: &time,
+                    level: record.level().as_str(),
+                    target: record.target(),
+                    message: record.args().to_string(),
+                    pkgbase: context.pkgbase.as_deref(),
+                    phase: context.phase,
+                    domain: context.domain.as_deref(),
+                };
+                if let Ok(mut line) = serde_json::to_string(&line) {
+                    line.push('\n');
+                    let _ = std::io::stdout().lock().write_all(line.as_bytes());
+                }
+            },
+        }
+        if ! PACKAGE_LOGS.load(Ordering::Relaxed) {
+            return
+        }
+        if let Some(pkgbase) = &context.pkgbase {
+            let mut line = format!("[{} {:5} {}", time, record.level(),
+                context.phase.unwrap_or("-"));
+            if let Some(domain) = &context.domain {
+                line.push(' ');
+                line.push_str(domain)
+            }
+            line.push_str(&format!("] {}\n", record.args()));
+            log_package(pkgbase, line)
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+pub(crate) fn setup(format: LogFormat) {
+    let inner = env_logger::Builder::from_env(
+        env_logger::Env::default().filter_or(
+            "ARB_LOG_LEVEL", "info")
+        ).target(env_logger::Target::Stdout).build();
+    log::set_max_level(inner.filter());
+    if let Ok(mut packages) = PACKAGES.lock() {
+        packages.run = logfile::time_formatted().unwrap_or_default()
+    }
+    let logger = Logger { inner, format };
+    if let Err(e) = log::set_boxed_logger(Box::new(logger)) {
+        eprintln!("Failed to set logger: {}", e)
+    }
+}
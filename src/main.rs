@@ -1,14 +1,29 @@
+mod arch;
 mod build;
+mod ccache;
+mod changelog;
 mod child;
 mod config;
 mod depend;
+mod distcc;
 mod error;
+mod failure;
 mod filesystem;
 mod logfile;
+mod logger;
 mod identity;
+mod index;
+mod lint;
+mod lock;
+mod metrics;
+mod package;
 mod pkgbuild;
+mod publish;
+mod report;
 mod root;
+mod scheduler;
 mod sign;
+mod soname;
 mod source;
 mod threading;
 
@@ -21,41 +36,54 @@ struct Settings {
     proxy: Option<source::Proxy>,
     holdpkg: bool,
     holdgit: bool,
+    locked: bool,
+    partial: bool,
     skipint: bool,
     nobuild: bool,
     noclean: bool,
     nonet: bool,
+    nopublish: bool,
     gmr: Option<String>,
     dephash_strategy: config::DepHashStrategy,
     sign: Option<String>,
     home_binds: Vec<String>,
+    makepkg: pkgbuild::Makepkg,
+    target: arch::Target,
+    ccache: Option<config::Ccache>,
+    distcc: Option<config::Distcc>,
+    scheduler: scheduler::Scheduler,
+    lint: Option<config::Lint>,
+    metrics: Option<String>,
+    publish: Option<config::Publish>,
     terminal: bool
 }
 
-fn log_setup() {
-    env_logger::Builder::from_env(
-        env_logger::Env::default().filter_or(
-            "ARB_LOG_LEVEL", "info")
-        ).target(env_logger::Target::Stdout).init();
+fn check_config(arg: &config::Arg, config: Option<&str>)
+    -> Result<(), &'static str>
+{
+    let actual_identity =
+    identity::IdentityActual::new_and_drop(arg.drop.as_deref())
+        .or(Err("Failed to get actual identity"))?;
+    let config = config.unwrap_or(&arg.config);
+    let problems = config::check_file(config, &actual_identity)
+        .or(Err("Failed to read config"))?;
+    if problems.is_empty() {
+        log::info!("Config '{}' is valid", config);
+        return Ok(())
+    }
+    for problem in problems.iter() {
+        println!("{}", problem)
+    }
+    log::error!("Config '{}' has {} problems", config, problems.len());
+    Err("Invalid config")
 }
 
-fn prepare() -> Result<Settings, &'static str> {
-    log_setup();
-    let arg: config::Arg = clap::Parser::parse();
+fn prepare(arg: config::Arg) -> Result<Settings, &'static str> {
     let actual_identity =
     identity::IdentityActual::new_and_drop(arg.drop.as_deref())
         .or_else(|_|Err("Failed to get actual identity"))?;
-    let mut config: config::Config = serde_yaml::from_reader(
-        std::fs::File::open(&arg.config).or_else(
-        |e|{
-            log::error!("Failed to open config file '{}': {}", arg.config, e);
-            Err("Failed to open config file")
-        })?)
-    .or_else(
-    |e|{
-        log::error!("Failed to parse YAML: {}", e);
-        Err("Failed to parse YAML config")
-    })?;
+    let mut config = config::Config::from_file(&arg.config, &actual_identity)
+        .or(Err("Failed to read config"))?;
     if ! arg.build.is_empty() {
         log::warn!("Only build the following packages: {:?}", arg.build);
         config.pkgbuilds.retain(|name, _|arg.build.contains(name));
@@ -69,6 +97,21 @@ fn prepare() -> Result<Settings, &'static str> {
                 None => 0,
             },
         });
+    let makepkg = pkgbuild::Makepkg::from_config(&config.env,
+        &config.makeflags, &config.cflags, &config.cxxflags, &config.options,
+        &config.buildenv, &config.makepkg_conf).or(Err("Failed to read makepkg overrides"))?;
+    let scheduler = scheduler::Scheduler::from_config(
+        config.scheduler.as_ref()).or(Err("Failed to prepare scheduler"))?;
+    let target = arch::Target::new(arg.arch.as_deref().or(config.arch.as_deref()),
+        config.pacman_conf.as_deref()).or(Err("Invalid target arch"))?;
+    for (enabled, pkg) in [
+        (config.ccache.is_some(), "ccache"),
+        (config.distcc.is_some(), "distcc")]
+    {
+        if enabled && ! config.basepkgs.iter().any(|basepkg| basepkg == pkg) {
+            config.basepkgs.push(String::from(pkg))
+        }
+    }
     Ok(Settings {
         actual_identity,
         pkgbuilds_config: config.pkgbuilds,
@@ -76,48 +119,179 @@ fn prepare() -> Result<Settings, &'static str> {
         proxy,
         holdpkg: arg.holdpkg || config.holdpkg,
         holdgit: arg.holdgit || config.holdgit,
+        locked: arg.locked,
+        partial: !arg.build.is_empty(),
         skipint: arg.skipint || config.skipint,
         nobuild: arg.nobuild || config.nobuild,
         noclean: !arg.build.is_empty() || arg.noclean || config.noclean,
         nonet: arg.nonet || config.nonet,
+        nopublish: arg.nopublish || config.nopublish,
         gmr: arg.gmr.or(config.gmr),
         dephash_strategy: config.dephash_strategy,
         sign: arg.sign.or(config.sign),
         home_binds: config.home_binds,
+        makepkg,
+        target,
+        ccache: config.ccache,
+        distcc: config.distcc,
+        scheduler,
+        lint: config.lint,
+        metrics: config.metrics,
+        publish: config.publish,
         terminal: is_terminal::is_terminal(std::io::stdout())
     })
 }
 
 fn work(settings: Settings) -> Result<(), &'static str> {
+    let mut metrics = settings.metrics.as_ref().map(metrics::init);
     let gmr = settings.gmr.and_then(|gmr|
         Some(crate::source::git::Gmr::init(gmr.as_str())));
-    filesystem::create_layout().or(Err("Failed to create layout"))?;
+    filesystem::create_layout(&settings.target.pkgs_dir())
+        .or(Err("Failed to create layout"))?;
+    logger::enable_package_logs();
+    let lock = match settings.locked {
+        true => Some(lock::Lock::from_file(lock::LOCKFILE)
+                    .or(Err("Failed to read lockfile"))?),
+        false => None,
+    };
     let mut pkgbuilds =
         pkgbuild::PKGBUILDs::from_config_healthy(
             &settings.pkgbuilds_config, settings.holdpkg,
             settings.noclean, settings.proxy.as_ref(),
-            gmr.as_ref(), &settings.home_binds, settings.terminal
+            gmr.as_ref(), &settings.home_binds, &settings.makepkg,
+            &settings.target, &settings.scheduler, lock.as_ref(),
+            settings.terminal
         ).or_else(|_|Err("Failed to prepare PKGBUILDs list"))?;
     let root = pkgbuilds.prepare_sources(
         &settings.actual_identity, &settings.basepkgs, settings.holdgit,
         settings.skipint, settings.noclean, settings.proxy.as_ref(),
-        gmr.as_ref(), &settings.dephash_strategy, settings.terminal
+        gmr.as_ref(), &settings.dephash_strategy, &settings.target,
+        &settings.scheduler, lock.as_ref(), settings.terminal
         ).or_else(|_|Err("Failed to prepare sources"))?;
+    if lock.is_none() {
+        // Only building some of the PKGBUILDs, keep the others locked
+        let mut lock = match settings.partial {
+            true => lock::Lock::from_file_or_default(lock::LOCKFILE),
+            false => lock::Lock::default(),
+        };
+        pkgbuilds.update_lock(&mut lock)
+            .and_then(|_|lock.to_file(lock::LOCKFILE))
+            .or(Err("Failed to write lockfile"))?;
+    }
+    let ccache = match (&settings.ccache, &root) {
+        (Some(config), Some(root)) => Some(ccache::Ccache::new(config,
+            root::CommonRoot::path(root), &settings.target,
+            &settings.actual_identity, settings.noclean
+        ).or(Err("Failed to prepare compiler cache"))?),
+        _ => None,
+    };
+    let distcc = match (&settings.distcc, &root) {
+        (Some(_), Some(_)) if settings.nonet => {
+            log::warn!("Network is disabled for builds, not using distcc");
+            None
+        },
+        (Some(config), Some(_)) => distcc::Distcc::new(config)
+            .or(Err("Failed to prepare distcc hosts"))?,
+        _ => None,
+    };
+    let mut report = report::Report::default();
     let r = build::maybe_build(&pkgbuilds,
         root, &settings.actual_identity, settings.nobuild, settings.nonet,
-         settings.sign.as_deref());
+         settings.sign.as_deref(), &settings.target, ccache.as_ref(),
+         distcc.as_ref(), &settings.scheduler, &settings.dephash_strategy,
+         settings.lint.as_ref(), &mut report);
+    if let Some(ccache) = &ccache {
+        report.ccache = Some(ccache.to_report())
+    }
+    if ! report.packages.is_empty() {
+        let _ = report.to_file(report::REPORTFILE);
+    }
     let _ = std::fs::remove_dir("build");
-    pkgbuilds.link_pkgs();
+    pkgbuilds.link_pkgs(&settings.target);
     if ! settings.noclean {
-        pkgbuilds.clean_pkgdir();
+        pkgbuilds.clean_pkgdir(&settings.target);
     }
     if r.is_err() {
-        Err("Failed to build")
-    } else {
-        Ok(())
+        return Err("Failed to build")
+    }
+    if let Some(publish) = &settings.publish {
+        if settings.nopublish {
+            log::info!("Publishing is disabled, not publishing")
+        } else if settings.nobuild {
+            // pkgs/latest lacks those not built, they would be deleted
+            log::warn!("Building is disabled, not publishing")
+        } else if settings.partial {
+            // pkgs/latest only has those built, the others would be deleted
+            log::warn!("Only built some of the PKGBUILDs, not publishing")
+        } else {
+            publish::publish(publish, &settings.target,
+                &settings.actual_identity, settings.sign.as_deref())
+                .or(Err("Failed to publish"))?
+        }
+    }
+    if let Some(metrics) = &mut metrics {
+        metrics.succeed()
     }
+    Ok(())
+}
+
+fn graph(settings: Settings, format: config::GraphFormat,
+    output: Option<String>
+) -> Result<(), &'static str>
+{
+    let gmr = settings.gmr.map(|gmr|
+        crate::source::git::Gmr::init(gmr.as_str()));
+    filesystem::create_layout(&settings.target.pkgs_dir())
+        .or(Err("Failed to create layout"))?;
+    let lock = match settings.locked {
+        true => Some(lock::Lock::from_file(lock::LOCKFILE)
+                    .or(Err("Failed to read lockfile"))?),
+        false => None,
+    };
+    let mut pkgbuilds =
+        pkgbuild::PKGBUILDs::from_config_healthy(
+            &settings.pkgbuilds_config, settings.holdpkg,
+            settings.noclean, settings.proxy.as_ref(),
+            gmr.as_ref(), &settings.home_binds, &settings.makepkg,
+            &settings.target, &settings.scheduler, lock.as_ref(),
+            settings.terminal
+        ).or(Err("Failed to prepare PKGBUILDs list"))?;
+    pkgbuilds.read_graph(&settings.actual_identity)
+        .or(Err("Failed to read PKGBUILDs"))?;
+    let graph = depend::DepGraph::from_pkgbuilds(&pkgbuilds)
+        .or(Err("Failed to resolve wants among PKGBUILDs"))?;
+    let cycles = graph.cycles();
+    let (content, default) = match format {
+        config::GraphFormat::Dot => (graph.to_dot(&cycles), "graph.dot"),
+        config::GraphFormat::Json => (graph.to_json(&cycles)
+            .or(Err("Failed to serialize graph"))?, "graph.json"),
+    };
+    let output = output.as_deref().unwrap_or(default);
+    if let Err(e) = std::fs::write(output, content) {
+        log::error!("Failed to write graph into '{}': {}", output, e);
+        return Err("Failed to write graph")
+    }
+    log::info!("Wrote dependency graph of {} PKGBUILDs into '{}'",
+        graph.nodes.len(), output);
+    if cycles.is_empty() {
+        return Ok(())
+    }
+    for cycle in cycles.iter() {
+        log::error!("Cycle: {}", graph.describe_cycle(cycle))
+    }
+    Err("PKGBUILDs want each other in cycles")
 }
 
 fn main() -> Result<(), &'static str> {
-    work(prepare()?)
+    let arg: config::Arg = clap::Parser::parse();
+    logger::setup(arg.log_format);
+    match &arg.command {
+        Some(config::Command::CheckConfig { config }) =>
+            check_config(&arg, config.as_deref()),
+        Some(config::Command::Graph { format, output }) => {
+            let (format, output) = (*format, output.clone());
+            graph(prepare(arg)?, format, output)
+        },
+        None => work(prepare(arg)?),
+    }
 }
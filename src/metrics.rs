@@ -0,0 +1,340 @@
+// Metrics of builder activity, written as a Prometheus textfile-collector file
+// (e.g. for node_exporter's --collector.textfile.directory). Like logs, they're
+// recorded from wherever they happen, sync threads included, into one registry
+use std::{
+        collections::BTreeMap,
+        fs::{
+            File,
+            symlink_metadata,
+        },
+        io::Write,
+        os::unix::fs::MetadataExt,
+        path::{
+            Path,
+            PathBuf,
+        },
+        sync::Mutex,
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+
+use crate::error::Result;
+
+/// Least time between writes while building
+const INTERVAL: Duration = Duration::from_secs(15);
+/// Dirs whose disk usage is reported, only at the end of a run as walking
+/// them is slow
+const DIRS: &[&str] = &["sources", "pkgs", "roots"];
+
+#[derive(Default)]
+struct Sum {
+    seconds: f64,
+    count: u64,
+}
+
+struct Metrics {
+    /// Where to write, nothing is written if unset
+    path: Option<PathBuf>,
+    last_write: Option<Instant>,
+    /// Builders by their states, from the last look
+    states: BTreeMap<&'static str, usize>,
+    jobs: usize,
+    /// Finished builds by status
+    builds: BTreeMap<&'static str, u64>,
+    /// Seconds of the successful try, by pkgbase
+    durations: BTreeMap<String, u64>,
+    /// Netfile sources already in cache or not, by domain
+    cache: BTreeMap<(String, &'static str), u64>,
+    /// Bytes of netfiles downloaded, by domain
+    downloaded: BTreeMap<String, u64>,
+    /// Git fetches, by domain
+    fetches: BTreeMap<String, Sum>,
+    /// some avg10, avg60 and avg300, as the scheduler saw it last time
+    pressure: Option<[f32; 3]>,
+    heavy_load: Option<bool>,
+    /// Bytes used on disk, by dir
+    disk: BTreeMap<&'static str, u64>,
+    /// Whether the run succeeded, and when it ended
+    last_run: Option<(bool, i64)>,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    path: None,
+    last_write: None,
+    states: BTreeMap::new(),
+    jobs: 0,
+    builds: BTreeMap::new(),
+    durations: BTreeMap::new(),
+    cache: BTreeMap::new(),
+    downloaded: BTreeMap::new(),
+    fetches: BTreeMap::new(),
+    pressure: None,
+    heavy_load: None,
+    disk: BTreeMap::new(),
+    last_run: None,
+});
+
+/// Run the closure on the registry, nothing is recorded if it's poisoned
+fn with<F: FnOnce(&mut Metrics)>(record: F) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        record(&mut metrics)
+    }
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Bytes used on disk by everything under the path, not crossing into other
+/// filesystems (e.g. overlay mounts under roots), unreadable entries skipped
+fn disk_usage(path: &Path, dev: u64) -> u64 {
+    let metadata = match symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if metadata.dev() != dev {
+        return 0
+    }
+    let mut usage = metadata.blocks() * 512;
+    if metadata.is_dir() {
+        if let Ok(readdir) = path.read_dir() {
+            for entry in readdir.flatten() {
+                usage += disk_usage(&entry.path(), dev)
+            }
+        }
+    }
+    usage
+}
+
+impl Metrics {
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut family = |name: &str, kind: &str, help: &str,
+            samples: Vec<(String, String)>|
+        {
+            if samples.is_empty() {
+                return
+            }
+            text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind));
+            for (labels, value) in samples {
+                text.push_str(&format!("{}{} {}\n", name, labels, value))
+            }
+        };
+        family("arb_builders", "gauge",
+            "Builders in each state of chroot and build",
+            self.states.iter().map(|(state, count)|
+                (format!("{{state=\"{}\"}}", state), count.to_string()))
+            .collect());
+        family("arb_jobs", "gauge",
+            "Jobs running, weighted as the scheduler counts them",
+            vec![(String::new(), self.jobs.to_string())]);
+        family("arb_builds_total", "counter",
+            "Finished builds by status",
+            self.builds.iter().map(|(status, count)|
+                (format!("{{status=\"{}\"}}", status), count.to_string()))
+            .collect());
+        family("arb_build_duration_seconds", "gauge",
+            "Duration of the successful try of the last build",
+            self.durations.iter().map(|(pkgbase, seconds)|
+                (format!("{{pkgbase=\"{}\"}}", escape(pkgbase)),
+                    seconds.to_string()))
+            .collect());
+        family("arb_source_cache_total", "counter",
+            "Netfile sources found in cache (hit) or to download (miss)",
+            self.cache.iter().map(|((domain, result), count)|
+                (format!("{{domain=\"{}\",result=\"{}\"}}", escape(domain),
+                    result), count.to_string()))
+            .collect());
+        family("arb_source_downloaded_bytes_total", "counter",
+            "Bytes of netfile sources downloaded",
+            self.downloaded.iter().map(|(domain, bytes)|
+                (format!("{{domain=\"{}\"}}", escape(domain)),
+                    bytes.to_string()))
+            .collect());
+        family("arb_git_fetch_seconds", "summary",
+            "Time spent fetching git repos",
+            self.fetches.iter().flat_map(|(domain, sum)| {
+                let labels = format!("{{domain=\"{}\"}}", escape(domain));
+                [(format!("_sum{}", labels), sum.seconds.to_string()),
+                    (format!("_count{}", labels), sum.count.to_string())]
+            }).collect());
+        family("arb_cpu_pressure_some_percent", "gauge",
+            "CPU pressure (some) as the scheduler saw it last time, percents",
+            match self.pressure {
+                Some(pressure) => ["avg10", "avg60", "avg300"].iter().zip(
+                    pressure).map(|(window, value)|
+                        (format!("{{window=\"{}\"}}", window),
+                            value.to_string())).collect(),
+                None => vec![],
+            });
+        family("arb_heavy_load", "gauge",
+            "Whether the scheduler held back new jobs last time it looked",
+            match self.heavy_load {
+                Some(heavy_load) =>
+                    vec![(String::new(), (heavy_load as u8).to_string())],
+                None => vec![],
+            });
+        family("arb_disk_usage_bytes", "gauge",
+            "Bytes used on disk, as of the end of the last run",
+            self.disk.iter().map(|(dir, bytes)|
+                (format!("{{dir=\"{}\"}}", dir), bytes.to_string()))
+            .collect());
+        family("arb_last_run_success", "gauge",
+            "Whether the last run succeeded, building and publishing included",
+            match self.last_run {
+                Some((success, _)) =>
+                    vec![(String::new(), (success as u8).to_string())],
+                None => vec![],
+            });
+        family("arb_last_run_timestamp_seconds", "gauge",
+            "When the last run ended, seconds since epoch",
+            match self.last_run {
+                Some((_, time)) => vec![(String::new(), time.to_string())],
+                None => vec![],
+            });
+        text
+    }
+
+    fn write(&mut self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.last_write = Some(Instant::now());
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".temp");
+        let mut file = match File::create(&temp_name) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to create metrics '{}': {}",
+                    path.display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = file.write_all(self.to_text().as_bytes()) {
+            log::error!("Failed to write metrics '{}': {}", path.display(), e);
+            return Err(e.into())
+        }
+        if let Err(e) = std::fs::rename(&temp_name, path) {
+            log::error!("Failed to move metrics into '{}': {}",
+                path.display(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+}
+
+/// Writes everything when dropped, so the metrics are always of the latest
+/// run however it ends, failed ones included
+pub(crate) struct Run {
+    succeeded: bool,
+}
+
+impl Run {
+    pub(crate) fn succeed(&mut self) {
+        self.succeeded = true
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        finish(self.succeeded)
+    }
+}
+
+/// Where to write the metrics, nothing is written without this
+pub(crate) fn init<P: AsRef<Path>>(path: P) -> Run {
+    with(|metrics| metrics.path = Some(path.as_ref().to_owned()));
+    Run { succeeded: false }
+}
+
+/// Builders by state, and the jobs count, written if it's been a while
+pub(crate) fn record_builders<I>(states: I, jobs: usize)
+where
+    I: IntoIterator<Item = &'static str>
+{
+    let mut counts = BTreeMap::new();
+    for state in states {
+        *counts.entry(state).or_default() += 1
+    }
+    with(|metrics| {
+        metrics.states = counts;
+        metrics.jobs = jobs;
+        if metrics.last_write.map(|last| last.elapsed() >= INTERVAL)
+            .unwrap_or(true)
+        {
+            let _ = metrics.write();
+        }
+    })
+}
+
+pub(crate) fn record_build(
+    pkgbase: &str, status: &'static str, duration: Option<Duration>
+) {
+    with(|metrics| {
+        *metrics.builds.entry(status).or_default() += 1;
+        if let Some(duration) = duration {
+            metrics.durations.insert(pkgbase.into(), duration.as_secs());
+        }
+    })
+}
+
+pub(crate) fn record_cache(domain: &str, hit: bool) {
+    let result = match hit {
+        true => "hit",
+        false => "miss",
+    };
+    with(|metrics|
+        *metrics.cache.entry((domain.into(), result)).or_default() += 1)
+}
+
+pub(crate) fn record_download(domain: &str, bytes: u64) {
+    with(|metrics|
+        *metrics.downloaded.entry(domain.into()).or_default() += bytes)
+}
+
+pub(crate) fn record_fetch(domain: &str, duration: Duration) {
+    with(|metrics| {
+        let sum = metrics.fetches.entry(domain.into()).or_default();
+        sum.seconds += duration.as_secs_f64();
+        sum.count += 1
+    })
+}
+
+pub(crate) fn record_pressure(pressure: [f32; 3]) {
+    with(|metrics| metrics.pressure = Some(pressure))
+}
+
+pub(crate) fn record_heavy_load(heavy_load: bool) {
+    with(|metrics| metrics.heavy_load = Some(heavy_load))
+}
+
+/// Write everything with the disk usage, at the end of a run
+fn finish(succeeded: bool) {
+    let enabled = METRICS.lock().map(|metrics| metrics.path.is_some())
+        .unwrap_or_default();
+    if ! enabled {
+        return
+    }
+    let mut disk = BTreeMap::new();
+    for dir in DIRS {
+        if let Ok(metadata) = symlink_metadata(dir) {
+            disk.insert(*dir, disk_usage(Path::new(dir), metadata.dev()));
+        }
+    }
+    with(|metrics| {
+        metrics.disk = disk;
+        metrics.last_run = Some((succeeded,
+            time::OffsetDateTime::now_utc().unix_timestamp()));
+        metrics.states.clear();
+        metrics.jobs = 0;
+        if metrics.write().is_ok() {
+            log::info!("Wrote metrics '{}'", metrics.path.as_ref().map(
+                |path| path.display().to_string()).unwrap_or_default())
+        }
+    })
+}
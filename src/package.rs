@@ -0,0 +1,603 @@
+// Packages built by makepkg: their metadata, files and linked sonames
+use std::{
+        fs::File,
+        io::{
+            ErrorKind,
+            Read,
+            Write,
+        },
+        path::Path,
+    };
+
+use flate2::read::GzDecoder;
+
+use xz2::read::XzDecoder;
+
+use serde::{
+        Deserialize,
+        Serialize,
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result,
+        },
+        soname,
+    };
+
+/// Bytes read at once when looking for paths in a file
+const CHUNK: usize = 0x10000;
+
+/// Fields of .PKGINFO, those appearing multiple times are lists
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub(crate) struct PkgInfo {
+    pub(crate) pkgname: String,
+    pub(crate) pkgbase: String,
+    /// [epoch:]pkgver-pkgrel
+    pub(crate) pkgver: String,
+    pub(crate) pkgdesc: String,
+    pub(crate) url: String,
+    pub(crate) builddate: i64,
+    pub(crate) packager: String,
+    /// Installed size in bytes
+    pub(crate) size: u64,
+    pub(crate) arch: String,
+    pub(crate) license: Vec<String>,
+    pub(crate) group: Vec<String>,
+    pub(crate) depend: Vec<String>,
+    pub(crate) optdepend: Vec<String>,
+    pub(crate) makedepend: Vec<String>,
+    pub(crate) checkdepend: Vec<String>,
+    pub(crate) provides: Vec<String>,
+    pub(crate) conflict: Vec<String>,
+    pub(crate) replaces: Vec<String>,
+    pub(crate) backup: Vec<String>,
+}
+
+/// Fields of .BUILDINFO, those appearing multiple times are lists
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub(crate) struct BuildInfo {
+    pub(crate) pkgbuild_sha256sum: String,
+    pub(crate) builddate: i64,
+    pub(crate) builddir: String,
+    #[serde(default)]
+    pub(crate) startdir: String,
+    pub(crate) buildtool: String,
+    pub(crate) buildtoolver: String,
+    pub(crate) buildenv: Vec<String>,
+    pub(crate) options: Vec<String>,
+    /// Packages installed in the build environment, as name-version-arch
+    pub(crate) installed: Vec<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EntryType {
+    File,
+    Dir,
+    Link,
+    Other,
+}
+
+/// An entry in .MTREE, i.e. a file the package installs
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Entry {
+    /// Relative to the root, without leading ./
+    pub(crate) path: String,
+    #[serde(rename = "type")]
+    pub(crate) entry_type: EntryType,
+    pub(crate) mode: String,
+    #[serde(default)]
+    pub(crate) size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) link: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) sha256: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Package {
+    /// File name of the archive
+    pub(crate) file: String,
+    pub(crate) pkginfo: PkgInfo,
+    pub(crate) buildinfo: BuildInfo,
+    pub(crate) entries: Vec<Entry>,
+    /// DT_NEEDED of all ELF files in it
+    pub(crate) sonames: Vec<String>,
+    /// ELF files and scripts still referring to $srcdir or $pkgdir
+    #[serde(default)]
+    pub(crate) references: Vec<String>,
+}
+
+/// Lines of 'key = value', comments and empty lines skipped
+fn key_values(content: &str) -> impl Iterator<Item = (&str, &str)> {
+    content.lines().filter_map(|line| {
+        if line.starts_with('#') {
+            return None
+        }
+        line.split_once(" = ")
+    })
+}
+
+impl PkgInfo {
+    fn from_content(content: &str) -> Self {
+        let mut pkginfo = Self::default();
+        for (key, value) in key_values(content) {
+            let value = value.to_string();
+            match key {
+                "pkgname" => pkginfo.pkgname = value,
+                "pkgbase" => pkginfo.pkgbase = value,
+                "pkgver" => pkginfo.pkgver = value,
+                "pkgdesc" => pkginfo.pkgdesc = value,
+                "url" => pkginfo.url = value,
+                "builddate" =>
+                    pkginfo.builddate = value.parse().unwrap_or_default(),
+                "packager" => pkginfo.packager = value,
+                "size" => pkginfo.size = value.parse().unwrap_or_default(),
+                "arch" => pkginfo.arch = value,
+                "license" => pkginfo.license.push(value),
+                "group" => pkginfo.group.push(value),
+                "depend" => pkginfo.depend.push(value),
+                "optdepend" => pkginfo.optdepend.push(value),
+                "makedepend" => pkginfo.makedepend.push(value),
+                "checkdepend" => pkginfo.checkdepend.push(value),
+                "provides" => pkginfo.provides.push(value),
+                "conflict" => pkginfo.conflict.push(value),
+                "replaces" => pkginfo.replaces.push(value),
+                "backup" => pkginfo.backup.push(value),
+                _ => (),
+            }
+        }
+        pkginfo
+    }
+}
+
+impl BuildInfo {
+    fn from_content(content: &str) -> Self {
+        let mut buildinfo = Self::default();
+        for (key, value) in key_values(content) {
+            let value = value.to_string();
+            match key {
+                "pkgbuild_sha256sum" => buildinfo.pkgbuild_sha256sum = value,
+                "builddate" =>
+                    buildinfo.builddate = value.parse().unwrap_or_default(),
+                "builddir" => buildinfo.builddir = value,
+                "startdir" => buildinfo.startdir = value,
+                "buildtool" => buildinfo.buildtool = value,
+                "buildtoolver" => buildinfo.buildtoolver = value,
+                "buildenv" => buildinfo.buildenv.push(value),
+                "options" => buildinfo.options.push(value),
+                "installed" => buildinfo.installed.push(value),
+                _ => (),
+            }
+        }
+        buildinfo
+    }
+
+    /// $srcdir and $pkgdir of the build, laid out as makepkg does
+    fn build_paths(&self, pkgbase: &str) -> Vec<String> {
+        if self.builddir.is_empty() {
+            return vec![]
+        }
+        let parent = match self.startdir.is_empty() ||
+            self.startdir == self.builddir
+        {
+            true => self.builddir.clone(),
+            false => format!("{}/{}", self.builddir, pkgbase),
+        };
+        vec![format!("{}/src", parent), format!("{}/pkg", parent)]
+    }
+}
+
+/// mtree escapes special chars in paths as \ and 3 octal digits
+fn unescape(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'\\' && index + 3 < bytes.len() &&
+            bytes[index + 1..index + 4].iter().all(
+                |byte| (b'0'..=b'7').contains(byte))
+        {
+            unescaped.push(bytes[index + 1..index + 4].iter().fold(
+                0u8, |value, byte| value.wrapping_mul(8) + (byte - b'0')));
+            index += 4
+        } else {
+            unescaped.push(bytes[index]);
+            index += 1
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// Entries of the decompressed .MTREE, without the metadata files
+fn entries_from_mtree(content: &str) -> Vec<Entry> {
+    let mut defaults: Vec<(String, String)> = vec![];
+    let mut entries = vec![];
+    for line in content.lines() {
+        let mut words = line.split_whitespace();
+        let first = match words.next() {
+            Some(first) => first,
+            None => continue,
+        };
+        if first.starts_with('#') {
+            continue
+        }
+        if first == "/set" {
+            for word in words {
+                if let Some((key, value)) = word.split_once('=') {
+                    defaults.retain(|(default, _)| default != key);
+                    defaults.push((key.to_string(), value.to_string()))
+                }
+            }
+            continue
+        }
+        if first == "/unset" {
+            for key in words {
+                defaults.retain(|(default, _)| default != key)
+            }
+            continue
+        }
+        let path = unescape(first.trim_start_matches("./"));
+        if path.starts_with('.') && ! path.contains('/') {
+            continue
+        }
+        let mut keywords = defaults.clone();
+        for word in words {
+            if let Some((key, value)) = word.split_once('=') {
+                keywords.retain(|(keyword, _)| keyword != key);
+                keywords.push((key.to_string(), value.to_string()))
+            }
+        }
+        let keyword = |key: &str| keywords.iter().find(
+            |(keyword, _)| keyword == key).map(|(_, value)| value.as_str());
+        entries.push(Entry {
+            entry_type: match keyword("type") {
+                Some("file") => EntryType::File,
+                Some("dir") => EntryType::Dir,
+                Some("link") => EntryType::Link,
+                _ => EntryType::Other,
+            },
+            mode: keyword("mode").unwrap_or_default().to_string(),
+            size: keyword("size").and_then(
+                |size| size.parse().ok()).unwrap_or_default(),
+            link: keyword("link").map(unescape),
+            sha256: keyword("sha256digest").map(|digest| digest.to_string()),
+            path,
+        })
+    }
+    entries
+}
+
+/// Decompressed stream of a package archive, by its suffix
+fn decoder(archive: &Path) -> std::io::Result<Box<dyn Read>> {
+    let file = File::open(archive)?;
+    let name = archive.to_string_lossy();
+    Ok(if name.ends_with(".zst") {
+        Box::new(zstd::stream::read::Decoder::new(file)?)
+    } else if name.ends_with(".xz") {
+        Box::new(XzDecoder::new(file))
+    } else if name.ends_with(".gz") {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    })
+}
+
+/// Read up to the buffer length, less only at the end
+fn read_head<R: Read>(reader: &mut R, head: &mut [u8])
+    -> std::io::Result<usize>
+{
+    let mut len = 0;
+    while len < head.len() {
+        match reader.read(&mut head[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Whether what's read contains any of the paths, read in chunks so a huge
+/// file is never fully in memory, all of it is copied into the spool if any
+fn refers_to<R: Read>(
+    reader: &mut R, needles: &[String], mut spool: Option<&mut File>
+) -> std::io::Result<bool>
+{
+    let keep = needles.iter().map(|needle| needle.len()).max()
+        .unwrap_or_default().saturating_sub(1);
+    let mut chunk = vec![0; CHUNK];
+    let mut buffer = Vec::with_capacity(CHUNK + keep);
+    let mut found = needles.is_empty();
+    loop {
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(spool) = spool.as_mut() {
+            spool.write_all(&chunk[..len])?
+        }
+        if found {
+            if spool.is_none() {
+                break
+            }
+            continue
+        }
+        buffer.extend_from_slice(&chunk[..len]);
+        found = needles.iter().any(|needle| buffer.windows(needle.len())
+            .any(|window| window == needle.as_bytes()));
+        buffer.drain(..buffer.len().saturating_sub(keep));
+    }
+    Ok(found && ! needles.is_empty())
+}
+
+/// Whether a file in a pkgdir is a package archive, not its signature
+pub(crate) fn is_archive(name: &str) -> bool {
+    [".pkg.tar.zst", ".pkg.tar.xz", ".pkg.tar.gz", ".pkg.tar"].iter().any(
+        |suffix| name.ends_with(suffix))
+}
+
+impl Package {
+    /// Stream the archive through its decoder and read its metadata, then
+    /// only look into ELF files and scripts. An ELF file is spooled beside the
+    /// archive, one at a time, as its sections could only be read randomly
+    pub(crate) fn from_archive<P: AsRef<Path>>(archive: P) -> Result<Self> {
+        let archive = archive.as_ref();
+        let (file, parent) = match (archive.file_name(), archive.parent()) {
+            (Some(file), Some(parent)) =>
+                (file.to_string_lossy().into_owned(), parent),
+            _ => {
+                log::error!("Package archive '{}' has no file name",
+                    archive.display());
+                return Err(Error::ImpossibleLogic)
+            },
+        };
+        let bad = |e: std::io::Error| {
+            log::error!("Failed to read package '{}': {}", archive.display(), e);
+            Error::BadPackages(vec![file.clone()])
+        };
+        let mut tar = tar::Archive::new(decoder(archive).map_err(bad)?);
+        let mut pkginfo = None;
+        let mut buildinfo = None;
+        let mut entries = None;
+        let mut sonames = vec![];
+        let mut references = vec![];
+        for entry in tar.entries().map_err(bad)? {
+            let mut entry = entry.map_err(bad)?;
+            let path = entry.path().map_err(bad)?.to_string_lossy()
+                .trim_start_matches("./").to_string();
+            let mut content = String::new();
+            match path.as_str() {
+                ".PKGINFO" => {
+                    entry.read_to_string(&mut content).map_err(bad)?;
+                    pkginfo = Some(PkgInfo::from_content(&content));
+                    continue
+                },
+                ".BUILDINFO" => {
+                    entry.read_to_string(&mut content).map_err(bad)?;
+                    buildinfo = Some(BuildInfo::from_content(&content));
+                    continue
+                },
+                ".MTREE" => {
+                    GzDecoder::new(&mut entry).read_to_string(&mut content)
+                        .map_err(bad)?;
+                    entries = Some(entries_from_mtree(&content));
+                    continue
+                },
+                _ => (),
+            }
+            if ! entry.header().entry_type().is_file() {
+                continue
+            }
+            let mut head = [0; 4];
+            let len = read_head(&mut entry, &mut head).map_err(bad)?;
+            let head = &head[..len];
+            let is_elf = head == b"\x7fELF";
+            if ! is_elf && ! head.starts_with(b"#!") {
+                continue
+            }
+            // makepkg puts the metadata files first
+            let needles = match (&pkginfo, &buildinfo) {
+                (Some(pkginfo), Some(buildinfo)) =>
+                    buildinfo.build_paths(&pkginfo.pkgbase),
+                _ => {
+                    log::error!("Package '{}' has '{}' before its .PKGINFO \
+                        and .BUILDINFO", file, path);
+                    return Err(Error::BadPackages(vec![file]))
+                },
+            };
+            let mut spool = match is_elf {
+                true => match tempfile::Builder::new().prefix(".elf")
+                    .tempfile_in(parent)
+                {
+                    Ok(spool) => Some(spool),
+                    Err(e) => {
+                        log::error!("Failed to create spool in '{}' for ELF \
+                            file: {}", parent.display(), e);
+                        return Err(e.into())
+                    },
+                },
+                false => None,
+            };
+            match refers_to(&mut head.chain(&mut entry), &needles,
+                spool.as_mut().map(|spool| spool.as_file_mut()))
+            {
+                Ok(true) => references.push(path.clone()),
+                Ok(false) => (),
+                Err(e) => return Err(bad(e)),
+            }
+            if let Some(spool) = spool {
+                match soname::needed_in_elf(spool.path()) {
+                    Ok(Some(mut needed)) => sonames.append(&mut needed),
+                    Ok(None) => (),
+                    Err(e) => {
+                        log::error!("Failed to read ELF '{}' in package \
+                            '{}': {}", path, file, e);
+                        return Err(e.into())
+                    },
+                }
+            }
+        }
+        let (pkginfo, buildinfo, entries) =
+            match (pkginfo, buildinfo, entries)
+        {
+            (Some(pkginfo), Some(buildinfo), Some(entries)) =>
+                (pkginfo, buildinfo, entries),
+            _ => {
+                log::error!("Package '{}' lacks any of .PKGINFO, .BUILDINFO \
+                    and .MTREE", file);
+                return Err(Error::BadPackages(vec![file]))
+            },
+        };
+        sonames.sort_unstable();
+        sonames.dedup();
+        Ok(Self { file, pkginfo, buildinfo, entries, sonames, references })
+    }
+
+    /// All package archives in a dir
+    pub(crate) fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<Self>> {
+        let readdir = match dir.as_ref().read_dir() {
+            Ok(readdir) => readdir,
+            Err(e) => {
+                log::error!("Failed to read dir '{}': {}",
+                    dir.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        let mut packages = vec![];
+        for entry in readdir {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::error!("Failed to read entry from dir '{}': {}",
+                        dir.as_ref().display(), e);
+                    return Err(e.into())
+                },
+            };
+            if is_archive(&entry.file_name().to_string_lossy()) {
+                packages.push(Self::from_archive(entry.path())?)
+            }
+        }
+        packages.sort_unstable_by(|a, b| a.file.cmp(&b.file));
+        Ok(packages)
+    }
+
+    /// Why this is not a sane package, if it's not
+    pub(crate) fn problem(&self) -> Option<String> {
+        let pkginfo = &self.pkginfo;
+        if pkginfo.pkgname.is_empty() || pkginfo.pkgver.is_empty() ||
+            pkginfo.arch.is_empty()
+        {
+            return Some("no pkgname, pkgver or arch in .PKGINFO".into())
+        }
+        let prefix = format!("{}-{}-{}.pkg.tar",
+            pkginfo.pkgname, pkginfo.pkgver, pkginfo.arch);
+        if ! self.file.starts_with(&prefix) {
+            return Some(format!("file name does not start with '{}' as \
+                .PKGINFO says", prefix))
+        }
+        // Meta packages install nothing but depend on something
+        if pkginfo.depend.is_empty() && ! self.entries.iter().any(
+            |entry| ! matches!(entry.entry_type, EntryType::Dir))
+        {
+            return Some("it installs nothing and depends on nothing".into())
+        }
+        None
+    }
+}
+
+/// Metadata of all packages of a pkgid, recorded as pkgs/[pkgid].json
+pub(crate) fn write_metadata<P: AsRef<Path>>(path: P, packages: &[Package])
+    -> Result<()>
+{
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to create package metadata '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        },
+    };
+    if let Err(e) = serde_json::to_writer_pretty(file, packages) {
+        log::error!("Failed to write package metadata '{}': {}",
+            path.as_ref().display(), e);
+        return Err(std::io::Error::other(e).into())
+    }
+    Ok(())
+}
+
+pub(crate) fn read_metadata<P: AsRef<Path>>(path: P) -> Result<Vec<Package>> {
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open package metadata '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        },
+    };
+    match serde_json::from_reader(file) {
+        Ok(packages) => Ok(packages),
+        Err(e) => {
+            log::error!("Failed to parse package metadata '{}': {}",
+                path.as_ref().display(), e);
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescape_octal() {
+        assert_eq!(unescape("usr/share/my\\040file"), "usr/share/my file");
+        assert_eq!(unescape("a\\134b"), "a\\b");
+        assert_eq!(unescape("caf\\303\\251"), "café");
+        // Not an escape, or cut short at the end
+        assert_eq!(unescape("a\\9b"), "a\\9b");
+        assert_eq!(unescape("a\\04"), "a\\04");
+    }
+
+    #[test]
+    fn mtree_set_unset() {
+        let mtree = "#mtree\n\
+            /set type=file uid=0 gid=0 mode=644\n\
+            ./.BUILDINFO time=1 size=100 sha256digest=aa\n\
+            ./.PKGINFO time=1 size=200 sha256digest=bb\n\
+            ./usr time=1 mode=755 type=dir\n\
+            ./usr/bin time=1 mode=755 type=dir\n\
+            ./usr/bin/foo time=1 mode=755 size=42 sha256digest=cc\n\
+            /set mode=777 type=link\n\
+            ./usr/bin/bar time=1 link=foo\n\
+            /unset mode\n\
+            ./usr/bin/my\\040baz time=1 link=my\\040foo\n\
+            /set type=file\n\
+            ./usr/share/doc/readme time=1 size=7\n";
+        let entries = entries_from_mtree(mtree);
+        let paths: Vec<&str> = entries.iter().map(
+            |entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["usr", "usr/bin", "usr/bin/foo", "usr/bin/bar",
+            "usr/bin/my baz", "usr/share/doc/readme"]);
+        assert!(matches!(entries[0].entry_type, EntryType::Dir));
+        assert_eq!(entries[0].mode, "755");
+        assert!(matches!(entries[2].entry_type, EntryType::File));
+        assert_eq!(entries[2].mode, "755");
+        assert_eq!(entries[2].size, 42);
+        assert_eq!(entries[2].sha256.as_deref(), Some("cc"));
+        assert!(matches!(entries[3].entry_type, EntryType::Link));
+        assert_eq!(entries[3].mode, "777");
+        assert_eq!(entries[3].link.as_deref(), Some("foo"));
+        assert_eq!(entries[4].mode, "");
+        assert_eq!(entries[4].link.as_deref(), Some("my foo"));
+        assert!(matches!(entries[5].entry_type, EntryType::File));
+        assert_eq!(entries[5].mode, "");
+        assert_eq!(entries[5].size, 7);
+        assert_eq!(entries[5].sha256, None);
+    }
+}
@@ -1,12 +1,31 @@
 // TODO: Split this into multiple modules
 // Progress: already splitted part into pkgbuild/parse.rs, add mod parse to enable part of that
 use crate::{
-        config::Pkgbuild as PkgbuildConfig,
+        arch::Target,
+        ccache::Ccache,
+        changelog::{
+            self,
+            Built,
+            Changelog,
+        },
+        distcc::Distcc,
+        scheduler::Scheduler,
+        config::{
+            CheckMode,
+            Lint,
+            Pkgbuild as PkgbuildConfig,
+            PkgbuildOrigin,
+        },
         error::{
             Error,
             Result
         },
         identity::IdentityActual,
+        logger,
+        index::{
+            self,
+            IndexEntry,
+        },
         source::{
             self,
             git::{self, Gmr},
@@ -20,9 +39,32 @@ use crate::{
         threading::{
             self,
             wait_if_too_busy,
-        }, filesystem::remove_dir_all_try_best, sign::sign_pkgs, depend::{Depends, DbHandle}, config::DepHashStrategy
+        }, filesystem::remove_dir_all_try_best, sign::sign_pkgs, depend::{Depends, DbHandle, DepGraph, Offer}, config::DepHashStrategy,
+        lint::{
+            self,
+            Finding,
+        },
+        package::{
+            self,
+            Package,
+        },
+        soname::{
+            self,
+            Linked,
+            Sonames,
+            SONAMESFILE,
+        },
+        lock::{
+            Lock,
+            LockedNetfile,
+            LockedPkgbuild,
+        },
+    };
+use git2::{
+        Commit,
+        Oid,
+        Tree,
     };
-use git2::Oid;
 use std::{
         collections::HashMap,
         ffi::OsString,
@@ -46,12 +88,21 @@ use std::{
             Stdio
         },
         thread,
+        time::SystemTime,
         iter::zip,
     };
-use xxhash_rust::xxh3::xxh3_64;
+use xxhash_rust::xxh3::{
+        xxh3_64,
+        Xxh3,
+    };
 // use super::{depend::Depends, DepHashStrategy};
 // use super::depend::DbHandle;
 // mod parse;
+mod makepkg;
+mod origin;
+mod patch;
+
+pub(crate) use makepkg::Makepkg;
 
 
 #[derive(Clone)]
@@ -62,23 +113,39 @@ enum Pkgver {
 
 #[derive(Clone)]
 pub(crate) struct PKGBUILD {
+    /// arch=() of the PKGBUILD
+    arches: Vec<String>,
     pub(crate) base: String,
-    branch: String,
     build: PathBuf,
+    /// The target arch, as CARCH for the scripts reading PKGBUILD on host
+    carch: String,
+    check: Option<CheckMode>,
     commit: git2::Oid,
     depends: Depends,
     pub(crate) extracted: bool,
     git: PathBuf,
     home_binds: Vec<String>,
-    names: Vec<String>,
+    /// Lint levels set for this package, on top of the global ones
+    lint: Option<Lint>,
+    locked: Option<Oid>,
+    makepkg: Makepkg,
+    pub(crate) names: Vec<String>,
     pub(crate) need_build: bool,
+    origin: PkgbuildOrigin,
+    patches: Vec<patch::Patch>,
     pub(crate) pkgid: String,
     pkgdir: PathBuf,
     pkgver: Pkgver,
-    provides: Vec<String>,
+    pub(crate) provides: Vec<String>,
+    /// Whether pkgids of the wanted PKGBUILDs are part of the dephash
+    rebuild_on_dep_change: Option<bool>,
+    reference: git::Reference,
+    revision: Oid,
     sources: Vec<source::Source>,
     subtree: Option<PathBuf>,
     url: String,
+    /// [epoch:]pkgver-pkgrel, with pkgver from pkgver() if it was run
+    version: String,
 }
 
 impl source::MapByDomain for PKGBUILD {
@@ -100,8 +167,12 @@ impl git::ToReposMap for PKGBUILD {
         Some(&self.git.as_path())
     }
 
-    fn branch(&self) -> Option<String> {
-        Some(self.branch.clone())
+    fn refname(&self) -> Option<String> {
+        // A locked commit could be on any ref
+        match self.locked {
+            Some(_) => None,
+            None => self.reference.refname(),
+        }
     }
 }
 
@@ -115,22 +186,37 @@ impl PKGBUILD {
     // pub(crate) fn provides(&self, pkg: &String) -> bool {
     //     self.names.contains(pkg) || self.provides.contains(pkg)
     // }
-    pub(crate) fn wants<'a> (&'a self, other: &'a Self) -> Option<&'a str> {
-        for pkg in other.names.iter().chain(other.provides.iter()) {
-            if self.depends.wants(pkg) {
-                return Some(pkg)
-            }
+    /// The dep of this and the pkgname or provide of the other satisfying it,
+    /// if this wants the other
+    pub(crate) fn wants<'a> (&'a self, other: &'a Self)
+        -> Option<(&'a str, &'a str)>
+    {
+        self.depends.wants(&other.offer())
+    }
+
+    /// The pkgnames, provides of all split packages and the version
+    fn offer(&self) -> Offer<'_> {
+        Offer {
+            names: &self.names,
+            version: &self.version,
+            provides: &self.provides,
         }
-        None
     }
+
     fn new(
         name: &str, url: &str, build_parent: &Path, git_parent: &Path,
-        branch: Option<&str>, subtree: Option<&str>, deps: Option<&Vec<String>>,
+        pkgs_parent: &Path, origin: PkgbuildOrigin, reference: git::Reference,
+        subtree: Option<&str>, patches: Vec<patch::Patch>,
+        deps: Option<&Vec<String>>,
         makedeps: Option<&Vec<String>>, home_binds: Option<&Vec<String>>,
-        home_binds_global: &Vec<String>
+        home_binds_global: &Vec<String>, makepkg: Makepkg,
+        check: Option<CheckMode>, rebuild_on_dep_change: Option<bool>,
+        lint: Option<Lint>
     ) -> Self
     {
-        let url = if url == "AUR" {
+        let url = if origin != PkgbuildOrigin::Git {
+            url.to_string()
+        } else if url == "AUR" {
             format!("https://aur.archlinux.org/{}.git", name)
         } else if url.starts_with("GITHUB/") {
             if url.ends_with('/') {
@@ -148,12 +234,11 @@ impl PKGBUILD {
             url.to_string()
         };
         Self {
+            arches: vec![],
             base: name.to_string(),
-            branch: match branch {
-                Some(branch) => branch.to_owned(),
-                None => String::from("master"),
-            },
             build: build_parent.join(name),
+            carch: String::new(),
+            check,
             commit: Oid::zero(),
             depends: Depends {
                 deps: match deps {
@@ -172,6 +257,8 @@ impl PKGBUILD {
                 },
                 needs: vec![],
                 hash: 0,
+                soname_provides: vec![],
+                sonames: None,
             },
             extracted: false,
             git: git_parent.join(
@@ -186,12 +273,20 @@ impl PKGBUILD {
                 }
                 home_binds
             },
+            lint,
+            locked: None,
+            makepkg,
             names: vec![],
             need_build: false,
+            origin,
+            patches,
             pkgid: String::new(),
-            pkgdir: PathBuf::from("pkgs"),
+            pkgdir: pkgs_parent.to_owned(),
             pkgver: Pkgver::Plain,
             provides: vec![],
+            rebuild_on_dep_change,
+            reference,
+            revision: Oid::zero(),
             sources: vec![],
             subtree: match subtree {
                 Some(subtree) => {
@@ -209,10 +304,60 @@ impl PKGBUILD {
                 None => None,
             },
             url,
+            version: String::new(),
+        }
+    }
+    /// The commit to take PKGBUILD from, the locked one if locked, otherwise
+    /// the one the reference points to
+    fn get_commit<'a>(&self, repo: &'a git::Repo) -> Result<Commit<'a>> {
+        match self.locked {
+            Some(locked) => repo.get_commit(locked),
+            None => repo.get_reference_commit(&self.reference),
         }
     }
-    // If healthy, return the latest commit id
-    fn healthy_get_commit(&self) -> Result<Oid> {
+
+    /// Whether the PKGBUILD is pinned to a commit that never moves
+    fn is_immutable(&self) -> bool {
+        self.locked.is_some() ||
+            matches!(self.reference, git::Reference::Commit(_))
+    }
+
+    /// Import the PKGBUILD into its bare repo if it's not from a git repo
+    fn import(&self, proxy: Option<&Proxy>) -> Result<()> {
+        let repo = git::Repo::open_bare(&self.git, &self.url, None)?;
+        match self.origin {
+            PkgbuildOrigin::Git => return Ok(()),
+            PkgbuildOrigin::Dir =>
+                origin::import_dir(&repo, Path::new(&self.url))?,
+            PkgbuildOrigin::Tarball =>
+                origin::import_tarball(&repo, &self.url, proxy)?,
+        };
+        Ok(())
+    }
+
+    /// The tree to take PKGBUILD from, with all patches applied
+    fn get_tree<'a>(&self, repo: &'a git::Repo, commit: &Commit<'a>)
+        -> Result<Tree<'a>>
+    {
+        let mut tree = repo.get_commit_tree(commit, self.subtree.as_deref())?;
+        for patch in self.patches.iter() {
+            tree = match repo.apply_patch(&tree, &patch.content) {
+                Ok(tree) => tree,
+                Err(_) => {
+                    log::error!("Patch '{}' does not apply to PKGBUILD '{}' \
+                        at commit '{}'", patch.path.display(), self.base,
+                        commit.id());
+                    return Err(Error::BrokenPatches(vec![format!(
+                        "'{}' for PKGBUILD '{}'", patch.path.display(),
+                        self.base)]))
+                },
+            }
+        }
+        Ok(tree)
+    }
+
+    // If healthy, return the latest commit id, and the commit or subtree id
+    fn healthy_get_commit(&self) -> Result<(Oid, Oid)> {
         let repo = match git::Repo::open_bare(
             &self.git, &self.url, None) 
         {
@@ -223,29 +368,40 @@ impl PKGBUILD {
                 return Err(e.into())
             },
         };
-        let commit = repo.get_branch_commit_or_subtree_id(
-            &self.branch, self.subtree.as_deref()
+        let revision = self.get_commit(&repo)?;
+        let commit = repo.get_commit_or_subtree_id(
+            &revision, self.subtree.as_deref()
         )?;
         match &self.subtree {
-            Some(_) => log::info!("PKGBUILD '{}' at tree '{}'",
-                        self.base, commit),
-            None => log::info!("PKGBUILD '{}' at commit '{}'", self.base, commit),
+            Some(_) => log::info!("PKGBUILD '{}' ({}) at tree '{}'",
+                        self.base, self.reference, commit),
+            None => log::info!("PKGBUILD '{}' ({}) at commit '{}'",
+                        self.base, self.reference, commit),
         }
-        if let Err(e) = repo.get_pkgbuild_blob(&self.branch,
-                self.subtree.as_deref()) 
-        {
+        let tree = self.get_tree(&repo, &revision)?;
+        if let Err(e) = repo.get_tree_pkgbuild_blob(&tree) {
             log::error!("Failed to get PKGBUILD blob");
             return Err(e)
         }
-        Ok(commit)
+        Ok((revision.id(), commit))
     }
 
     fn healthy_set_commit(&mut self) -> Result<()> {
         match self.healthy_get_commit() {
-            Ok(commit) => {
+            Ok((revision, commit)) => {
+                self.revision = revision;
                 self.commit = commit;
                 Ok(())
             },
+            Err(Error::ReferenceMissing(refs)) => {
+                log::error!("PKGBUILD '{}' could not be found at {}, it might \
+                    have been deleted upstream", self.base, self.reference);
+                Err(Error::ReferenceMissing(refs.into_iter().map(|reference|
+                    format!("{} for PKGBUILD '{}'", reference, self.base)
+                ).collect()))
+            },
+            Err(Error::BrokenPatches(patches)) =>
+                Err(Error::BrokenPatches(patches)),
             Err(_e) => {
                 // log::error!("PKGBUILD '{}' is not healthy: {}", &self.base, e);
                 Err(Error::BrokenPKGBUILDs(vec![self.base.clone()]))
@@ -253,11 +409,89 @@ impl PKGBUILD {
         }
     }
 
+    fn subtree_string(&self) -> Option<String> {
+        self.subtree.as_ref().map(|subtree|
+            subtree.to_string_lossy().into_owned())
+    }
+
+    fn apply_lock(&mut self, locked: &LockedPkgbuild) -> Result<()> {
+        if locked.url != self.url || locked.subtree != self.subtree_string() ||
+            locked.reference != self.reference.to_string()
+        {
+            log::error!("PKGBUILD '{}' changed its url, reference or subtree \
+                since locked, run without --locked to update the lockfile",
+                self.base);
+            return Err(Error::InvalidConfig)
+        }
+        let commit = match Oid::from_str(&locked.commit) {
+            Ok(commit) => commit,
+            Err(e) => {
+                log::error!("Illegal locked commit '{}' for PKGBUILD '{}': {}",
+                    locked.commit, self.base, e);
+                return Err(e.into())
+            },
+        };
+        log::info!("PKGBUILD '{}' locked at commit '{}'", self.base, commit);
+        self.locked = Some(commit);
+        Ok(())
+    }
+
+    fn to_locked(&self) -> LockedPkgbuild {
+        let mut netfiles = vec![];
+        let mut gits = vec![];
+        for source in self.sources.iter() {
+            if source.is_netfile() {
+                netfiles.push(LockedNetfile {
+                    name: source.name().to_string(),
+                    url: source::MapByDomain::url(source).to_string(),
+                    cksums: source.cksums(),
+                })
+            } else if source.is_git() {
+                gits.push(source::MapByDomain::url(source).to_string())
+            }
+        }
+        LockedPkgbuild {
+            url: self.url.clone(),
+            reference: self.reference.to_string(),
+            subtree: self.subtree_string(),
+            commit: self.revision.to_string(),
+            tree: self.commit.to_string(),
+            pkgid: self.pkgid.clone(),
+            deps: self.depends.deps.clone(),
+            makedeps: self.depends.makedeps.clone(),
+            dephash: format!("{:016x}", self.depends.hash),
+            netfiles,
+            gits,
+        }
+    }
+
+    /// Netfiles must be exactly the locked ones, pkgid differing is only
+    /// warned, as the sync repos could not be locked
+    fn check_locked(&self, locked: &LockedPkgbuild) -> Result<()> {
+        let current = self.to_locked();
+        if current.netfiles != locked.netfiles {
+            log::error!("Netfile sources of PKGBUILD '{}' differ from the \
+                locked ones, refusing to continue", self.base);
+            return Err(Error::IntegrityError)
+        }
+        if current.dephash != locked.dephash {
+            log::warn!("Dephash of PKGBUILD '{}' changed from locked '{}' to \
+                '{}' as sync repos moved on", self.base, locked.dephash,
+                current.dephash);
+        }
+        if current.pkgid != locked.pkgid {
+            log::warn!("Pkgid of PKGBUILD '{}' changed from locked '{}' to '{}'",
+                self.base, locked.pkgid, current.pkgid);
+        }
+        Ok(())
+    }
+
     fn dump<P: AsRef<Path>> (&self, target: P) -> Result<()> {
         let repo = git::Repo::open_bare(
             &self.git, &self.url, None)?;
-        let blob = repo.get_pkgbuild_blob(&self.branch,
-            self.subtree.as_deref())?;
+        let commit = repo.get_commit(self.revision)?;
+        let tree = self.get_tree(&repo, &commit)?;
+        let blob = repo.get_tree_pkgbuild_blob(&tree)?;
         let mut file = match std::fs::File::create(&target) {
             Ok(file) => file,
             Err(e) => {
@@ -275,21 +509,55 @@ impl PKGBUILD {
     }
 
     fn dep_reader_file<P: AsRef<Path>> (
-        actual_identity: &IdentityActual, pkgbuild_file: P
+        actual_identity: &IdentityActual, pkgbuild_file: P, carch: &str
     ) -> std::io::Result<Child>
     {
         actual_identity.set_root_drop_command(
             Command::new("/bin/bash")
                 .arg("-ec")
-                .arg(". \"$1\"; \
-                    for dep in \"${depends[@]}\"; do \
+                .arg("CARCH=\"$2\"; \
+                    . \"$1\"; \
+                    declare -n depends_arch=\"depends_${CARCH}\"; \
+                    declare -n makedepends_arch=\"makedepends_${CARCH}\"; \
+                    declare -n provides_arch=\"provides_${CARCH}\"; \
+                    for dep in \"${depends[@]}\" \"${depends_arch[@]}\"; do \
                         echo \"d:${dep}\"; \
                     done; \
-                    for dep in  \"${makedepends[@]}\"; do \
+                    for dep in  \"${makedepends[@]}\" \
+                        \"${makedepends_arch[@]}\"; do \
                         echo \"m:${dep}\"; \
+                    done; \
+                    echo \"e:${epoch}\"; \
+                    echo \"v:${pkgver}\"; \
+                    echo \"r:${pkgrel}\"; \
+                    for arch in \"${arch[@]}\"; do \
+                        echo \"a:${arch}\"; \
+                    done; \
+                    re='^[[:space:]]*(declare( -[[:alpha:]]+)* )?'; \
+                    re+='provides(_'\"${CARCH}\"')?(\\+?)=(.*)$'; \
+                    for name in \"${pkgname[@]}\"; do \
+                        echo \"n:${name}\"; \
+                        pkg_provides=(\"${provides[@]}\" \
+                            \"${provides_arch[@]}\"); \
+                        if func=\"$(declare -f \"package_${name}\")\"; then \
+                            while IFS= read -r line; do \
+                                if [[ \"${line}\" =~ ${re} ]]; then \
+                                    op=\"${BASH_REMATCH[4]}=\"; \
+                                    if [[ -n \"${BASH_REMATCH[3]}\" ]]; then \
+                                        op='+='; \
+                                    fi; \
+                                    value=\"${BASH_REMATCH[5]%;}\"; \
+                                    eval \"pkg_provides${op}${value}\"; \
+                                fi; \
+                            done <<< \"${func}\"; \
+                        fi; \
+                        for provide in \"${pkg_provides[@]}\"; do \
+                            echo \"p:${provide}\"; \
+                        done; \
                     done")
                 .arg("Depends reader")
                 .arg(pkgbuild_file.as_ref())
+                .arg(carch)
                 .stdout(Stdio::piped()))
             .spawn()
     }
@@ -298,18 +566,18 @@ impl PKGBUILD {
         -> std::io::Result<Child>
     {
         let pkgbuild_file = dir.as_ref().join(&self.base);
-        Self::dep_reader_file(actual_identity, &pkgbuild_file)
+        Self::dep_reader_file(actual_identity, &pkgbuild_file, &self.carch)
     }
 
-    fn get_sources_file<P: AsRef<Path>> (pkgbuild_file: P)
+    fn get_sources_file<P: AsRef<Path>> (pkgbuild_file: P, carch: &str)
         -> Result<Vec<source::Source>>
     {
-        source::get_sources(pkgbuild_file)
+        source::get_sources(pkgbuild_file, carch)
     }
 
     fn get_sources<P: AsRef<Path>> (&mut self, dir: P) -> Result<()> {
         let pkgbuild_file = dir.as_ref().join(&self.base);
-        match Self::get_sources_file(&pkgbuild_file) {
+        match Self::get_sources_file(&pkgbuild_file, &self.carch) {
             Ok(sources) => {
                 self.sources = sources;
                 Ok(())
@@ -318,8 +586,9 @@ impl PKGBUILD {
         }
     }
 
+    /// The extractor, and where its log goes
     pub(crate) fn extractor_source(
-        &self, actual_identity: &IdentityActual) -> Result<Child>
+        &self, actual_identity: &IdentityActual) -> Result<(Child, PathBuf)>
     {
         const SCRIPT: &str = include_str!("../scripts/extract_sources.bash");
         if let Err(e) = create_dir_all(&self.build) {
@@ -328,9 +597,9 @@ impl PKGBUILD {
         }
         let repo = git::Repo::open_bare(
             &self.git, &self.url, None)?;
-        repo.checkout(
-            &self.build, &self.branch, self.subtree.as_deref()
-        )?;
+        let commit = repo.get_commit(self.revision)?;
+        let tree = self.get_tree(&repo, &commit)?;
+        repo.checkout_tree(&self.build, &tree)?;
         source::extract(&self.build, &self.sources)?;
         let pkgbuild_dir = self.build.canonicalize().or_else(
         |e|{
@@ -342,6 +611,7 @@ impl PKGBUILD {
         arg0.push("] /bin/bash");
         let log_file = crate::logfile::LogFile::new(
             crate::logfile::LogType::Extract, &self.base)?;
+        let log_path = log_file.path.clone();
         match actual_identity.set_root_drop_command(
             log_file.set_command(
                 Command::new("/bin/bash")
@@ -349,11 +619,12 @@ impl PKGBUILD {
                     .arg("-ec")
                     .arg(SCRIPT)
                     .arg("Source extractor")
-                    .arg(&pkgbuild_dir))?
+                    .arg(&pkgbuild_dir)
+                    .arg(&self.carch))?
             )
             .spawn()
         {
-            Ok(child) => Ok(child),
+            Ok(child) => Ok((child, log_path)),
             Err(e) => {
                 log::error!("Faiiled to spawn extractor: {}", e);
                 Err(Error::IoError(e))
@@ -361,23 +632,295 @@ impl PKGBUILD {
         }
     }
 
-    fn fill_id_dir(&mut self, dephash_strategy: &DepHashStrategy) {
-        let mut pkgid = if let DepHashStrategy::None = dephash_strategy
-        {
-            format!("{}-{}", self.base, self.commit)
+    /// Whether the pkgids of the wanted PKGBUILDs should be folded into the
+    /// dephash, by default only with the cascade strategy
+    fn cascades(&self, dephash_strategy: &DepHashStrategy) -> bool {
+        self.rebuild_on_dep_change.unwrap_or(
+            *dephash_strategy == DepHashStrategy::Cascade)
+    }
+
+    /// The dephash going into the pkgid, if any, with the pkgids of the
+    /// wanted PKGBUILDs folded in if cascading
+    fn dephash(
+        &self, dephash_strategy: &DepHashStrategy, wanted_pkgids: &[String]
+    ) -> Option<u64>
+    {
+        if self.cascades(dephash_strategy) && ! wanted_pkgids.is_empty() {
+            let mut hash = Xxh3::new();
+            hash.update(&self.depends.hash.to_le_bytes());
+            for pkgid in wanted_pkgids.iter() {
+                hash.update(pkgid.as_bytes());
+            }
+            Some(hash.digest())
+        } else if *dephash_strategy != DepHashStrategy::None {
+            Some(self.depends.hash)
         } else {
-            format!( "{}-{}-{:016x}", self.base, self.commit,
-                self.depends.hash)
-        };
+            None
+        }
+    }
+
+    /// The part of the pkgid from what it's built from, before the dephash
+    fn sources_id(&self) -> String {
+        let mut id = format!("{}-{}", self.base, self.commit);
+        if ! self.patches.is_empty() {
+            id.push_str(&format!("-p{:016x}", patch::hash(&self.patches)))
+        }
+        if ! self.makepkg.is_default() {
+            id.push_str(&format!("-m{:016x}", self.makepkg.hash()))
+        }
+        id
+    }
+
+    fn compose_id(&self, dephash: Option<u64>) -> String {
+        let mut pkgid = self.sources_id();
+        if let Some(dephash) = dephash {
+            pkgid.push_str(&format!("-{:016x}", dephash))
+        }
         if let Pkgver::Func { pkgver } = &self.pkgver {
             pkgid.push('-');
             pkgid.push_str(&pkgver);
         }
+        pkgid
+    }
+
+    /// Hash only the soname provides of the given sonames, or all if none
+    fn soname_dephash(
+        &mut self, dephash_strategy: &DepHashStrategy, wanted_pkgids: &[String],
+        sonames: Option<Vec<String>>
+    ) -> (Option<u64>, String)
+    {
+        self.depends.sonames = sonames;
+        self.depends.hash = soname::hash(&self.depends.soname_provides,
+            self.depends.sonames.as_deref());
+        let dephash = self.dephash(dephash_strategy, wanted_pkgids);
+        (dephash, self.compose_id(dephash))
+    }
+
+    /// Before the first build with the soname strategy, take over the last
+    /// build of the same sources, e.g. with the strategy used before, and
+    /// seed the record from what it links against, instead of rebuilding it
+    fn seed_linked(
+        &mut self, dephash_strategy: &DepHashStrategy, wanted_pkgids: &[String]
+    ) -> Option<Linked>
+    {
+        let sources_id = self.sources_id();
+        let suffix = match &self.pkgver {
+            Pkgver::Func { pkgver } => format!("-{}", pkgver),
+            Pkgver::Plain => String::new(),
+        };
+        let mut last: Option<(SystemTime, String)> = None;
+        for entry in self.pkgdir.read_dir().ok()?.flatten() {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let dephash = match name.strip_prefix(&sources_id)
+                .and_then(|rest| rest.strip_suffix(&suffix))
+            {
+                Some(dephash) => dephash,
+                None => continue,
+            };
+            if ! dephash.is_empty() && (dephash.len() != 17 ||
+                ! dephash.starts_with('-') ||
+                ! dephash[1..].bytes().all(|c| c.is_ascii_hexdigit()))
+            {
+                continue
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => metadata,
+                _ => continue,
+            };
+            let mtime = metadata.modified().ok()?;
+            let newer = match &last {
+                Some((last, _)) => mtime > *last,
+                None => true,
+            };
+            if newer {
+                last = Some((mtime, name))
+            }
+        }
+        let (_, last) = last?;
+        let last_dir = self.pkgdir.join(&last);
+        let last_metadata = self.pkgdir.join(format!("{}.json", last));
+        let packages = if last_metadata.exists() {
+            package::read_metadata(&last_metadata).ok()?
+        } else {
+            Package::from_dir(&last_dir).ok()?
+        };
+        if packages.is_empty() {
+            return None
+        }
+        let mut needed: Vec<String> = packages.into_iter().flat_map(
+            |package| package.sonames).collect();
+        needed.sort_unstable();
+        needed.dedup();
+        let (_, pkgid) = self.soname_dephash(
+            dephash_strategy, wanted_pkgids, Some(needed.clone()));
+        if pkgid != last {
+            let dir = self.pkgdir.join(&pkgid);
+            if dir.exists() {
+                return None
+            }
+            if let Err(e) = rename(&last_dir, &dir) {
+                log::warn!("Failed to take over '{}' as '{}': {}",
+                    last_dir.display(), dir.display(), e);
+                return None
+            }
+            if last_metadata.exists() {
+                let _ = rename(&last_metadata,
+                    self.pkgdir.join(format!("{}.json", pkgid)));
+            }
+            log::info!("PKGBUILD '{}' took over '{}' as '{}' for the soname \
+                strategy", self.base, last, pkgid);
+        }
+        Some(Linked {
+            pkgid,
+            hashed: Some(needed.clone()),
+            needed,
+        })
+    }
+
+    /// The wanted pkgids should all have been filled. With the soname
+    /// strategy, the sonames hashed for the last build are kept as long as it
+    /// stays valid, so its pkgid does not change just because what it really
+    /// links against was only known after building it; once it needs to be
+    /// rebuilt anyway, only those really linked against are hashed
+    fn fill_id_dir(
+        &mut self, dephash_strategy: &DepHashStrategy, wanted_pkgids: &[String],
+        sonames: Option<&mut Sonames>
+    ) {
+        let linked = match sonames {
+            Some(sonames) => {
+                if sonames.get(&self.base).is_none() {
+                    if let Some(linked) =
+                        self.seed_linked(dephash_strategy, wanted_pkgids)
+                    {
+                        sonames.record(&self.base, linked)
+                    }
+                }
+                sonames.get(&self.base)
+            },
+            None => None,
+        };
+        let (mut dephash, mut pkgid) = match linked {
+            Some(linked) => self.soname_dephash(
+                dephash_strategy, wanted_pkgids, linked.hashed.clone()),
+            None => {
+                let dephash = self.dephash(dephash_strategy, wanted_pkgids);
+                (dephash, self.compose_id(dephash))
+            },
+        };
+        if let Some(linked) = linked {
+            if linked.pkgid != pkgid {
+                (dephash, pkgid) = self.soname_dephash(dephash_strategy,
+                    wanted_pkgids, Some(linked.needed.clone()));
+            }
+            log::info!("PKGBUILD '{}' dephash from sonames {:?}", self.base,
+                self.depends.sonames);
+        }
+        if let Some(dephash) = dephash {
+            if dephash != self.depends.hash {
+                log::info!("PKGBUILD '{}' dephash {:016x} after folding \
+                    pkgids of wanted PKGBUILDs: {:?}", self.base, dephash,
+                    wanted_pkgids);
+            }
+            self.depends.hash = dephash
+        }
+        let _scope = logger::package(&self.base, "pkgid");
         self.pkgdir.push(&pkgid);
         self.pkgid = pkgid;
         log::info!("PKGBUILD '{}' pkgid is '{}'", self.base, self.pkgid);
     }
 
+    /// pkgs/[pkgid].json, next to the pkgdir
+    fn metadata_path(&self) -> PathBuf {
+        self.pkgdir.with_file_name(format!("{}.json", self.pkgid))
+    }
+
+    /// The recorded metadata, or for pkgdirs built before it's recorded, that
+    /// of the archives in the pkgdir, which is then recorded
+    fn metadata_or_inspect(&self) -> Result<Vec<Package>> {
+        let path = self.metadata_path();
+        if path.exists() {
+            return package::read_metadata(&path)
+        }
+        log::info!("No metadata recorded for '{}', reading its packages",
+            self.pkgid);
+        let packages = Package::from_dir(&self.pkgdir)?;
+        if package::write_metadata(&path, &packages).is_err() {
+            log::warn!("Failed to record metadata of '{}', it would be read \
+                from its packages again", self.pkgid)
+        }
+        Ok(packages)
+    }
+
+    /// Entries of its packages in the index, from the recorded metadata
+    fn index_entries(&self, logs: &[String]) -> Result<Vec<IndexEntry>> {
+        let log = index::find_log(logs, &self.pkgid);
+        let changelog_path = self.pkgdir.join(changelog::file_name(&self.base));
+        let changelog = match changelog_path.exists() {
+            true => Changelog::from_file(&changelog_path).ok(),
+            false => None,
+        };
+        Ok(self.metadata_or_inspect()?.into_iter().map(
+            |package| {
+                let archive = self.pkgdir.join(&package.file);
+                let mut sig = archive.as_os_str().to_owned();
+                sig.push(".sig");
+                IndexEntry {
+                    size: archive.metadata().map(
+                        |metadata| metadata.len()).unwrap_or_default(),
+                    signed: Path::new(&sig).exists(),
+                    name: package.pkginfo.pkgname,
+                    version: package.pkginfo.pkgver,
+                    arch: package.pkginfo.arch,
+                    file: package.file,
+                    installed_size: package.pkginfo.size,
+                    builddate: package.pkginfo.builddate,
+                    pkgid: self.pkgid.clone(),
+                    pkgbase: self.base.clone(),
+                    url: self.url.clone(),
+                    commit: self.revision.to_string(),
+                    log: log.clone(),
+                    changelog: changelog.clone(),
+                }
+            }).collect())
+    }
+
+    /// What the packages built just now link against, for the soname strategy
+    pub(crate) fn read_linked(&self) -> Result<Linked> {
+        let mut needed: Vec<String> = package::read_metadata(
+            self.metadata_path())?.into_iter().flat_map(
+                |package| package.sonames).collect();
+        needed.sort_unstable();
+        needed.dedup();
+        Ok(Linked {
+            pkgid: self.pkgid.clone(),
+            hashed: self.depends.sonames.clone(),
+            needed,
+        })
+    }
+
+    /// What this is built from, to be recorded after building it
+    pub(crate) fn to_built(&self) -> Built {
+        Built {
+            pkgid: self.pkgid.clone(),
+            commit: self.revision.to_string(),
+            version: self.version.clone(),
+        }
+    }
+
+    /// The changes since the last build into the pkgdir, so it's linked and
+    /// published along with the packages
+    fn write_changelog(&self, previous: Option<&Built>) -> Result<()> {
+        let repo = git::Repo::open_bare(&self.git, &self.url, None)?;
+        let changelog = Changelog::new(&repo, &self.base, &self.pkgid,
+            self.revision, &self.version, self.subtree.as_deref(), previous)?;
+        changelog.to_file(self.pkgdir.join(changelog::file_name(&self.base)))?;
+        log::info!("Changelog of '{}': {}", self.pkgid, changelog.describe());
+        Ok(())
+    }
+
     pub(crate) fn get_temp_pkgdir(&self) -> Result<PathBuf> {
         let mut temp_name = self.pkgid.clone();
         temp_name.push_str(".temp");
@@ -392,16 +935,16 @@ impl PKGBUILD {
         }
     }
 
-    pub(crate) fn get_build_command(
-        &self,
-        actual_identity: &IdentityActual,
-        temp_pkgdir: &Path
+    /// makepkg running in the chroot, with args common to building and
+    /// checking
+    fn get_makepkg_command(
+        &self, actual_identity: &IdentityActual, target: &Target,
+        ccache: Option<&Ccache>, role: &str
     )
         -> Result<Command>
     {
         let cwd = actual_identity.cwd();
         let cwd_no_root = actual_identity.cwd_no_root()?;
-        let pkgdest = cwd.join(temp_pkgdir);
         let root = OverlayRoot::get_root_no_init(&self.base);
         let mut builder = cwd.join(&root);
         builder.push(cwd_no_root);
@@ -410,24 +953,74 @@ impl PKGBUILD {
         let mut command = Command::new("/bin/bash");
         command
             .current_dir(&builder)
-            .arg0(format!("[BUILDER/{}] /bin/bash", self.pkgid))
+            .arg0(format!("[{}/{}] /bin/bash", role, self.pkgid))
             .arg("--login")
             .arg("/usr/bin/makepkg")
             .arg("--holdver")
             .arg("--nodeps")
             .arg("--noextract")
-            .arg("--ignorearch")
-            .arg("--nosign")
-            .env("PKGDEST", &pkgdest);
+            .arg("--nosign");
+        // Only respect arch=() if the target arch is explicitly set
+        if target.arch.is_none() {
+            command.arg("--ignorearch");
+        }
         actual_identity.set_root_chroot_drop_command(&mut command, chroot);
+        self.makepkg.set_command(&mut command);
+        if let Some(ccache) = ccache {
+            ccache.set_command(&mut command, &cwd.join(&self.build));
+        }
         command.env_remove("PATH");
         Ok(command)
     }
 
+    pub(crate) fn get_build_command(
+        &self,
+        actual_identity: &IdentityActual,
+        target: &Target,
+        ccache: Option<&Ccache>,
+        temp_pkgdir: &Path
+    )
+        -> Result<Command>
+    {
+        let mut command = self.get_makepkg_command(
+            actual_identity, target, ccache, "BUILDER")?;
+        match self.check {
+            Some(CheckMode::Run) => { command.arg("--check"); },
+            Some(CheckMode::Skip) | Some(CheckMode::AllowFailure) => {
+                command.arg("--nocheck");
+            },
+            None => (),
+        }
+        command.env("PKGDEST", actual_identity.cwd().join(temp_pkgdir));
+        Ok(command)
+    }
+
+    /// The command to run check() on its own after a successful build, only
+    /// if check is allow-failure. This is a full re-run of build(), check()
+    /// and package() on the existing build dir, only without creating the
+    /// archive, so it's as heavy as the build itself
+    pub(crate) fn get_check_command(
+        &self, actual_identity: &IdentityActual, target: &Target,
+        ccache: Option<&Ccache>
+    )
+        -> Result<Option<Command>>
+    {
+        if self.check != Some(CheckMode::AllowFailure) {
+            return Ok(None)
+        }
+        let mut command = self.get_makepkg_command(
+            actual_identity, target, ccache, "CHECKER")?;
+        command
+            .arg("--noprepare")
+            .arg("--check")
+            .arg("--noarchive");
+        Ok(Some(command))
+    }
+
     pub(crate) fn link_pkgs(&self) -> Result<()> {
         let mut rel = PathBuf::from("..");
         rel.push(&self.pkgid);
-        let updated = PathBuf::from("pkgs/updated");
+        let updated = self.pkgdir.with_file_name("updated");
         // let mut bad = false;
         let readdir = match self.pkgdir.read_dir() {
             Ok(readdir) => readdir,
@@ -457,8 +1050,60 @@ impl PKGBUILD {
         r
     }
 
+    /// Read all packages makepkg produced, and reject them if there's none,
+    /// or any of them is not sane or not one of our pkgnames
+    pub(crate) fn check_packages(&self, temp_pkgdir: &Path) -> Result<Vec<Package>> {
+        let packages = Package::from_dir(temp_pkgdir)?;
+        if packages.is_empty() {
+            log::error!("No package was built for '{}'", self.pkgid);
+            return Err(Error::BadPackages(vec![]))
+        }
+        let mut bad = vec![];
+        for package in packages.iter() {
+            let problem = match package.problem() {
+                Some(problem) => Some(problem),
+                // makepkg splits debug symbols into [pkgbase]-debug
+                None => match self.names.is_empty() ||
+                    self.names.contains(&package.pkginfo.pkgname) ||
+                    package.pkginfo.pkgname ==
+                        format!("{}-debug", package.pkginfo.pkgbase)
+                {
+                    true => None,
+                    false => Some(format!("pkgname '{}' is not any of {:?}",
+                        package.pkginfo.pkgname, self.names)),
+                },
+            };
+            if let Some(problem) = problem {
+                log::error!("Package '{}' built for '{}' is bad: {}",
+                    package.file, self.pkgid, problem);
+                bad.push(package.file.clone())
+            }
+        }
+        if bad.is_empty() {
+            Ok(packages)
+        } else {
+            Err(Error::BadPackages(bad))
+        }
+    }
+
+    /// Run the lint rules over the checked packages, in the root they were
+    /// built in, with levels of this package on top of the global ones
+    pub(crate) fn lint_packages(&self, packages: &[Package],
+        lint_global: Option<&Lint>, target: &Target
+    )
+        -> Result<Vec<Finding>>
+    {
+        let lint = match &self.lint {
+            Some(lint) => lint.on_top_of(lint_global),
+            None => lint_global.cloned().unwrap_or_default(),
+        };
+        lint::lint(packages, &lint, &self.base, &self.pkgid, &self.arches,
+            target, &OverlayRoot::get_root_no_init(&self.base))
+    }
+
     pub(crate) fn finish_build(&self,
-        actual_identity: &IdentityActual, temp_pkgdir: &Path, sign: Option<&str>
+        actual_identity: &IdentityActual, temp_pkgdir: &Path,
+        packages: &[Package], sign: Option<&str>, previous: Option<&Built>
     )
         -> Result<()>
     {
@@ -477,6 +1122,11 @@ impl PKGBUILD {
                 '{}': {}", temp_pkgdir.display(), self.pkgdir.display(), e);
             return Err(e.into())
         }
+        package::write_metadata(self.metadata_path(), packages)?;
+        if self.write_changelog(previous).is_err() {
+            log::warn!("Failed to write changelog of '{}', its packages are \
+                still kept", self.pkgid)
+        }
         self.link_pkgs()?;
         log::info!("Finished building '{}'", &self.pkgid);
         Ok(())
@@ -511,20 +1161,48 @@ impl PKGBUILD {
         binds
     }
 
+    /// The distcc to use, None if disabled or opted out
+    pub(crate) fn distcc<'a>(&self, distcc: Option<&'a Distcc>)
+        -> Option<&'a Distcc>
+    {
+        distcc.filter(|_| ! self.makepkg.disables_buildenv("distcc"))
+    }
+
+    fn get_makepkg_conf_append(
+        &self, ccache: Option<&Ccache>, distcc: Option<&Distcc>
+    ) -> String {
+        // Ours go first, so they could be overriden by the user
+        let mut append = String::new();
+        if let Some(ccache) = ccache {
+            append.push_str(ccache.conf_append())
+        }
+        if let Some(distcc) = self.distcc(distcc) {
+            append.push_str(&distcc.conf_append())
+        }
+        append.push_str(&self.makepkg.conf_append());
+        append
+    }
+
     pub(crate) fn _get_overlay_root(
-        &self, actual_identity: &IdentityActual, nonet: bool
+        &self, actual_identity: &IdentityActual, target: &Target,
+        ccache: Option<&Ccache>, distcc: Option<&Distcc>, nonet: bool
     ) -> Result<OverlayRoot>
     {
-        OverlayRoot::_new(&self.base, actual_identity,
-            &self.depends.needs, self.get_home_binds(), nonet)
+        OverlayRoot::_new(&self.base, actual_identity, target,
+            &self.depends.needs, self.get_home_binds(),
+            &self.get_makepkg_conf_append(ccache, distcc),
+            ccache.map(|ccache| ccache.dir()), nonet)
     }
 
     pub(crate) fn get_bootstrapping_overlay_root(
-        &self, actual_identity: &IdentityActual, nonet: bool
+        &self, actual_identity: &IdentityActual, target: &Target,
+        ccache: Option<&Ccache>, distcc: Option<&Distcc>, nonet: bool
     ) -> Result<BootstrappingOverlayRoot>
     {
-        BootstrappingOverlayRoot::new(&self.base, actual_identity,
-            &self.depends.needs, self.get_home_binds(), nonet)
+        BootstrappingOverlayRoot::new(&self.base, actual_identity, target,
+            &self.depends.needs, self.get_home_binds(),
+            &self.get_makepkg_conf_append(ccache, distcc),
+            ccache.map(|ccache| ccache.dir()), nonet)
     }
 }
 
@@ -533,41 +1211,117 @@ pub(crate) struct PKGBUILDs (pub(crate) Vec<PKGBUILD>);
 
 impl PKGBUILDs {
     pub(crate) fn from_config(
-        config: &HashMap<String, PkgbuildConfig>, home_binds_global: &Vec<String>
+        config: &HashMap<String, PkgbuildConfig>, home_binds_global: &Vec<String>,
+        makepkg_global: &Makepkg, target: &Target
     )
         -> Result<Self>
     {
         let build_parent = PathBuf::from("build");
         let git_parent = PathBuf::from("sources/PKGBUILD");
-        let mut pkgbuilds: Vec<_> = config.iter().map(|
-            (name, detail)|
-        {
-            match detail {
+        let pkgs_parent = target.pkgs_dir();
+        let mut pkgbuilds = vec![];
+        for (name, detail) in config.iter() {
+            pkgbuilds.push(match detail {
                 PkgbuildConfig::Simple(url) => PKGBUILD::new(
-                    name, url, &build_parent, &git_parent,
-                    None, None, None, None,
-                    None, home_binds_global
+                    name, url, &build_parent, &git_parent, &pkgs_parent,
+                    PkgbuildOrigin::from_url(url),
+                    git::Reference::Branch(String::from("master")), None,
+                    vec![], None, None, None, home_binds_global,
+                    makepkg_global.clone(), None, None, None
                 ),
-                PkgbuildConfig::Complex { url, branch,
-                    subtree, deps,
+                PkgbuildConfig::Complex { url, origin, branch,
+                    tag, commit,
+                    subtree, patches, deps,
                     makedeps,
-                    home_binds,binds: _
-                } => PKGBUILD::new(
-                    name, url, &build_parent, &git_parent,
-                    branch.as_deref(), subtree.as_deref(),
-                    deps.as_ref(), makedeps.as_ref(), home_binds.as_ref(), home_binds_global)
-            }
-        }).collect();
+                    home_binds,binds: _,
+                    env, makeflags, cflags, cxxflags, options, buildenv,
+                    makepkg_conf, check, rebuild_on_dep_change, lint
+                } => {
+                    let origin = match origin {
+                        Some(origin) => origin.clone(),
+                        None => PkgbuildOrigin::from_url(url),
+                    };
+                    if origin != PkgbuildOrigin::Git && (branch.is_some() ||
+                        tag.is_some() || commit.is_some())
+                    {
+                        log::error!("PKGBUILD '{}' is not from git, could not \
+                            set branch, tag or commit", name);
+                        return Err(Error::InvalidConfig)
+                    }
+                    PKGBUILD::new(
+                        name, url, &build_parent, &git_parent, &pkgs_parent,
+                        origin,
+                        Self::reference_from_config(name, branch, tag, commit)?,
+                        subtree.as_deref(),
+                        match patches {
+                            Some(patches) => patch::load(name, patches)?,
+                            None => vec![],
+                        },
+                        deps.as_ref(), makedeps.as_ref(), home_binds.as_ref(),
+                        home_binds_global,
+                        Makepkg::from_config(env, makeflags, cflags, cxxflags,
+                            options, buildenv, makepkg_conf)?.on_top_of(makepkg_global),
+                        check.clone(), *rebuild_on_dep_change, lint.clone())
+                }
+            })
+        }
+        for pkgbuild in pkgbuilds.iter_mut() {
+            pkgbuild.carch = target.name().to_string()
+        }
         pkgbuilds.sort_unstable_by(
             |a, b| a.base.cmp(&b.base));
         Ok(Self(pkgbuilds))
     }
 
-    fn sync(&self, hold: bool, proxy: Option<&Proxy>, gmr: Option<&Gmr>, terminal: bool)
+    fn reference_from_config(
+        name: &str, branch: &Option<String>, tag: &Option<String>,
+        commit: &Option<String>
+    ) -> Result<git::Reference>
+    {
+        match (branch, tag, commit) {
+            (None, None, None) =>
+                Ok(git::Reference::Branch(String::from("master"))),
+            (Some(branch), None, None) =>
+                Ok(git::Reference::Branch(branch.clone())),
+            (None, Some(tag), None) => Ok(git::Reference::Tag(tag.clone())),
+            (None, None, Some(commit)) =>
+                Ok(git::Reference::Commit(commit.clone())),
+            _ => {
+                log::error!("PKGBUILD '{}' could only set one of branch, tag \
+                    and commit", name);
+                Err(Error::InvalidConfig)
+            },
+        }
+    }
+
+    fn sync(&self, hold: bool, proxy: Option<&Proxy>, gmr: Option<&Gmr>,
+        scheduler: &Scheduler, terminal: bool)
         -> Result<()>
     {
+        // Commits never move, only fetch for them if they're missing
+        let mut pkgbuilds = vec![];
+        for pkgbuild in self.0.iter() {
+            if pkgbuild.is_immutable() && pkgbuild.healthy_get_commit().is_ok() {
+                continue
+            }
+            if pkgbuild.origin == PkgbuildOrigin::Git {
+                pkgbuilds.push(pkgbuild.clone());
+                continue
+            }
+            if hold && pkgbuild.healthy_get_commit().is_ok() {
+                continue
+            }
+            if let Err(e) = pkgbuild.import(proxy) {
+                log::error!("Failed to import PKGBUILD '{}' from '{}'",
+                    pkgbuild.base, pkgbuild.url);
+                return Err(e)
+            }
+        }
+        if pkgbuilds.is_empty() {
+            return Ok(())
+        }
         let map =
-            PKGBUILD::map_by_domain(&self.0);
+            PKGBUILD::map_by_domain(&pkgbuilds);
         let repos_map =
             match git::ToReposMap::to_repos_map(
                 map, "sources/PKGBUILD", gmr)
@@ -578,32 +1332,77 @@ impl PKGBUILDs {
                 return Err(e.into())
             },
         };
-        git::Repo::sync_mt(repos_map, hold, proxy, terminal)
+        git::Repo::sync_mt(repos_map, hold, proxy, scheduler, terminal)
     }
 
     fn healthy_set_commit(&mut self) -> Result<()> {
         let mut broken = vec![];
+        let mut missing = vec![];
+        let mut broken_patches = vec![];
         for pkgbuild in self.0.iter_mut() {
-            if let Err(e) = pkgbuild.healthy_set_commit() {
-                if let Error::BrokenPKGBUILDs(mut pkgbuilds) = e {
-                    broken.append(&mut pkgbuilds)
-                }
+            let _scope = logger::package(&pkgbuild.base, "sync");
+            match pkgbuild.healthy_set_commit() {
+                Err(Error::BrokenPKGBUILDs(mut pkgbuilds)) =>
+                    broken.append(&mut pkgbuilds),
+                Err(Error::ReferenceMissing(mut refs)) =>
+                    missing.append(&mut refs),
+                Err(Error::BrokenPatches(mut patches)) =>
+                    broken_patches.append(&mut patches),
+                _ => (),
             }
         }
-        if broken.is_empty() {
-            Ok(())
-        } else {
+        // Only one error is returned, so all that went wrong are logged
+        if ! broken.is_empty() {
+            log::error!("Broken PKGBUILDs: {:?}", broken)
+        }
+        if ! missing.is_empty() {
+            log::error!("Missing references: {:?}", missing)
+        }
+        if ! broken_patches.is_empty() {
+            log::error!("Broken patches: {:?}", broken_patches)
+        }
+        if ! broken.is_empty() {
             Err(Error::BrokenPKGBUILDs(broken))
+        } else if ! missing.is_empty() {
+            Err(Error::ReferenceMissing(missing))
+        } else if ! broken_patches.is_empty() {
+            Err(Error::BrokenPatches(broken_patches))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn apply_lock(&mut self, lock: &Lock) -> Result<()> {
+        let mut r = Ok(());
+        for pkgbuild in self.0.iter_mut() {
+            match lock.get_pkgbuild(&pkgbuild.base) {
+                Ok(locked) => if let Err(e) = pkgbuild.apply_lock(locked) {
+                    r = Err(e)
+                },
+                Err(e) => r = Err(e),
+            }
         }
+        r
     }
 
     pub(crate) fn from_config_healthy(
         config: &HashMap<String, PkgbuildConfig>,
         hold: bool, noclean: bool, proxy: Option<&Proxy>, gmr: Option<&Gmr>,
-        home_binds: &Vec<String>, terminal: bool
+        home_binds: &Vec<String>, makepkg: &Makepkg, target: &Target,
+        scheduler: &Scheduler, lock: Option<&Lock>, terminal: bool
     ) -> Result<Self>
     {
-        let mut pkgbuilds = Self::from_config(config, home_binds)?;
+        let mut pkgbuilds = Self::from_config(
+            config, home_binds, makepkg, target)?;
+        // Locked PKGBUILDs only need update when the locked commits are
+        // missing, and in that case the repos could still look healthy
+        let (hold, hold_sync) = match lock {
+            Some(lock) => {
+                pkgbuilds.apply_lock(lock)?;
+                (true, false)
+            },
+            None => (hold, hold),
+        };
         let update_pkg = if hold {
             if let Err(e) = pkgbuilds.healthy_set_commit() {
                 log::error!("Warning: holdpkg set, but PKGBUILDs unhealthy, \
@@ -628,7 +1427,8 @@ impl PKGBUILDs {
                         source::remove_unused("sources/PKGBUILD", &used))),
         };
         if update_pkg {
-            if let Err(e) = pkgbuilds.sync(hold, proxy, gmr, terminal) {
+            if let Err(e) = pkgbuilds.sync(
+                hold_sync, proxy, gmr, scheduler, terminal) {
                 log::error!("Failed to sync PKGBUILDs: {}", e);
                 return Err(e)
             }
@@ -658,9 +1458,9 @@ impl PKGBUILDs {
         r
     }
 
-    fn get_deps<P: AsRef<Path>> (
-        &mut self, actual_identity: &IdentityActual, dir: P, db_handle: &DbHandle,
-        dephash_strategy: &DepHashStrategy
+    /// Read deps, makedeps, pkgnames and provides of all PKGBUILDs
+    fn read_deps<P: AsRef<Path>> (
+        &mut self, actual_identity: &IdentityActual, dir: P
     ) -> Result<()>
     {
         let mut r = Ok(());
@@ -694,6 +1494,9 @@ impl PKGBUILDs {
         {
             let output = child.wait_with_output()
                 .expect("Failed to wait for child");
+            let mut epoch = String::new();
+            let mut pkgver = String::new();
+            let mut pkgrel = String::new();
             for line in
                 output.stdout.split(|byte| byte == &b'\n')
             {
@@ -705,13 +1508,68 @@ impl PKGBUILDs {
                 match &line[0..2] {
                     b"d:" => pkgbuild.depends.deps.push(dep),
                     b"m:" => pkgbuild.depends.makedeps.push(dep),
+                    b"e:" => epoch = dep,
+                    b"v:" => pkgver = dep,
+                    b"r:" => pkgrel = dep,
+                    b"a:" => pkgbuild.arches.push(dep),
+                    b"n:" => pkgbuild.names.push(dep),
+                    b"p:" => pkgbuild.provides.push(dep),
                     _ => ()
                 }
             }
+            if let Pkgver::Func { pkgver: pkgver_func } = &pkgbuild.pkgver {
+                pkgver = pkgver_func.clone()
+            }
+            pkgbuild.version = match epoch.is_empty() {
+                true => format!("{}-{}", pkgver, pkgrel),
+                false => format!("{}:{}-{}", epoch, pkgver, pkgrel),
+            };
             pkgbuild.depends.deps.sort_unstable();
             pkgbuild.depends.makedeps.sort_unstable();
+            pkgbuild.provides.sort_unstable();
             pkgbuild.depends.deps.dedup();
             pkgbuild.depends.makedeps.dedup();
+            pkgbuild.provides.dedup();
+        }
+        Ok(())
+    }
+
+    /// Fail if any versioned dep names another PKGBUILD's pkgname or provide
+    /// but is satisfied by none of the PKGBUILDs, as the one built from here
+    /// would not be installable anyway
+    fn check_constraints(&self) -> Result<()> {
+        let mut r = Ok(());
+        for pkgbuild in self.0.iter() {
+            let others = || self.0.iter().filter(
+                |other| ! std::ptr::eq(pkgbuild, *other));
+            for pkgbuild_target in others() {
+                for dep in pkgbuild.depends.unsatisfied_by(
+                    &pkgbuild_target.offer())
+                {
+                    if others().any(|other| other.offer().satisfies(dep)) {
+                        continue
+                    }
+                    log::error!("Dep '{}' of PKGBUILD '{}' can not be \
+                        satisfied by PKGBUILD '{}' at version '{}' with \
+                        pkgnames {:?} and provides {:?}", dep, pkgbuild.base,
+                        pkgbuild_target.base, pkgbuild_target.version,
+                        pkgbuild_target.names, pkgbuild_target.provides);
+                    r = Err(Error::InvalidConfig)
+                }
+            }
+        }
+        r
+    }
+
+    fn get_deps<P: AsRef<Path>> (
+        &mut self, actual_identity: &IdentityActual, dir: P, db_handle: &DbHandle,
+        dephash_strategy: &DepHashStrategy
+    ) -> Result<()>
+    {
+        self.read_deps(actual_identity, dir)?;
+        self.check_constraints()?;
+        let mut r = Ok(());
+        for pkgbuild in self.0.iter_mut() {
             match pkgbuild.depends.needed_and_hash(
                 db_handle, dephash_strategy)
             {
@@ -739,10 +1597,10 @@ impl PKGBUILDs {
 
     fn check_deps<P: AsRef<Path>> (
         &mut self, actual_identity: &IdentityActual, dir: P, root: P,
-        dephash_strategy: &DepHashStrategy
+        target: &Target, dephash_strategy: &DepHashStrategy
     )   -> Result<()>
     {
-        let db_handle = DbHandle::new(root)?;
+        let db_handle = DbHandle::new(root, target.pacman_conf())?;
         self.get_deps(actual_identity, dir, &db_handle, dephash_strategy)
     }
 
@@ -907,7 +1765,7 @@ impl PKGBUILDs {
         let mut r = Ok(());
         for pkgbuild in pkgbuilds.iter_mut() {
             match pkgbuild.extractor_source(actual_identity) {
-                Ok(child) => children.push(child),
+                Ok((child, _)) => children.push(child),
                 Err(e) => {
                     log::error!("Failed to spawn source extractor: {}", e);
                     r = Err(e)
@@ -953,6 +1811,7 @@ impl PKGBUILDs {
                 .expect("Failed to wait for child");
             let pkgver = String::from_utf8_lossy(&output.stdout)
                 .trim().to_string();
+            let _scope = logger::package(&pkgbuild.base, "pkgver");
             log::info!("PKGBUILD '{}' pkgver is '{}'", &pkgbuild.base, &pkgver);
             pkgbuild.pkgver = Pkgver::Func { pkgver };
             pkgbuild.extracted = true
@@ -960,10 +1819,43 @@ impl PKGBUILDs {
         Ok(())
     }
 
-    fn fill_all_ids_dirs(&mut self, dephash_strategy: &DepHashStrategy) {
-        for pkgbuild in self.0.iter_mut() {
-            pkgbuild.fill_id_dir(dephash_strategy)
+    /// Wanted PKGBUILDs get their pkgids first if any PKGBUILD cascades, so
+    /// a rebuild of one leads to rebuilds of all those wanting it
+    fn fill_all_ids_dirs(
+        &mut self, dephash_strategy: &DepHashStrategy,
+        mut sonames: Option<&mut Sonames>
+    ) -> Result<()>
+    {
+        if ! self.0.iter().any(|pkgbuild|
+            pkgbuild.cascades(dephash_strategy))
+        {
+            for pkgbuild in self.0.iter_mut() {
+                pkgbuild.fill_id_dir(
+                    dephash_strategy, &[], sonames.as_deref_mut())
+            }
+            return Ok(())
         }
+        let (sorted, wants) = {
+            let graph = DepGraph::from_pkgbuilds(self)?;
+            let sorted = match graph.sorted() {
+                Ok(sorted) => sorted,
+                Err(e) => {
+                    log::error!("Could not cascade rebuilds through PKGBUILDs \
+                        wanting each other in cycles");
+                    return Err(e)
+                },
+            };
+            let wants: Vec<Vec<usize>> = graph.nodes.iter().map(|node|
+                node.wants.iter().map(|edge| edge.id).collect()).collect();
+            (sorted, wants)
+        };
+        for id in sorted {
+            let wanted_pkgids: Vec<String> = wants[id].iter().map(
+                |wanted| self.0[*wanted].pkgid.clone()).collect();
+            self.0[id].fill_id_dir(
+                dephash_strategy, &wanted_pkgids, sonames.as_deref_mut())
+        }
+        Ok(())
     }
 
     fn check_if_need_build(&mut self)
@@ -1010,6 +1902,48 @@ impl PKGBUILDs {
         r
     }
 
+    fn check_locked(&self, lock: &Lock) -> Result<()> {
+        let mut r = Ok(());
+        for pkgbuild in self.0.iter() {
+            match lock.get_pkgbuild(&pkgbuild.base) {
+                Ok(locked) => if let Err(e) = pkgbuild.check_locked(locked) {
+                    r = Err(e)
+                },
+                Err(e) => r = Err(e),
+            }
+        }
+        r
+    }
+
+    /// Record the current state of all PKGBUILDs into the lock, existing
+    /// entries of PKGBUILDs not in this run are kept as-is
+    pub(crate) fn update_lock(&self, lock: &mut Lock) -> Result<()> {
+        for pkgbuild in self.0.iter() {
+            lock.pkgbuilds.insert(pkgbuild.base.clone(), pkgbuild.to_locked());
+        }
+        let git_sources = self.0.iter().flat_map(
+            |pkgbuild| pkgbuild.sources.iter()).filter(
+                |source| source.is_git());
+        lock.gits.append(&mut git::lock_sources(git_sources)?);
+        Ok(())
+    }
+
+    /// Only read what's needed for the dependency graph, without caching
+    /// sources or touching the roots
+    pub(crate) fn read_graph(&mut self, actual_identity: &IdentityActual)
+        -> Result<()>
+    {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => {
+                log::error!("Failed to create temp dir to dump PKGBUILDs: {}", e);
+                return Err(e.into())
+            },
+        };
+        self.dump(&dir)?;
+        self.read_deps(actual_identity, &dir)
+    }
+
     pub(crate) fn prepare_sources(
         &mut self,
         actual_identity: &IdentityActual,
@@ -1020,6 +1954,9 @@ impl PKGBUILDs {
         proxy: Option<&Proxy>,
         gmr: Option<&git::Gmr>,
         dephash_strategy: &DepHashStrategy,
+        target: &Target,
+        scheduler: &Scheduler,
+        lock: Option<&Lock>,
         terminal: bool
     ) -> Result<Option<BaseRoot>>
     {
@@ -1042,7 +1979,11 @@ impl PKGBUILDs {
             = self.get_all_sources(&dir)?;
         source::cache_sources_mt(
             &netfile_sources, &git_sources, actual_identity,
-            holdgit, skipint, proxy, gmr, terminal)?;
+            holdgit || lock.is_some(), skipint, proxy, gmr, scheduler,
+            terminal)?;
+        if let Some(lock) = lock {
+            git::pin_sources(&git_sources, lock, proxy, gmr, terminal)?;
+        }
         if let Some(cleaner) = cleaner {
             match cleaner.join() {
                 Ok(r) => if let Err(e) = r {
@@ -1061,11 +2002,22 @@ impl PKGBUILDs {
         };
         self.fill_all_pkgvers(actual_identity, &dir)?;
         // Use the fresh DBs in target root
-        let base_root = BaseRoot::db_only()?;
+        let base_root = BaseRoot::db_only(target)?;
         self.check_deps(
-            actual_identity, dir.as_ref(), base_root.path(),
+            actual_identity, dir.as_ref(), base_root.path(), target,
             dephash_strategy)?;
-        self.fill_all_ids_dirs(dephash_strategy);
+        let mut sonames = match dephash_strategy {
+            DepHashStrategy::Soname =>
+                Some(Sonames::from_file_or_default(SONAMESFILE)),
+            _ => None,
+        };
+        self.fill_all_ids_dirs(dephash_strategy, sonames.as_mut())?;
+        if let Some(sonames) = &sonames {
+            let _ = sonames.to_file(SONAMESFILE);
+        }
+        if let Some(lock) = lock {
+            self.check_locked(lock)?;
+        }
         let need_builds = self.check_if_need_build()? > 0;
         if need_builds {
             let mut all_deps = vec![];
@@ -1082,9 +2034,10 @@ impl PKGBUILDs {
             }
             all_deps.sort_unstable();
             all_deps.dedup();
-            Depends::cache_raw(&all_deps, base_root.db_path())?;
-            base_root.finish(actual_identity, basepkgs)?;
-            let db_handle = DbHandle::new(base_root.path())?;
+            Depends::cache_raw(&all_deps, base_root.db_path(), target)?;
+            base_root.finish(actual_identity, target, basepkgs)?;
+            let db_handle = DbHandle::new(
+                base_root.path(), target.pacman_conf())?;
             for pkgbuild in self.0.iter_mut() {
                 if pkgbuild.need_build {
                     pkgbuild.depends.update_needed(&db_handle);
@@ -1104,18 +2057,25 @@ impl PKGBUILDs {
         }
     }
 
-    pub(crate) fn clean_pkgdir(&self) {
-        let mut used: Vec<String> = self.0.iter().map(
-            |pkgbuild| pkgbuild.pkgid.clone()).collect();
+    pub(crate) fn clean_pkgdir(&self, target: &Target) {
+        let mut used: Vec<String> = self.0.iter().flat_map(
+            |pkgbuild| [pkgbuild.pkgid.clone(),
+                format!("{}.json", pkgbuild.pkgid)]).collect();
         used.push(String::from("updated"));
         used.push(String::from("latest"));
+        // Runs without arch share pkgs/ with the per-arch subdirs
+        if target.arch.is_none() {
+            for arch in crate::arch::supported() {
+                used.push(arch.to_string())
+            }
+        }
         used.sort_unstable();
-        source::remove_unused("pkgs", &used);
+        source::remove_unused(target.pkgs_dir(), &used);
     }
 
-    pub(crate) fn link_pkgs(&self) {
+    pub(crate) fn link_pkgs(&self, target: &Target) {
         let rel = PathBuf::from("..");
-        let latest = PathBuf::from("pkgs/latest");
+        let latest = target.pkgs_dir().join("latest");
         for pkgbuild in self.0.iter() {
             if ! pkgbuild.pkgdir.exists() {
                 continue;
@@ -1140,5 +2100,34 @@ impl PKGBUILDs {
                 }
             }
         }
+        self.write_index(&latest)
+    }
+
+    /// Regenerate the index of what's linked into latest, packages without
+    /// usable metadata are left out
+    fn write_index(&self, latest: &Path) {
+        let logs: Vec<String> = match Path::new("logs").read_dir() {
+            Ok(readdir) => readdir.filter_map(|entry| entry.ok().map(
+                |entry| entry.file_name().to_string_lossy().into_owned()))
+                .collect(),
+            Err(e) => {
+                log::warn!("Failed to read logs dir, index would have no \
+                    logs: {}", e);
+                vec![]
+            },
+        };
+        let mut entries = vec![];
+        for pkgbuild in self.0.iter() {
+            if ! pkgbuild.pkgdir.exists() {
+                continue
+            }
+            match pkgbuild.index_entries(&logs) {
+                Ok(mut pkgbuild_entries) =>
+                    entries.append(&mut pkgbuild_entries),
+                Err(_) => log::warn!("Packages of '{}' are left out of the \
+                    index as their metadata is unusable", pkgbuild.pkgid),
+            }
+        }
+        let _ = index::write_index(latest, entries);
     }
 }
\ No newline at end of file
@@ -0,0 +1,162 @@
+// Environment and makepkg.conf overrides, global ones and per-PKGBUILD ones
+use std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        fs::read_to_string,
+        process::Command,
+    };
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::error::Result;
+
+#[derive(Clone, Default)]
+pub(crate) struct Makepkg {
+    env: BTreeMap<String, String>,
+    makeflags: Option<String>,
+    cflags: Option<String>,
+    cxxflags: Option<String>,
+    options: Vec<String>,
+    buildenv: Vec<String>,
+    /// Content of custom makepkg.conf fragments
+    conf: String,
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+impl Makepkg {
+    pub(crate) fn from_config(
+        env: &Option<HashMap<String, String>>, makeflags: &Option<String>,
+        cflags: &Option<String>, cxxflags: &Option<String>,
+        options: &Option<Vec<String>>, buildenv: &Option<Vec<String>>,
+        makepkg_conf: &Option<String>
+    ) -> Result<Self>
+    {
+        let conf = match makepkg_conf {
+            Some(makepkg_conf) => match read_to_string(makepkg_conf) {
+                Ok(conf) => conf,
+                Err(e) => {
+                    log::error!("Failed to read makepkg.conf fragment '{}': {}",
+                        makepkg_conf, e);
+                    return Err(e.into())
+                },
+            },
+            None => String::new(),
+        };
+        Ok(Self {
+            env: match env {
+                Some(env) => env.iter().map(|(key, value)|
+                    (key.clone(), value.clone())).collect(),
+                None => BTreeMap::new(),
+            },
+            makeflags: makeflags.clone(),
+            cflags: cflags.clone(),
+            cxxflags: cxxflags.clone(),
+            options: match options {
+                Some(options) => options.clone(),
+                None => vec![],
+            },
+            buildenv: match buildenv {
+                Some(buildenv) => buildenv.clone(),
+                None => vec![],
+            },
+            conf,
+        })
+    }
+
+    /// Per-PKGBUILD overrides on top of global ones: env and flags of the
+    /// PKGBUILD win, options, buildenv and fragments are appended after global
+    /// ones
+    pub(super) fn on_top_of(&self, global: &Self) -> Self {
+        let mut env = global.env.clone();
+        for (key, value) in self.env.iter() {
+            env.insert(key.clone(), value.clone());
+        }
+        let mut options = global.options.clone();
+        options.extend_from_slice(&self.options);
+        let mut buildenv = global.buildenv.clone();
+        buildenv.extend_from_slice(&self.buildenv);
+        let mut conf = global.conf.clone();
+        conf.push_str(&self.conf);
+        Self {
+            env,
+            makeflags: self.makeflags.clone().or(global.makeflags.clone()),
+            cflags: self.cflags.clone().or(global.cflags.clone()),
+            cxxflags: self.cxxflags.clone().or(global.cxxflags.clone()),
+            options,
+            buildenv,
+            conf,
+        }
+    }
+
+    /// Lines to append to the makepkg.conf in the chroot, empty if nothing is
+    /// overriden
+    pub(super) fn conf_append(&self) -> String {
+        let mut append = String::new();
+        if let Some(makeflags) = &self.makeflags {
+            append.push_str(&format!("MAKEFLAGS={}\n", quote(makeflags)))
+        }
+        if let Some(cflags) = &self.cflags {
+            append.push_str(&format!("CFLAGS={}\n", quote(cflags)))
+        }
+        if let Some(cxxflags) = &self.cxxflags {
+            append.push_str(&format!("CXXFLAGS={}\n", quote(cxxflags)))
+        }
+        // makepkg takes the last one if an option appears multiple times
+        for (name, options) in [
+            ("OPTIONS", &self.options), ("BUILDENV", &self.buildenv)]
+        {
+            if options.is_empty() {
+                continue
+            }
+            append.push_str(name);
+            append.push_str("+=(");
+            for option in options.iter() {
+                append.push_str(&quote(option));
+                append.push(' ');
+            }
+            append.pop();
+            append.push_str(")\n");
+        }
+        append.push_str(&self.conf);
+        if append.is_empty() {
+            return append
+        }
+        format!("\n# Overrides appended by arb\n{}\n", append)
+    }
+
+    /// Whether a BUILDENV option is explicitly disabled, the last one wins
+    pub(super) fn disables_buildenv(&self, option: &str) -> bool {
+        let negated = format!("!{}", option);
+        self.buildenv.iter().rev().find(|buildenv|
+            *buildenv == option || **buildenv == negated)
+        .is_some_and(|buildenv| *buildenv == negated)
+    }
+
+    pub(super) fn is_default(&self) -> bool {
+        self.env.is_empty() && self.conf_append().is_empty()
+    }
+
+    /// Hash of everything that could affect the build, to be folded into pkgid
+    pub(super) fn hash(&self) -> u64 {
+        let mut buffer = vec![];
+        for (key, value) in self.env.iter() {
+            buffer.extend_from_slice(key.as_bytes());
+            buffer.push(b'=');
+            buffer.extend_from_slice(value.as_bytes());
+            buffer.push(b'\0');
+        }
+        buffer.extend_from_slice(self.conf_append().as_bytes());
+        xxh3_64(&buffer)
+    }
+
+    pub(super) fn set_command<'a>(&self, command: &'a mut Command)
+        -> &'a mut Command
+    {
+        command.envs(self.env.iter())
+    }
+}
@@ -0,0 +1,124 @@
+// PKGBUILDs not from git repos, they're imported into bare repos under
+// sources/PKGBUILD so the rest would handle them just like git ones
+use std::{
+        fs::{
+            create_dir,
+            read_dir,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        process::Command,
+    };
+
+use git2::Oid;
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        source::{
+            self,
+            git,
+            Proxy,
+        },
+    };
+
+pub(super) fn import_dir(repo: &git::Repo, dir: &Path) -> Result<Oid> {
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(e) => {
+            log::error!("Failed to canonicalize PKGBUILD dir '{}': {}",
+                dir.display(), e);
+            return Err(e.into())
+        },
+    };
+    if ! dir.is_dir() {
+        log::error!("PKGBUILD dir '{}' is not a dir", dir.display());
+        return Err(Error::InvalidConfig)
+    }
+    repo.import_dir(&dir)
+}
+
+/// Snapshots like those from cgit wrap everything in a single top-level dir,
+/// use that dir as root then
+fn snapshot_root(dir: &Path) -> Result<PathBuf> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Failed to read extracted dir '{}': {}",
+                dir.display(), e);
+            return Err(e.into())
+        },
+    };
+    let mut only = None;
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::error!("Failed to read entry from extracted dir '{}': {}",
+                    dir.display(), e);
+                return Err(e.into())
+            },
+        };
+        if only.is_some() {
+            return Ok(dir.to_owned())
+        }
+        only = Some(entry.path())
+    }
+    match only {
+        Some(only) => if only.is_dir() {
+            Ok(only)
+        } else {
+            Ok(dir.to_owned())
+        },
+        None => {
+            log::error!("Extracted dir '{}' is empty", dir.display());
+            Err(Error::InvalidConfig)
+        },
+    }
+}
+
+pub(super) fn import_tarball(
+    repo: &git::Repo, url: &str, proxy: Option<&Proxy>
+) -> Result<Oid>
+{
+    let temp = match tempfile::tempdir() {
+        Ok(temp) => temp,
+        Err(e) => {
+            log::error!("Failed to create temp dir to extract tarball: {}", e);
+            return Err(e.into())
+        },
+    };
+    let archive = temp.path().join("archive");
+    let extracted = temp.path().join("extracted");
+    source::download_file(url, &archive, proxy)?;
+    if let Err(e) = create_dir(&extracted) {
+        log::error!("Failed to create dir '{}': {}", extracted.display(), e);
+        return Err(e.into())
+    }
+    let output = match Command::new("/usr/bin/tar")
+        .arg("--extract")
+        .arg("--no-same-owner")
+        .arg("--no-same-permissions")
+        .arg("--file")
+        .arg(&archive)
+        .arg("--directory")
+        .arg(&extracted)
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            log::error!("Failed to spawn tar to extract '{}': {}", url, e);
+            return Err(e.into())
+        },
+    };
+    if ! output.status.success() {
+        log::error!("Failed to extract tarball '{}': {}", url,
+            String::from_utf8_lossy(&output.stderr));
+        return Err(Error::BadChild { pid: None, code: output.status.code() })
+    }
+    repo.import_dir(&snapshot_root(&extracted)?)
+}
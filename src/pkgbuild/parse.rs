@@ -263,7 +263,7 @@ impl PkgbuildsOwned {
 
 impl PkgbuildsOwned {
     fn from_dumped_pkgbuilds<P, I, S> (
-        dir: P, list: I, actual_identity: IdentityActual
+        dir: P, list: I, actual_identity: IdentityActual, carch: &str
     ) -> Result<Self>
     where
         P: AsRef<Path>,
@@ -282,6 +282,7 @@ impl PkgbuildsOwned {
                 .arg("-c")
                 .arg(include_str!("../../scripts/parse_pkgbuilds.bash"))
                 .arg("PKGBUILD Parser")
+                .arg(carch)
                 .current_dir(dir.as_ref())
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped()))
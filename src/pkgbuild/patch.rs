@@ -0,0 +1,102 @@
+// Local patch series applied on top of PKGBUILDs
+use std::{
+        fs::{
+            read,
+            read_dir,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::error::{
+        Error,
+        Result
+    };
+
+#[derive(Clone)]
+pub(super) struct Patch {
+    pub(super) path: PathBuf,
+    pub(super) content: Vec<u8>,
+}
+
+impl Patch {
+    fn from_file(path: &Path) -> Result<Self> {
+        match read(path) {
+            Ok(content) => Ok(Self { path: path.to_owned(), content }),
+            Err(e) => {
+                log::error!("Failed to read patch '{}': {}", path.display(), e);
+                Err(e.into())
+            },
+        }
+    }
+
+    /// All *.patch and *.diff files in a dir, sorted by name
+    fn vec_from_dir(dir: &Path) -> Result<Vec<Self>> {
+        let entries = match read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("Failed to read patch dir '{}': {}",
+                    dir.display(), e);
+                return Err(e.into())
+            },
+        };
+        let mut paths = vec![];
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::error!("Failed to read entry from patch dir '{}': {}",
+                        dir.display(), e);
+                    return Err(e.into())
+                },
+            };
+            let path = entry.path();
+            if ! path.is_file() {
+                continue
+            }
+            match path.extension() {
+                Some(extension) if extension == "patch" || extension == "diff"
+                    => paths.push(path),
+                _ => (),
+            }
+        }
+        paths.sort_unstable();
+        let mut patches = vec![];
+        for path in paths.iter() {
+            patches.push(Self::from_file(path)?)
+        }
+        Ok(patches)
+    }
+}
+
+/// Read the patches for a PKGBUILD, each entry could either be a patch file
+/// or a dir containing patches
+pub(super) fn load(name: &str, entries: &[String]) -> Result<Vec<Patch>> {
+    let mut patches = vec![];
+    for entry in entries.iter() {
+        let path = PathBuf::from(entry);
+        if path.is_dir() {
+            patches.append(&mut Patch::vec_from_dir(&path)?)
+        } else if path.is_file() {
+            patches.push(Patch::from_file(&path)?)
+        } else {
+            log::error!("Patch '{}' for PKGBUILD '{}' does not exist",
+                entry, name);
+            return Err(Error::InvalidConfig)
+        }
+    }
+    Ok(patches)
+}
+
+/// Hash of the whole series, order matters
+pub(super) fn hash(patches: &[Patch]) -> u64 {
+    let mut buffer = vec![];
+    for patch in patches.iter() {
+        buffer.extend_from_slice(&xxh3_64(&patch.content).to_le_bytes())
+    }
+    xxh3_64(&buffer)
+}
@@ -0,0 +1,415 @@
+// Publishing packages in pkgs/updated, with the regenerated repo db, to
+// remote hosts, and removing what's no longer in pkgs/latest from them
+mod github;
+mod remote;
+mod s3;
+
+use std::{
+        collections::{
+            HashMap,
+            HashSet,
+        },
+        os::unix::fs::symlink,
+        path::{
+            Path,
+            PathBuf,
+        },
+        process::Command,
+    };
+
+use tempfile::TempDir;
+
+use crate::{
+        arch::Target,
+        child::output_and_check,
+        config::Publish,
+        error::{
+            Error,
+            Result,
+        },
+        identity::IdentityActual,
+        index::{
+            INDEXHTML,
+            INDEXJSON,
+        },
+        package::is_archive,
+    };
+
+/// A remote dir holding the files of the repo, and nothing else, as anything
+/// not in the repo is considered stale there
+trait Backend {
+    /// Where it is, for logs
+    fn describe(&self) -> String;
+    /// The name a local file has in it, for backends not accepting every
+    /// character of package names, e.g. ':' of epochs
+    fn remote_name(&self, name: &str) -> String {
+        name.to_string()
+    }
+    /// Names of all files in it
+    fn list(&mut self) -> Result<Vec<String>>;
+    /// Upload a local file into it as the name, replacing any existing one
+    fn upload(&mut self, name: &str, path: &Path) -> Result<()>;
+    fn delete(&mut self, name: &str) -> Result<()>;
+}
+
+fn backends(publish: &Publish) -> Result<Vec<Box<dyn Backend>>> {
+    let mut backends: Vec<Box<dyn Backend>> = vec![];
+    if let Some(config) = &publish.github {
+        backends.push(Box::new(github::Release::new(config)?))
+    }
+    if let Some(config) = &publish.s3 {
+        backends.push(Box::new(s3::Bucket::new(config)?))
+    }
+    if let Some(dest) = &publish.rsync {
+        backends.push(Box::new(remote::Remote::rsync(dest)))
+    }
+    if let Some(dest) = &publish.sftp {
+        backends.push(Box::new(remote::Remote::sftp(dest)))
+    }
+    Ok(backends)
+}
+
+/// Names of entries in a dir, sorted
+fn names_in(dir: &Path) -> Result<Vec<String>> {
+    let readdir = match dir.read_dir() {
+        Ok(readdir) => readdir,
+        Err(e) => {
+            log::error!("Failed to read dir '{}': {}", dir.display(), e);
+            return Err(e.into())
+        },
+    };
+    let mut names = vec![];
+    for entry in readdir {
+        match entry {
+            Ok(entry) =>
+                names.push(entry.file_name().to_string_lossy().into_owned()),
+            Err(e) => {
+                log::error!("Failed to read entry from dir '{}': {}",
+                    dir.display(), e);
+                return Err(e.into())
+            },
+        }
+    }
+    names.sort_unstable();
+    Ok(names)
+}
+
+/// Create the repo db and files db from all packages in latest, with repo-add
+/// into the staging dir, return names of the db files
+fn regenerate_db(
+    repo: &str, latest: &Path, latest_names: &[String], staging: &Path,
+    actual_identity: &IdentityActual, sign: Option<&str>
+) -> Result<Vec<String>>
+{
+    let db = staging.join(format!("{}.db.tar.gz", repo));
+    let mut command = Command::new("/usr/bin/repo-add");
+    command.arg("--quiet");
+    if let Some(key) = sign {
+        command.arg("--sign").arg("--key").arg(key);
+    }
+    command.arg(&db);
+    for name in latest_names.iter() {
+        if is_archive(name) {
+            command.arg(latest.join(name));
+        }
+    }
+    output_and_check(actual_identity.set_root_drop_command(&mut command),
+        "to regenerate repo db")?;
+    let names = names_in(staging)?;
+    if ! names.iter().any(|name| name == &format!("{}.db", repo)) {
+        log::error!("repo-add did not create '{}.db'", repo);
+        return Err(Error::ImpossibleLogic)
+    }
+    log::info!("Regenerated repo db '{}' from {} packages", repo,
+        latest_names.iter().filter(|name| is_archive(name)).count());
+    Ok(names)
+}
+
+/// What a sync uploads, in order, and what it keeps remotely
+struct Plan {
+    uploads: Vec<(String, PathBuf)>,
+    keeps: HashSet<String>,
+}
+
+impl Plan {
+    /// Packages and signatures go first, so the db never lists what's not
+    /// there yet, then the db, then the index. A package is uploaded only if
+    /// it's updated, or missing remotely, e.g. after a failed publish
+    fn new(
+        remote: &HashSet<String>, latest: &Path, latest_names: &[String],
+        updated_names: &HashSet<&str>, staging: &Path, db_names: &[String]
+    ) -> Self
+    {
+        let is_index = |name: &str| name == INDEXHTML || name == INDEXJSON;
+        let mut uploads = vec![];
+        for name in latest_names.iter() {
+            if ! is_index(name) && (updated_names.contains(name.as_str()) ||
+                ! remote.contains(name))
+            {
+                uploads.push((name.clone(), latest.join(name)))
+            }
+        }
+        for name in db_names.iter() {
+            uploads.push((name.clone(), staging.join(name)))
+        }
+        for name in latest_names.iter() {
+            if is_index(name) {
+                uploads.push((name.clone(), latest.join(name)))
+            }
+        }
+        let keeps = latest_names.iter().chain(db_names.iter()).cloned()
+            .collect();
+        Self { uploads, keeps }
+    }
+}
+
+/// Latest as a backend names its files, only when some of them are named
+/// differently: a staging dir of symlinks under the remote names, so repo-add
+/// records the names that would actually be there, and its own db
+struct Renamed {
+    latest: TempDir,
+    names: Vec<String>,
+    updated: HashSet<String>,
+    staging: TempDir,
+}
+
+impl Renamed {
+    fn new(
+        backend: &dyn Backend, latest: &Path, latest_names: &[String],
+        updated_names: &HashSet<&str>
+    ) -> Result<Option<Self>>
+    {
+        let renames: Vec<(&String, String)> = latest_names.iter().map(
+            |name| (name, backend.remote_name(name))).collect();
+        if renames.iter().all(|(name, remote_name)| *name == remote_name) {
+            return Ok(None)
+        }
+        let mut locals: HashMap<&str, &str> = HashMap::new();
+        for (name, remote_name) in renames.iter() {
+            if let Some(other) = locals.insert(remote_name, name) {
+                log::error!("Both '{}' and '{}' would be named '{}' in {}",
+                    other, name, remote_name, backend.describe());
+                return Err(Error::InvalidConfig)
+            }
+        }
+        let (renamed_latest, staging) = match (
+            tempfile::tempdir(), tempfile::tempdir())
+        {
+            (Ok(renamed_latest), Ok(staging)) => (renamed_latest, staging),
+            (Err(e), _) | (_, Err(e)) => {
+                log::error!("Failed to create staging dirs for renamed \
+                    files: {}", e);
+                return Err(e.into())
+            },
+        };
+        for (name, remote_name) in renames.iter() {
+            let link = renamed_latest.path().join(remote_name);
+            if let Err(e) = symlink(latest.join(name), &link) {
+                log::error!("Failed to link '{}' as '{}': {}", name,
+                    link.display(), e);
+                return Err(e.into())
+            }
+        }
+        let mut names: Vec<String> = renames.iter().map(
+            |(_, remote_name)| remote_name.clone()).collect();
+        names.sort_unstable();
+        let updated = renames.into_iter().filter_map(
+            |(name, remote_name)| updated_names.contains(name.as_str())
+                .then_some(remote_name)).collect();
+        Ok(Some(Self {
+            latest: renamed_latest,
+            names,
+            updated,
+            staging,
+        }))
+    }
+}
+
+fn sync(backend: &mut dyn Backend, latest: &Path, latest_names: &[String],
+    updated_names: &HashSet<&str>, staging: &Path, db_names: &[String]
+) -> Result<()>
+{
+    let describe = backend.describe();
+    log::info!("Publishing to {}", describe);
+    let remote: HashSet<String> = backend.list()?.into_iter().collect();
+    let plan = Plan::new(&remote, latest, latest_names, updated_names,
+        staging, db_names);
+    for (name, path) in plan.uploads.iter() {
+        log::info!("Uploading '{}' to {}", name, describe);
+        backend.upload(name, path)?
+    }
+    let mut stale: Vec<&String> = remote.iter().filter(
+        |name| ! plan.keeps.contains(*name)).collect();
+    stale.sort_unstable();
+    for name in stale.iter() {
+        log::info!("Deleting stale '{}' from {}", name, describe);
+        backend.delete(name)?
+    }
+    log::info!("Published to {}, {} uploaded, {} deleted", describe,
+        plan.uploads.len(), stale.len());
+    Ok(())
+}
+
+/// Publish to every backend, one failing does not stop the others
+pub(crate) fn publish(
+    publish: &Publish, target: &Target, actual_identity: &IdentityActual,
+    sign: Option<&str>
+) -> Result<()>
+{
+    let pkgs = target.pkgs_dir();
+    let latest = pkgs.join("latest");
+    let latest_names = names_in(&latest)?;
+    let updated_names = names_in(&pkgs.join("updated"))?;
+    let updated_names: HashSet<&str> = updated_names.iter().map(
+        |name| name.as_str()).collect();
+    let staging = match tempfile::tempdir() {
+        Ok(staging) => staging,
+        Err(e) => {
+            log::error!("Failed to create staging dir for repo db: {}", e);
+            return Err(e.into())
+        },
+    };
+    let db_names = regenerate_db(&publish.repo, &latest, &latest_names,
+        staging.path(), actual_identity, sign)?;
+    let mut r = Ok(());
+    for mut backend in backends(publish)? {
+        let result = match Renamed::new(backend.as_ref(), &latest,
+            &latest_names, &updated_names)
+        {
+            Ok(Some(renamed)) => regenerate_db(&publish.repo,
+                renamed.latest.path(), &renamed.names, renamed.staging.path(),
+                actual_identity, sign
+            ).and_then(|db_names| sync(backend.as_mut(),
+                renamed.latest.path(), &renamed.names,
+                &renamed.updated.iter().map(|name| name.as_str()).collect(),
+                renamed.staging.path(), &db_names)),
+            Ok(None) => sync(backend.as_mut(), &latest, &latest_names,
+                &updated_names, staging.path(), &db_names),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("Failed to publish to {}: {}", backend.describe(), e);
+            r = Err(e)
+        }
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    /// Records what's done to it, starting with the remote names
+    struct Mock {
+        remote: Vec<String>,
+        calls: Vec<String>,
+    }
+
+    impl Backend for Mock {
+        fn describe(&self) -> String {
+            "mock".into()
+        }
+
+        fn list(&mut self) -> Result<Vec<String>> {
+            Ok(self.remote.clone())
+        }
+
+        fn upload(&mut self, name: &str, path: &Path) -> Result<()> {
+            self.calls.push(format!("upload {} {}", name, path.display()));
+            Ok(())
+        }
+
+        fn delete(&mut self, name: &str) -> Result<()> {
+            self.calls.push(format!("delete {}", name));
+            Ok(())
+        }
+    }
+
+    const LATEST: &[&str] = &[
+        "bar-1-1-x86_64.pkg.tar.zst",
+        "bar-1-1-x86_64.pkg.tar.zst.sig",
+        "foo-2-1-x86_64.pkg.tar.zst",
+        INDEXHTML,
+        INDEXJSON,
+    ];
+    const DB: &[&str] = &["repo.db", "repo.db.tar.gz"];
+
+    #[test]
+    fn plan() {
+        let remote: HashSet<String> = strings(&[
+            "bar-1-1-x86_64.pkg.tar.zst",
+            "bar-1-1-x86_64.pkg.tar.zst.sig",
+            "foo-1-1-x86_64.pkg.tar.zst",
+            "repo.db",
+            INDEXHTML,
+        ]).into_iter().collect();
+        let updated: HashSet<&str> = ["bar-1-1-x86_64.pkg.tar.zst.sig"]
+            .into_iter().collect();
+        let plan = Plan::new(&remote, Path::new("/latest"), &strings(LATEST),
+            &updated, Path::new("/staging"), &strings(DB));
+        let uploads: Vec<(&str, &Path)> = plan.uploads.iter().map(
+            |(name, path)| (name.as_str(), path.as_path())).collect();
+        assert_eq!(uploads, [
+            // Updated, then missing remotely
+            ("bar-1-1-x86_64.pkg.tar.zst.sig",
+                Path::new("/latest/bar-1-1-x86_64.pkg.tar.zst.sig")),
+            ("foo-2-1-x86_64.pkg.tar.zst",
+                Path::new("/latest/foo-2-1-x86_64.pkg.tar.zst")),
+            // Then the db, always
+            ("repo.db", Path::new("/staging/repo.db")),
+            ("repo.db.tar.gz", Path::new("/staging/repo.db.tar.gz")),
+            // Then the index, always
+            (INDEXHTML, Path::new("/latest/index.html")),
+            (INDEXJSON, Path::new("/latest/packages.json")),
+        ]);
+        let mut keeps: Vec<&str> = plan.keeps.iter().map(
+            |name| name.as_str()).collect();
+        keeps.sort_unstable();
+        let mut expected: Vec<&str> = LATEST.iter().chain(DB.iter()).copied()
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(keeps, expected);
+    }
+
+    #[test]
+    fn sync_deletes_stale() {
+        let mut mock = Mock {
+            remote: strings(&[
+                "zzz-1-1-any.pkg.tar.zst",
+                "bar-1-1-x86_64.pkg.tar.zst",
+                "bar-1-1-x86_64.pkg.tar.zst.sig",
+                "foo-2-1-x86_64.pkg.tar.zst",
+                "foo-1-1-x86_64.pkg.tar.zst",
+                "repo.db",
+                "repo.db.tar.gz",
+                INDEXHTML,
+                INDEXJSON,
+            ]),
+            calls: vec![],
+        };
+        sync(&mut mock, Path::new("/latest"), &strings(LATEST),
+            &HashSet::new(), Path::new("/staging"), &strings(DB)).unwrap();
+        assert_eq!(mock.calls, [
+            "upload repo.db /staging/repo.db",
+            "upload repo.db.tar.gz /staging/repo.db.tar.gz",
+            "upload index.html /latest/index.html",
+            "upload packages.json /latest/packages.json",
+            // Only after all uploads, sorted
+            "delete foo-1-1-x86_64.pkg.tar.zst",
+            "delete zzz-1-1-any.pkg.tar.zst",
+        ]);
+    }
+
+    #[test]
+    fn sync_to_empty() {
+        let mut mock = Mock { remote: vec![], calls: vec![] };
+        sync(&mut mock, Path::new("/latest"), &strings(LATEST),
+            &HashSet::new(), Path::new("/staging"), &strings(DB)).unwrap();
+        assert_eq!(mock.calls.len(), LATEST.len() + DB.len());
+        assert!(mock.calls.iter().all(|call| call.starts_with("upload ")));
+    }
+}
@@ -0,0 +1,253 @@
+// Assets of a GitHub release, through the REST API
+use std::{
+        collections::HashMap,
+        fs::File,
+        path::Path,
+    };
+
+use serde::Deserialize;
+
+use crate::{
+        config::PublishGithub,
+        error::{
+            Error,
+            Result,
+        },
+    };
+
+use super::Backend;
+
+const API_URL: &str = "https://api.github.com";
+const UPLOAD_URL: &str = "https://uploads.github.com";
+const TOKEN_ENV: &str = "GITHUB_TOKEN";
+const PER_PAGE: usize = 100;
+
+/// GitHub replaces anything else in asset names with '.', done here before
+/// uploading so the names in the db are the ones really there
+fn is_asset_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')
+}
+
+#[derive(Deserialize)]
+struct ReleaseJson {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct AssetJson {
+    id: u64,
+    name: String,
+}
+
+pub(super) struct Release {
+    agent: ureq::Agent,
+    repository: String,
+    tag: String,
+    token: String,
+    api_url: String,
+    upload_url: String,
+    /// Looked up or created on the first listing
+    id: Option<u64>,
+    /// Asset ids by name, from the last listing and uploads since then
+    assets: HashMap<String, u64>,
+}
+
+impl Release {
+    pub(super) fn new(config: &PublishGithub) -> Result<Self> {
+        let token_env = config.token_env.as_deref().unwrap_or(TOKEN_ENV);
+        let token = match std::env::var(token_env) {
+            Ok(token) => token,
+            Err(e) => {
+                log::error!("Failed to get GitHub token from env '{}': {}",
+                    token_env, e);
+                return Err(Error::InvalidConfig)
+            },
+        };
+        Ok(Self {
+            agent: ureq::AgentBuilder::new().build(),
+            repository: config.repository.trim_matches('/').to_string(),
+            tag: config.tag.clone(),
+            token,
+            api_url: config.api_url.as_deref().unwrap_or(API_URL)
+                .trim_end_matches('/').to_string(),
+            upload_url: config.upload_url.as_deref().unwrap_or(UPLOAD_URL)
+                .trim_end_matches('/').to_string(),
+            id: None,
+            assets: HashMap::new(),
+        })
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        self.agent.request(method, url)
+            .set("Accept", "application/vnd.github+json")
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .set("X-GitHub-Api-Version", "2022-11-28")
+    }
+
+    fn releases_url(&self) -> String {
+        format!("{}/repos/{}/releases", self.api_url, self.repository)
+    }
+
+    /// The release of the tag, created if it does not exist yet
+    fn release_id(&mut self) -> Result<u64> {
+        if let Some(id) = self.id {
+            return Ok(id)
+        }
+        let url = format!("{}/tags/{}", self.releases_url(), self.tag);
+        let response = match self.request("GET", &url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => {
+                log::info!("Creating release '{}' of '{}'", self.tag,
+                    self.repository);
+                match self.request("POST", &self.releases_url()).send_json(
+                    serde_json::json!({
+                        "tag_name": self.tag,
+                        "name": self.tag,
+                    }))
+                {
+                    Ok(response) => response,
+                    Err(e) => {
+                        log::error!("Failed to create release '{}' of '{}': \
+                            {}", self.tag, self.repository, e);
+                        return Err(e.into())
+                    },
+                }
+            },
+            Err(e) => {
+                log::error!("Failed to get release '{}' of '{}': {}",
+                    self.tag, self.repository, e);
+                return Err(e.into())
+            },
+        };
+        let id = match response.into_json::<ReleaseJson>() {
+            Ok(release) => release.id,
+            Err(e) => {
+                log::error!("Failed to parse release '{}' of '{}': {}",
+                    self.tag, self.repository, e);
+                return Err(e.into())
+            },
+        };
+        self.id = Some(id);
+        Ok(id)
+    }
+}
+
+impl Backend for Release {
+    fn describe(&self) -> String {
+        format!("GitHub release '{}' of '{}'", self.tag, self.repository)
+    }
+
+    fn remote_name(&self, name: &str) -> String {
+        name.chars().map(|c| if is_asset_char(c) { c } else { '.' }).collect()
+    }
+
+    fn list(&mut self) -> Result<Vec<String>> {
+        let id = self.release_id()?;
+        self.assets.clear();
+        let url = format!("{}/{}/assets", self.releases_url(), id);
+        for page in 1.. {
+            let assets: Vec<AssetJson> = match self.request("GET", &url)
+                .query("per_page", &PER_PAGE.to_string())
+                .query("page", &page.to_string())
+                .call()
+            {
+                Ok(response) => match response.into_json() {
+                    Ok(assets) => assets,
+                    Err(e) => {
+                        log::error!("Failed to parse assets of {}: {}",
+                            self.describe(), e);
+                        return Err(e.into())
+                    },
+                },
+                Err(e) => {
+                    log::error!("Failed to list assets of {}: {}",
+                        self.describe(), e);
+                    return Err(e.into())
+                },
+            };
+            let last = assets.len() < PER_PAGE;
+            for asset in assets {
+                self.assets.insert(asset.name, asset.id);
+            }
+            if last {
+                break
+            }
+        }
+        Ok(self.assets.keys().cloned().collect())
+    }
+
+    fn upload(&mut self, name: &str, path: &Path) -> Result<()> {
+        // Assets could not be overwritten, only replaced
+        if self.assets.contains_key(name) {
+            self.delete(name)?
+        }
+        let id = self.release_id()?;
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open '{}' to upload: {}",
+                    path.display(), e);
+                return Err(e.into())
+            },
+        };
+        let len = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                log::error!("Failed to get size of '{}': {}",
+                    path.display(), e);
+                return Err(e.into())
+            },
+        };
+        let url = format!("{}/repos/{}/releases/{}/assets", self.upload_url,
+            self.repository, id);
+        let asset: AssetJson = match self.request("POST", &url)
+            .query("name", name)
+            .set("Content-Type", "application/octet-stream")
+            .set("Content-Length", &len.to_string())
+            .send(file)
+        {
+            Ok(response) => match response.into_json() {
+                Ok(asset) => asset,
+                Err(e) => {
+                    log::error!("Failed to parse uploaded asset '{}': {}",
+                        name, e);
+                    return Err(e.into())
+                },
+            },
+            Err(e) => {
+                log::error!("Failed to upload '{}' to {}: {}", name,
+                    self.describe(), e);
+                return Err(e.into())
+            },
+        };
+        let renamed = asset.name != name;
+        if renamed {
+            log::error!("GitHub renamed asset '{}' to '{}', the repo db does \
+                not have it", name, asset.name)
+        }
+        self.assets.insert(asset.name, asset.id);
+        match renamed {
+            true => Err(Error::ImpossibleLogic),
+            false => Ok(()),
+        }
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        let id = match self.assets.get(name) {
+            Some(id) => *id,
+            None => {
+                log::error!("Asset '{}' is not in {}", name, self.describe());
+                return Err(Error::ImpossibleLogic)
+            },
+        };
+        let url = format!("{}/repos/{}/releases/assets/{}", self.api_url,
+            self.repository, id);
+        if let Err(e) = self.request("DELETE", &url).call() {
+            log::error!("Failed to delete '{}' from {}: {}", name,
+                self.describe(), e);
+            return Err(e.into())
+        }
+        self.assets.remove(name);
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+// A dir reached through rsync or sftp, local or over SSH
+use std::{
+        io::Write,
+        path::Path,
+        process::{
+            Command,
+            Stdio,
+        },
+    };
+
+use crate::error::{
+        Error,
+        Result,
+    };
+
+use super::Backend;
+
+enum Tool {
+    Rsync,
+    Sftp,
+}
+
+pub(super) struct Remote {
+    tool: Tool,
+    /// Without trailing /, e.g. user@host:/srv/repo
+    dest: String,
+}
+
+/// Quote an argument for sftp batch commands
+fn sftp_quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Escape wildcards in a name for an rsync filter pattern
+fn rsync_escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '*' | '?' | '[' | '\\') {
+            escaped.push('\\')
+        }
+        escaped.push(c)
+    }
+    escaped
+}
+
+/// The rest of the line after the fields separated by whitespace
+fn after_fields(line: &str, fields: usize) -> Option<&str> {
+    let mut rest = line;
+    for _ in 0..fields {
+        rest = rest.trim_start();
+        rest = &rest[rest.find(char::is_whitespace)?..];
+    }
+    let rest = rest.trim_start();
+    (! rest.is_empty()).then_some(rest)
+}
+
+/// Run the command and return its stdout, stdin fed if set
+fn run(command: &mut Command, stdin: Option<&str>, job: &str)
+    -> Result<String>
+{
+    let mut child = match command
+        .stdin(match stdin {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            log::error!("Failed to spawn child to {}: {}", job, e);
+            return Err(e.into())
+        },
+    };
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        if let Err(e) = pipe.write_all(input.as_bytes()) {
+            log::error!("Failed to write commands to child to {}: {}", job, e);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e.into())
+        }
+    }
+    let output = match child.wait_with_output() {
+        Ok(output) => output,
+        Err(e) => {
+            log::error!("Failed to wait for child to {}: {}", job, e);
+            return Err(e.into())
+        },
+    };
+    if ! output.status.success() {
+        log::error!("Child {} bad return {:?}", job, output.status.code());
+        return Err(Error::BadChild { pid: None, code: output.status.code() })
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl Remote {
+    pub(super) fn rsync(dest: &str) -> Self {
+        Self { tool: Tool::Rsync, dest: dest.trim_end_matches('/').into() }
+    }
+
+    pub(super) fn sftp(dest: &str) -> Self {
+        Self { tool: Tool::Sftp, dest: dest.trim_end_matches('/').into() }
+    }
+
+    /// Commands run in the dest dir through a single sftp session
+    fn sftp_batch(&self, commands: &str, job: &str) -> Result<String> {
+        run(Command::new("/usr/bin/sftp")
+            .arg("-q")
+            .arg("-b")
+            .arg("-")
+            .arg(&self.dest),
+            Some(commands), job)
+    }
+}
+
+impl Backend for Remote {
+    fn describe(&self) -> String {
+        match self.tool {
+            Tool::Rsync => format!("rsync dest '{}'", self.dest),
+            Tool::Sftp => format!("sftp dest '{}'", self.dest),
+        }
+    }
+
+    fn list(&mut self) -> Result<Vec<String>> {
+        let job = format!("to list {}", self.describe());
+        match self.tool {
+            // -rw-r--r--      1,234 2024/01/01 00:00:00 name
+            Tool::Rsync => Ok(run(Command::new("/usr/bin/rsync")
+                    .arg("--list-only")
+                    .arg(format!("{}/", self.dest)),
+                None, &job)?.lines().filter_map(|line| {
+                    if ! line.starts_with('-') && ! line.starts_with('l') {
+                        return None
+                    }
+                    let name = after_fields(line, 4)?;
+                    // Symlinks are listed as name -> target
+                    Some(match line.starts_with('l') {
+                        true => name.split(" -> ").next()?,
+                        false => name,
+                    }.to_string())
+                }).collect()),
+            Tool::Sftp => Ok(self.sftp_batch("ls -1a\n", &job)?.lines()
+                .filter(|line| ! line.starts_with("sftp>") &&
+                    *line != "." && *line != ".." && ! line.is_empty())
+                .map(|line| line.to_string()).collect()),
+        }
+    }
+
+    fn upload(&mut self, name: &str, path: &Path) -> Result<()> {
+        let job = format!("to upload '{}' to {}", name, self.describe());
+        match self.tool {
+            Tool::Rsync => run(Command::new("/usr/bin/rsync")
+                    .arg("--copy-links")
+                    .arg("--times")
+                    .arg(path)
+                    .arg(format!("{}/{}", self.dest, name)),
+                None, &job).and(Ok(())),
+            // Uploaded to a temp name first, so it's replaced at once
+            Tool::Sftp => {
+                let temp = format!(".{}.temp", name);
+                self.sftp_batch(&format!("put {} {}\n-rm {}\nrename {} {}\n",
+                    sftp_quote(&path.to_string_lossy()), sftp_quote(&temp),
+                    sftp_quote(name), sftp_quote(&temp), sftp_quote(name)),
+                    &job).and(Ok(()))
+            },
+        }
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        let job = format!("to delete '{}' from {}", name, self.describe());
+        match self.tool {
+            // Sync an empty dir with only the name included, so only it is
+            // deleted
+            Tool::Rsync => {
+                let empty = match tempfile::tempdir() {
+                    Ok(empty) => empty,
+                    Err(e) => {
+                        log::error!("Failed to create empty dir: {}", e);
+                        return Err(e.into())
+                    },
+                };
+                run(Command::new("/usr/bin/rsync")
+                    .arg("--recursive")
+                    .arg("--delete")
+                    .arg(format!("--include=/{}", rsync_escape(name)))
+                    .arg("--exclude=*")
+                    .arg(format!("{}/", empty.path().display()))
+                    .arg(format!("{}/", self.dest)),
+                None, &job).and(Ok(()))
+            },
+            Tool::Sftp => self.sftp_batch(&format!("rm {}\n", sftp_quote(name)),
+                &job).and(Ok(())),
+        }
+    }
+}
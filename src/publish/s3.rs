@@ -0,0 +1,395 @@
+// Objects in an S3-compatible bucket, addressed path-style, signed with
+// AWS Signature Version 4
+use std::{
+        fs::File,
+        path::Path,
+    };
+
+use hmac::{
+        Hmac,
+        Mac,
+    };
+
+use sha2::{
+        Digest,
+        Sha256,
+    };
+
+use time::macros::format_description;
+
+use crate::{
+        config::PublishS3,
+        error::{
+            Error,
+            Result,
+        },
+    };
+
+use super::Backend;
+
+const REGION: &str = "us-east-1";
+const ACCESS_KEY_ENV: &str = "AWS_ACCESS_KEY_ID";
+const SECRET_KEY_ENV: &str = "AWS_SECRET_ACCESS_KEY";
+/// Payloads are not hashed, so uploads could be streamed
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC takes keys of any length, so this never fails
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .expect("Failed to create HMAC");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode all but unreserved chars, and / if it's kept
+fn encode(text: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' |
+            b'~' => encoded.push(byte as char),
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Query pairs encoded and sorted, as both the canonical and the actual query
+fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut query: Vec<(String, String)> = query.iter().map(
+        |(key, value)| (encode(key, false), encode(value, false))).collect();
+    query.sort_unstable();
+    query.iter().map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<String>>().join("&")
+}
+
+/// The canonical request and its signed headers, headers must be lowercase
+/// and sorted
+fn canonical_request(method: &str, path: &str, query: &str,
+    headers: &[(&str, &str)], payload_hash: &str
+) -> (String, String)
+{
+    let signed_headers = headers.iter().map(|(name, _)| *name)
+        .collect::<Vec<&str>>().join(";");
+    let mut canonical = format!("{}\n{}\n{}\n", method, path, query);
+    for (name, value) in headers.iter() {
+        canonical.push_str(&format!("{}:{}\n", name, value.trim()))
+    }
+    canonical.push_str(&format!("\n{}\n{}", signed_headers, payload_hash));
+    (canonical, signed_headers)
+}
+
+/// Hex signature of the canonical request, date as YYYYMMDD and datetime as
+/// YYYYMMDDTHHMMSSZ
+fn signature(secret_key: &str, date: &str, datetime: &str, region: &str,
+    service: &str, canonical: &str
+) -> String
+{
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", datetime, scope,
+        hex::encode(Sha256::digest(canonical.as_bytes())));
+    let mut key = format!("AWS4{}", secret_key).into_bytes();
+    for part in [date, region, service, "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes())
+    }
+    hex::encode(hmac_sha256(&key, to_sign.as_bytes()))
+}
+
+/// Values of all elements of the tag in the XML, unescaped
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut values = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let end = match rest.find(&close) {
+            Some(end) => end,
+            None => break,
+        };
+        values.push(rest[..end].replace("&lt;", "<").replace("&gt;", ">")
+            .replace("&quot;", "\"").replace("&apos;", "'")
+            .replace("&amp;", "&"));
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+pub(super) struct Bucket {
+    agent: ureq::Agent,
+    endpoint: url::Url,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl Bucket {
+    pub(super) fn new(config: &PublishS3) -> Result<Self> {
+        let endpoint = match url::Url::parse(&config.endpoint) {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                log::error!("Illegal S3 endpoint '{}': {}", config.endpoint, e);
+                return Err(e.into())
+            },
+        };
+        let mut keys = vec![];
+        for env in [ACCESS_KEY_ENV, SECRET_KEY_ENV] {
+            match std::env::var(env) {
+                Ok(key) => keys.push(key),
+                Err(e) => {
+                    log::error!("Failed to get S3 credential from env '{}': {}",
+                        env, e);
+                    return Err(Error::InvalidConfig)
+                },
+            }
+        }
+        let secret_key = keys.pop().unwrap_or_default();
+        let access_key = keys.pop().unwrap_or_default();
+        let prefix = match config.prefix.as_deref().map(
+            |prefix| prefix.trim_matches('/'))
+        {
+            Some("") | None => String::new(),
+            Some(prefix) => format!("{}/", prefix),
+        };
+        Ok(Self {
+            agent: ureq::AgentBuilder::new().build(),
+            endpoint,
+            bucket: config.bucket.clone(),
+            prefix,
+            region: config.region.clone().unwrap_or(REGION.into()),
+            access_key,
+            secret_key,
+        })
+    }
+
+    /// The canonical path of the key, or the bucket itself if it's empty
+    fn path(&self, key: &str) -> String {
+        let base = self.endpoint.path().trim_end_matches('/');
+        match key.is_empty() {
+            true => format!("{}/{}", base, encode(&self.bucket, false)),
+            false => format!("{}/{}/{}", base, encode(&self.bucket, false),
+                encode(key, true)),
+        }
+    }
+
+    /// A request signed for the path and query, query pairs are encoded here
+    fn request(&self, method: &str, path: &str, query: &[(&str, &str)])
+        -> Result<ureq::Request>
+    {
+        let now = time::OffsetDateTime::now_utc();
+        let date = now.format(format_description!("[year][month][day]"))
+            .map_err(time::Error::from)?;
+        let datetime = now.format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"))
+            .map_err(time::Error::from)?;
+        let query = canonical_query(query);
+        let host = match (self.endpoint.host_str(), self.endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => {
+                log::error!("S3 endpoint '{}' has no host", self.endpoint);
+                return Err(Error::InvalidConfig)
+            },
+        };
+        let (canonical, signed_headers) = canonical_request(method, path,
+            &query, &[("host", &host),
+                ("x-amz-content-sha256", UNSIGNED_PAYLOAD),
+                ("x-amz-date", &datetime)], UNSIGNED_PAYLOAD);
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let signature = signature(&self.secret_key, &date, &datetime,
+            &self.region, "s3", &canonical);
+        let mut url = format!("{}://{}{}", self.endpoint.scheme(), host, path);
+        if ! query.is_empty() {
+            url.push('?');
+            url.push_str(&query)
+        }
+        Ok(self.agent.request(method, &url)
+            .set("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .set("x-amz-date", &datetime)
+            .set("Authorization", &format!("AWS4-HMAC-SHA256 Credential={}/{}, \
+                SignedHeaders={}, Signature={}", self.access_key, scope,
+                signed_headers, signature)))
+    }
+}
+
+impl Backend for Bucket {
+    fn describe(&self) -> String {
+        format!("S3 bucket '{}' at '{}' under '{}'", self.bucket,
+            self.endpoint, self.prefix)
+    }
+
+    /// Only objects right under the prefix, not those in deeper dirs
+    fn list(&mut self) -> Result<Vec<String>> {
+        let path = self.path("");
+        let mut names = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"),
+                ("prefix", self.prefix.as_str())];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()))
+            }
+            let body = match self.request("GET", &path, &query)?.call() {
+                Ok(response) => match response.into_string() {
+                    Ok(body) => body,
+                    Err(e) => {
+                        log::error!("Failed to read listing of {}: {}",
+                            self.describe(), e);
+                        return Err(e.into())
+                    },
+                },
+                Err(e) => {
+                    log::error!("Failed to list {}: {}", self.describe(), e);
+                    return Err(e.into())
+                },
+            };
+            for key in xml_values(&body, "Key") {
+                if let Some(name) = key.strip_prefix(&self.prefix) {
+                    if ! name.is_empty() && ! name.contains('/') {
+                        names.push(name.to_string())
+                    }
+                }
+            }
+            token = match xml_values(&body, "IsTruncated").first().map(
+                |truncated| truncated.as_str())
+            {
+                Some("true") => xml_values(&body, "NextContinuationToken")
+                    .pop(),
+                _ => None,
+            };
+            if token.is_none() {
+                break
+            }
+        }
+        Ok(names)
+    }
+
+    fn upload(&mut self, name: &str, path: &Path) -> Result<()> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open '{}' to upload: {}",
+                    path.display(), e);
+                return Err(e.into())
+            },
+        };
+        let len = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                log::error!("Failed to get size of '{}': {}",
+                    path.display(), e);
+                return Err(e.into())
+            },
+        };
+        let key = format!("{}{}", self.prefix, name);
+        if let Err(e) = self.request("PUT", &self.path(&key), &[])?
+            .set("Content-Type", "application/octet-stream")
+            .set("Content-Length", &len.to_string())
+            .send(file)
+        {
+            log::error!("Failed to upload '{}' to {}: {}", name,
+                self.describe(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        let key = format!("{}{}", self.prefix, name);
+        if let Err(e) = self.request("DELETE", &self.path(&key), &[])?.call() {
+            log::error!("Failed to delete '{}' from {}: {}", name,
+                self.describe(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Example credentials and requests from the AWS Signature Version 4 docs
+    const IAM_SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+    const S3_SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+    const EMPTY_HASH: &str =
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn sign_iam_list_users() {
+        let query = canonical_query(
+            &[("Version", "2010-05-08"), ("Action", "ListUsers")]);
+        assert_eq!(query, "Action=ListUsers&Version=2010-05-08");
+        let (canonical, signed_headers) = canonical_request("GET", "/", &query,
+            &[("content-type",
+                "application/x-www-form-urlencoded; charset=utf-8"),
+            ("host", "iam.amazonaws.com"),
+            ("x-amz-date", "20150830T123600Z")], EMPTY_HASH);
+        assert_eq!(signed_headers, "content-type;host;x-amz-date");
+        assert_eq!(canonical, "GET\n/\nAction=ListUsers&Version=2010-05-08\n\
+            content-type:application/x-www-form-urlencoded; charset=utf-8\n\
+            host:iam.amazonaws.com\nx-amz-date:20150830T123600Z\n\n\
+            content-type;host;x-amz-date\n\
+            e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex::encode(Sha256::digest(canonical.as_bytes())),
+            "f536975d06c0309214f805bb90ccff089219ecd68b2577efef23edd43b7e1a59");
+        assert_eq!(signature(IAM_SECRET_KEY, "20150830", "20150830T123600Z",
+            "us-east-1", "iam", &canonical),
+            "5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7");
+    }
+
+    #[test]
+    fn sign_s3_get_object() {
+        let (canonical, signed_headers) = canonical_request("GET",
+            "/test.txt", "", &[("host", "examplebucket.s3.amazonaws.com"),
+            ("range", "bytes=0-9"), ("x-amz-content-sha256", EMPTY_HASH),
+            ("x-amz-date", "20130524T000000Z")], EMPTY_HASH);
+        assert_eq!(signed_headers, "host;range;x-amz-content-sha256;x-amz-date");
+        assert_eq!(signature(S3_SECRET_KEY, "20130524", "20130524T000000Z",
+            "us-east-1", "s3", &canonical),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41");
+    }
+
+    #[test]
+    fn sign_s3_list_objects() {
+        let query = canonical_query(&[("prefix", "J"), ("max-keys", "2")]);
+        assert_eq!(query, "max-keys=2&prefix=J");
+        let (canonical, _) = canonical_request("GET", "/", &query,
+            &[("host", "examplebucket.s3.amazonaws.com"),
+            ("x-amz-content-sha256", EMPTY_HASH),
+            ("x-amz-date", "20130524T000000Z")], EMPTY_HASH);
+        assert_eq!(signature(S3_SECRET_KEY, "20130524", "20130524T000000Z",
+            "us-east-1", "s3", &canonical),
+            "34b48302e7b5fa45bde8084f4b7868a86f0a534bc59db6670ed5711ef69dc6f7");
+    }
+
+    #[test]
+    fn encode_keys() {
+        assert_eq!(encode("repo/foo 1:2+x.pkg.tar.zst", true),
+            "repo/foo%201%3A2%2Bx.pkg.tar.zst");
+        assert_eq!(encode("a/b~c", false), "a%2Fb~c");
+    }
+
+    #[test]
+    fn xml() {
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <ListBucketResult><IsTruncated>true</IsTruncated>\
+            <Contents><Key>repo/foo-1-1-x86_64.pkg.tar.zst</Key></Contents>\
+            <Contents><Key>repo/a&amp;b&lt;c&gt;&quot;d&apos;</Key></Contents>\
+            <Contents><Key></Key></Contents>\
+            <NextContinuationToken>1/2+3=</NextContinuationToken>\
+            </ListBucketResult>";
+        assert_eq!(xml_values(xml, "Key"), ["repo/foo-1-1-x86_64.pkg.tar.zst",
+            "repo/a&b<c>\"d'", ""]);
+        assert_eq!(xml_values(xml, "IsTruncated"), ["true"]);
+        assert_eq!(xml_values(xml, "NextContinuationToken"), ["1/2+3="]);
+        assert!(xml_values(xml, "Missing").is_empty());
+        // Not closed
+        assert!(xml_values("<Key>foo", "Key").is_empty());
+        // Escapes are only undone once
+        assert_eq!(xml_values("<Key>&amp;lt;</Key>", "Key"), ["&lt;"]);
+    }
+}
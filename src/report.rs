@@ -0,0 +1,119 @@
+// Report of a run, recording what happened to every PKGBUILD that was built
+use std::{
+        fs::File,
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use serde::Serialize;
+
+use crate::{
+        error::Result,
+        failure::Failure,
+        lint::Finding,
+    };
+
+pub(crate) const REPORTFILE: &str = "report.json";
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BuildStatus {
+    Built,
+    Failed,
+    /// Not tried, as a PKGBUILD it wants failed
+    Skipped,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CheckStatus {
+    Passed,
+    Failed,
+}
+
+/// check() run as a separate step, its failure does not fail the package
+#[derive(Serialize)]
+pub(crate) struct CheckReport {
+    pub(crate) status: CheckStatus,
+    pub(crate) log: PathBuf,
+}
+
+#[derive(Default, Serialize)]
+pub(crate) struct CcacheStats {
+    pub(crate) hits: usize,
+    pub(crate) misses: usize,
+    pub(crate) hit_rate: f64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CcacheReport {
+    pub(crate) dir: PathBuf,
+    /// Size in bytes after the run
+    pub(crate) size: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct PackageReport {
+    pub(crate) base: String,
+    pub(crate) pkgid: String,
+    /// The commit PKGBUILD was taken from, never the subtree id
+    pub(crate) commit: String,
+    pub(crate) version: String,
+    pub(crate) status: BuildStatus,
+    pub(crate) tries: usize,
+    /// Seconds of the successful try
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) log: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) check: Option<CheckReport>,
+    /// What the lint rules found in the built packages
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) lints: Vec<Finding>,
+    /// Why it failed, from the log of the step that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) failure: Option<Failure>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ccache: Option<CcacheStats>,
+}
+
+#[derive(Default, Serialize)]
+pub(crate) struct Report {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ccache: Option<CcacheReport>,
+    pub(crate) packages: Vec<PackageReport>,
+}
+
+impl Report {
+    pub(crate) fn add(&mut self, package: PackageReport) {
+        self.packages.push(package)
+    }
+
+    pub(crate) fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut temp_name = path.as_ref().as_os_str().to_owned();
+        temp_name.push(".temp");
+        let file = match File::create(&temp_name) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to create report '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = serde_json::to_writer_pretty(file, self) {
+            log::error!("Failed to write report '{}': {}",
+                path.as_ref().display(), e);
+            return Err(std::io::Error::other(e).into())
+        }
+        if let Err(e) = std::fs::rename(&temp_name, &path) {
+            log::error!("Failed to move report into '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        }
+        log::info!("Wrote report '{}'", path.as_ref().display());
+        Ok(())
+    }
+}
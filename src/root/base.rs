@@ -17,6 +17,7 @@ use nix::mount::{
     };
 
 use crate::{
+        arch::Target,
         error::{
             Error,
             Result
@@ -78,14 +79,19 @@ impl BaseRoot {
     }
 
     /// Root is expected
-    fn setup(&self, actual_identity: &IdentityActual) -> Result<&Self> {
+    fn setup(&self, actual_identity: &IdentityActual, target: &Target)
+        -> Result<&Self>
+    {
         log::warn!("Finishing base root setup");
         let builder = self.builder(actual_identity)?;
         self.copy_file_same("etc/passwd")?
             .copy_file_same("etc/group")?
-            .copy_file_same("etc/shadow")?
-            .copy_file_same("etc/makepkg.conf")?
-            .create_home(actual_identity)?;
+            .copy_file_same("etc/shadow")?;
+        // Foreign roots keep the makepkg.conf from their own pacman package
+        if ! target.is_foreign() {
+            self.copy_file_same("etc/makepkg.conf")?;
+        }
+        self.create_home(actual_identity)?;
         create_dir_all(&builder)
             .or_else(|e|{
                 log::error!("Failed to create chroot builder dir: {}", e);
@@ -102,7 +108,7 @@ impl BaseRoot {
         Ok(self)
     }
 
-    pub(crate) fn db_only() -> Result<Self> {
+    pub(crate) fn db_only(target: &Target) -> Result<Self> {
         IdentityActual::as_root(||MountedFolder::remove_all())?;
         log::info!("Creating base chroot (DB only)");
         let root = Self(MountedFolder(PathBuf::from("roots/base")));
@@ -111,8 +117,8 @@ impl BaseRoot {
                 .base_layout()?
                 .bind_self()?
                 .base_mounts()?
-                .refresh_dbs()?;
-            Ok(())
+                .refresh_dbs(target)?;
+            target.setup_qemu(root.path())
         })?;
         log::info!("Created base chroot (DB only)");
         Ok(root)
@@ -120,7 +126,9 @@ impl BaseRoot {
 
     /// Create a base rootfs containing the minimum packages and user setup
     /// This should not be used directly for building packages
-    pub(crate) fn _new<I, S>(actual_identity: &IdentityActual, pkgs: I)
+    pub(crate) fn _new<I, S>(
+        actual_identity: &IdentityActual, target: &Target, pkgs: I
+    )
         -> Result<Self>
     where
         I: IntoIterator<Item = S>,
@@ -134,9 +142,10 @@ impl BaseRoot {
                 .base_layout()?
                 .bind_self()?
                 .base_mounts()?
-                .refresh_dbs()?
-                .install_pkgs(pkgs)?
-                .setup(actual_identity)?
+                .refresh_dbs(target)?;
+            target.setup_qemu(root.path())?;
+            root.install_pkgs(target, pkgs)?
+                .setup(actual_identity, target)?
                 .umount_recursive()?;
             Ok(())
         })?;
@@ -145,7 +154,9 @@ impl BaseRoot {
     }
 
     /// Finish a DB-only base root
-    pub(crate) fn finish<I, S>(&self, actual_identity: &IdentityActual, pkgs: I)
+    pub(crate) fn finish<I, S>(
+        &self, actual_identity: &IdentityActual, target: &Target, pkgs: I
+    )
         -> Result<&Self>
     where
         I: IntoIterator<Item = S>,
@@ -153,8 +164,8 @@ impl BaseRoot {
     {
         log::info!("Finishing base chroot");
         IdentityActual::as_root(||{
-            self.install_pkgs(pkgs)?
-                .setup(actual_identity)?
+            self.install_pkgs(target, pkgs)?
+                .setup(actual_identity, target)?
                 .umount_recursive()?;
             Ok(())
         })?;
@@ -15,6 +15,7 @@ use std::{
 use nix::mount::MsFlags;
 
 use crate::{
+        arch::Target,
         error::{
             Error,
             Result
@@ -154,12 +155,13 @@ pub(crate) trait CommonRoot {
     }
 
     // Todo: split out common wait child parts
-    fn refresh_dbs(&self) -> Result<&Self> {
+    fn refresh_dbs(&self, target: &Target) -> Result<&Self> {
         crate::child::no_output_check(
             crate::logfile::LogFile::new(
                 crate::logfile::LogType::Pacman, "refresh-DB")?
                 .set_command(
-                    Command::new("/usr/bin/pacman")
+                    target.set_pacman_command(
+                        &mut Command::new("/usr/bin/pacman"))
                     .env("LANG", "C")
                     .arg("-Sy")
                     .arg("--root")
@@ -168,14 +170,14 @@ pub(crate) trait CommonRoot {
             "refresh pacman DB").and(Ok(self))
     }
 
-    fn install_pkgs<I, S>(&self, pkgs: I)
+    fn install_pkgs<I, S>(&self, target: &Target, pkgs: I)
         -> Result<&Self>
     where
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>,
     {
         let mut command = Command::new("/usr/bin/pacman");
-        command
+        target.set_pacman_command(&mut command)
             .env("LANG", "C")
             .arg("-S")
             .arg("--root")
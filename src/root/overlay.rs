@@ -3,7 +3,9 @@ use std::{
         fs::{
             create_dir_all,
             remove_dir_all,
+            OpenOptions,
         },
+        io::Write,
         path::{
             Path,
             PathBuf,
@@ -16,6 +18,7 @@ use nix::mount::{
 };
 
 use crate::{
+        arch::Target,
         child::ForkedChild,
         error::{
             Error,
@@ -98,6 +101,52 @@ impl OverlayRoot {
         Ok(self)
     }
 
+    /// Append overrides to makepkg.conf, the file gets copied up into upper
+    /// so the base root stays untouched
+    fn makepkg_conf(&self, append: &str) -> Result<&Self> {
+        if append.is_empty() {
+            return Ok(self)
+        }
+        let path = self.path().join("etc/makepkg.conf");
+        let mut file = match OpenOptions::new().append(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open '{}' to append: {}",
+                    path.display(), e);
+                return Err(Error::IoError(e))
+            },
+        };
+        if let Err(e) = file.write_all(append.as_bytes()) {
+            log::error!("Failed to append to '{}': {}", path.display(), e);
+            return Err(Error::IoError(e))
+        }
+        Ok(self)
+    }
+
+    /// Bind the compiler cache dir managed by the builder, if enabled
+    fn bind_ccache(&self, ccache: Option<&Path>) -> Result<&Self> {
+        let ccache = match ccache {
+            Some(ccache) => ccache,
+            None => return Ok(self),
+        };
+        let target = self.path().join(crate::ccache::CHROOT_DIR);
+        create_dir_all(&target).map_err(|e|{
+            log::error!("Failed to create '{}': {}", target.display(), e);
+            Error::IoError(e)
+        })?;
+        mount(Some(ccache),
+            &target,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>)
+        .map_err(|e|{
+            log::error!("Failed to bind mount compiler cache '{}': {}",
+                ccache.display(), e);
+            Error::NixErrno(e)
+        })?;
+        Ok(self)
+    }
+
     fn bind_homedirs<I, S>(&self, actual_identity: &IdentityActual, home_dirs: I)
         -> Result<&Self>
     where
@@ -144,7 +193,8 @@ impl OverlayRoot {
     }
 
     fn new_child<I, S, I2, S2>(
-        name: &str, actual_identity: &IdentityActual, pkgs: I, home_dirs: I2,
+        name: &str, actual_identity: &IdentityActual, target: &Target,
+        pkgs: I, home_dirs: I2, makepkg_conf: &str, ccache: Option<&Path>,
         nonet: bool
     ) -> Result<(Self, ForkedChild)>
     where
@@ -159,10 +209,12 @@ impl OverlayRoot {
             root.remove()?
                 .overlay()?
                 .base_mounts()?
-                .install_pkgs(pkgs)?
+                .install_pkgs(target, pkgs)?
+                .makepkg_conf(makepkg_conf)?
                 .create_home(actual_identity)?
                 .bind_builder(actual_identity)?
-                .bind_homedirs(actual_identity, home_dirs)?;
+                .bind_homedirs(actual_identity, home_dirs)?
+                .bind_ccache(ccache)?;
             if ! nonet {
                 root.resolv()?;
             }
@@ -175,7 +227,8 @@ impl OverlayRoot {
     /// Different from base, overlay would have upper, work, and merged.
     /// Note that the pkgs here can only come from repos, not as raw pkg files.
     pub(crate) fn _new<I, S, I2, S2>(
-        name: &str, actual_identity: &IdentityActual, pkgs: I, home_dirs: I2,
+        name: &str, actual_identity: &IdentityActual, target: &Target,
+        pkgs: I, home_dirs: I2, makepkg_conf: &str, ccache: Option<&Path>,
         nonet: bool
     ) -> Result<Self>
     where
@@ -190,10 +243,12 @@ impl OverlayRoot {
             root.remove()?
                 .overlay()?
                 .base_mounts()?
-                .install_pkgs(pkgs)?
+                .install_pkgs(target, pkgs)?
+                .makepkg_conf(makepkg_conf)?
                 .create_home(actual_identity)?
                 .bind_builder(actual_identity)?
-                .bind_homedirs(actual_identity, home_dirs)?;
+                .bind_homedirs(actual_identity, home_dirs)?
+                .bind_ccache(ccache)?;
             if ! nonet {
                 root.resolv()?;
             }
@@ -237,7 +292,8 @@ pub(crate) struct BootstrappingOverlayRoot {
 
 impl BootstrappingOverlayRoot {
     pub(crate) fn new<I, S, I2, S2>(
-        name: &str, actual_identity: &IdentityActual, pkgs: I, home_dirs: I2,
+        name: &str, actual_identity: &IdentityActual, target: &Target,
+        pkgs: I, home_dirs: I2, makepkg_conf: &str, ccache: Option<&Path>,
         nonet: bool
     ) -> Result<Self>
     where
@@ -247,7 +303,8 @@ impl BootstrappingOverlayRoot {
         S2: AsRef<str>
     {
         let (root, child) = OverlayRoot::new_child(
-            name, actual_identity, pkgs, home_dirs, nonet)?;
+            name, actual_identity, target, pkgs, home_dirs, makepkg_conf,
+            ccache, nonet)?;
         Ok(Self {
             root,
             child,
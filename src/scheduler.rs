@@ -0,0 +1,128 @@
+// Limits of parallelism, for both syncing sources and building
+use std::{
+        collections::HashMap,
+        time::Duration,
+    };
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+        config::Scheduler as SchedulerConfig,
+        error::{
+            Error,
+            Result,
+        },
+        metrics,
+    };
+
+const BUILD_TRIES: usize = 3;
+const THREADS: usize = 10;
+/// AUR does not like to be hammered, so it's synced with a single thread
+/// unless overriden
+const DOMAINS: &[(&str, usize)] = &[("aur.archlinux.org", 1)];
+const PRESSURE: f32 = 10.00;
+const LOAD_MARGIN: usize = 2;
+const POLL_INTERVAL: u64 = 100;
+const BUSY_INTERVAL: u64 = 1000;
+
+pub(crate) struct Scheduler {
+    pub(crate) build_tries: usize,
+    threads: usize,
+    /// Keyed by hash of domain, the same as the sources map
+    domains: HashMap<u64, usize>,
+    cores: usize,
+    jobs: usize,
+    weights: HashMap<String, usize>,
+    pressure: f32,
+    load_margin: usize,
+    pub(crate) poll_interval: Duration,
+    pub(crate) busy_interval: Duration,
+}
+
+impl Scheduler {
+    pub(crate) fn from_config(config: Option<&SchedulerConfig>)
+        -> Result<Self>
+    {
+        let default = SchedulerConfig::default();
+        let config = config.unwrap_or(&default);
+        let cores = match procfs::CpuInfo::new() {
+            Ok(cpuinfo) => cpuinfo.num_cores(),
+            Err(e) => {
+                log::error!("Failed to get cpuinfo: {}", e);
+                return Err(Error::ProcError(e))
+            },
+        };
+        let mut domains: HashMap<u64, usize> = DOMAINS.iter().map(
+            |(domain, threads)| (xxh3_64(domain.as_bytes()), *threads))
+            .collect();
+        if let Some(config_domains) = &config.domains {
+            for (domain, threads) in config_domains.iter() {
+                domains.insert(xxh3_64(domain.as_bytes()), *threads);
+            }
+        }
+        Ok(Self {
+            build_tries: config.build_tries.unwrap_or(BUILD_TRIES),
+            threads: config.threads.unwrap_or(THREADS),
+            domains,
+            cores,
+            jobs: config.jobs.unwrap_or(cores),
+            weights: config.weights.clone().unwrap_or_default(),
+            pressure: config.pressure.unwrap_or(PRESSURE),
+            load_margin: config.load_margin.unwrap_or(LOAD_MARGIN),
+            poll_interval: Duration::from_millis(
+                config.poll_interval.unwrap_or(POLL_INTERVAL)),
+            busy_interval: Duration::from_millis(
+                config.busy_interval.unwrap_or(BUSY_INTERVAL)),
+        })
+    }
+
+    /// Threads to sync sources from a domain, by its hash
+    pub(crate) fn domain_threads(&self, domain: u64) -> usize {
+        *self.domains.get(&domain).unwrap_or(&self.threads)
+    }
+
+    /// Jobs a package counts as while building
+    pub(crate) fn weight(&self, base: &str) -> usize {
+        *self.weights.get(base).unwrap_or(&1)
+    }
+
+    /// Whether no more job should be started, extra is the capacity outside of
+    /// this machine (e.g. distcc slots) that's only used for the jobs count
+    pub(crate) fn heavy_load(&self, jobs: usize, extra: usize) -> bool {
+        let heavy_load = self.check_heavy_load(jobs, extra);
+        metrics::record_heavy_load(heavy_load);
+        heavy_load
+    }
+
+    fn check_heavy_load(&self, jobs: usize, extra: usize) -> bool {
+        if jobs >= self.jobs + extra {
+            return true
+        }
+        if match procfs::CpuPressure::new() {
+            Ok(cpu_pressure) => {
+                let some = cpu_pressure.some;
+                metrics::record_pressure([some.avg10, some.avg60, some.avg300]);
+                some.avg10 > self.pressure || some.avg60 > self.pressure ||
+                    some.avg300 > self.pressure
+            },
+            Err(e) => {
+                log::error!("Failed to get CPU pressure: {}", e);
+                false
+            },
+        } {
+            return true
+        }
+        match procfs::LoadAverage::new() {
+            Ok(load_avg) => {
+                let max_load = (self.cores + self.load_margin) as f32;
+                load_avg.one >= max_load ||
+                load_avg.five >= max_load ||
+                load_avg.fifteen >= max_load
+            },
+            Err(e) => {
+                log::error!("Failed to get load avg: {}", e);
+                true
+            },
+        }
+    }
+}
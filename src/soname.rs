@@ -0,0 +1,429 @@
+// Sonames built packages link against, for the soname dephash strategy
+use std::{
+        collections::BTreeMap,
+        fs::File,
+        io::{
+            Read,
+            Seek,
+            SeekFrom,
+        },
+        path::Path,
+    };
+
+use serde::{
+        Deserialize,
+        Serialize,
+    };
+
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::error::{
+        Error,
+        Result,
+    };
+
+pub(crate) const SONAMESFILE: &str = "sonames.yaml";
+
+const SHT_DYNAMIC: u32 = 6;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+
+/// What the last build of a PKGBUILD linked against
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Linked {
+    pub(crate) pkgid: String,
+    /// Sonames its dephash was calculated from, none for all sonames provided
+    /// by its deps
+    pub(crate) hashed: Option<Vec<String>>,
+    /// DT_NEEDED of all ELF files in its packages
+    pub(crate) needed: Vec<String>,
+}
+
+/// By pkgbase
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct Sonames {
+    linked: BTreeMap<String, Linked>,
+}
+
+impl Sonames {
+    fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open sonames record '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        match serde_yaml::from_reader(file) {
+            Ok(sonames) => Ok(sonames),
+            Err(e) => {
+                log::error!("Failed to parse sonames record '{}': {}",
+                    path.as_ref().display(), e);
+                Err(Error::InvalidConfig)
+            },
+        }
+    }
+
+    /// Read the existing record if there's one, otherwise an empty one
+    pub(crate) fn from_file_or_default<P: AsRef<Path>>(path: P) -> Self {
+        if ! path.as_ref().exists() {
+            return Self::default()
+        }
+        match Self::from_file(&path) {
+            Ok(sonames) => sonames,
+            Err(_) => {
+                log::warn!("Existing sonames record '{}' unusable, would \
+                    overwrite it", path.as_ref().display());
+                Self::default()
+            },
+        }
+    }
+
+    pub(crate) fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut temp_name = path.as_ref().as_os_str().to_owned();
+        temp_name.push(".temp");
+        let file = match File::create(&temp_name) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to create sonames record '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = serde_yaml::to_writer(file, self) {
+            log::error!("Failed to write sonames record '{}': {}",
+                path.as_ref().display(), e);
+            return Err(std::io::Error::other(e).into())
+        }
+        if let Err(e) = std::fs::rename(&temp_name, &path) {
+            log::error!("Failed to move sonames record into '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, base: &str) -> Option<&Linked> {
+        self.linked.get(base)
+    }
+
+    pub(crate) fn record(&mut self, base: &str, linked: Linked) {
+        self.linked.insert(base.to_string(), linked);
+    }
+}
+
+/// Whether a provide is of a soname, e.g. 'libfoo.so=1-64' or
+/// 'lib:libfoo.so.1'
+pub(crate) fn is_soname_provide(provide: &str) -> bool {
+    if provide.starts_with("lib:") {
+        return true
+    }
+    match provide.split_once('=') {
+        Some((name, _)) => name.ends_with(".so"),
+        None => false,
+    }
+}
+
+/// Whether a provide is of the soname, e.g. both 'libfoo.so=1-64' and
+/// 'lib:libfoo.so.1' are of 'libfoo.so.1'
+pub(crate) fn provides_soname(provide: &str, soname: &str) -> bool {
+    if let Some(name) = provide.strip_prefix("lib:") {
+        return name == soname
+    }
+    let (name, version) = match provide.split_once('=') {
+        Some(split) => split,
+        None => return false,
+    };
+    let version = match version.rsplit_once('-') {
+        Some((version, _)) => version,
+        None => version,
+    };
+    match soname.strip_prefix(name) {
+        Some(rest) => rest.strip_prefix('.') == Some(version),
+        None => false,
+    }
+}
+
+/// Hash of the soname provides of the deps, only those of the sonames if set
+pub(crate) fn hash(provides: &[String], sonames: Option<&[String]>) -> u64 {
+    let mut hash = Xxh3::new();
+    for provide in provides.iter() {
+        if let Some(sonames) = sonames {
+            if ! sonames.iter().any(|soname| provides_soname(provide, soname)) {
+                continue
+            }
+        }
+        hash.update(provide.as_bytes());
+        hash.update(b"\n");
+    }
+    hash.digest()
+}
+
+fn read_at(file: &mut File, offset: u64, buffer: &mut [u8])
+    -> std::io::Result<()>
+{
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)
+}
+
+/// ELF class and byte order, to read fields of the right width
+struct ElfReader {
+    wide: bool,
+    little: bool,
+}
+
+impl ElfReader {
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self.little {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self.little {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        }
+    }
+
+    /// Either 32-bit or 64-bit depending on the class
+    fn word(&self, bytes: &[u8]) -> u64 {
+        if ! self.wide {
+            return self.u32(bytes) as u64
+        }
+        let mut word = [0; 8];
+        word.copy_from_slice(&bytes[0..8]);
+        match self.little {
+            true => u64::from_le_bytes(word),
+            false => u64::from_be_bytes(word),
+        }
+    }
+}
+
+/// DT_NEEDED of an ELF file through its dynamic section, none if the file is
+/// not a dynamically linked ELF
+pub(crate) fn needed_in_elf<P: AsRef<Path>>(path: P)
+    -> std::io::Result<Option<Vec<String>>>
+{
+    let mut file = File::open(&path)?;
+    let len = file.metadata()?.len();
+    let mut ident = [0; 16];
+    if file.read_exact(&mut ident).is_err() || &ident[0..4] != b"\x7fELF" {
+        return Ok(None)
+    }
+    let reader = ElfReader { wide: ident[4] == 2, little: ident[5] == 1 };
+    let (header_len, section_len) = match reader.wide {
+        true => (64, 64),
+        false => (52, 40),
+    };
+    if len < header_len as u64 {
+        return Ok(None)
+    }
+    let mut header = vec![0; header_len];
+    read_at(&mut file, 0, &mut header)?;
+    let (shoff, shentsize, shnum) = match reader.wide {
+        true => (reader.word(&header[0x28..]), reader.u16(&header[0x3a..]),
+                reader.u16(&header[0x3c..])),
+        false => (reader.word(&header[0x20..]), reader.u16(&header[0x2e..]),
+                reader.u16(&header[0x30..])),
+    };
+    if shoff == 0 || (shentsize as usize) < section_len ||
+        shoff.saturating_add(shnum as u64 * shentsize as u64) > len
+    {
+        return Ok(None)
+    }
+    // (type, link, offset, size) of each section
+    let mut sections = vec![];
+    let mut section = vec![0; section_len];
+    for index in 0..shnum as u64 {
+        read_at(&mut file, shoff + index * shentsize as u64, &mut section)?;
+        sections.push(match reader.wide {
+            true => (reader.u32(&section[4..]), reader.u32(&section[0x28..]),
+                    reader.word(&section[0x18..]),
+                    reader.word(&section[0x20..])),
+            false => (reader.u32(&section[4..]), reader.u32(&section[0x18..]),
+                    reader.word(&section[0x10..]),
+                    reader.word(&section[0x14..])),
+        })
+    }
+    let (link, offset, size) = match sections.iter().find(
+        |section| section.0 == SHT_DYNAMIC)
+    {
+        Some((_, link, offset, size)) => (*link, *offset, *size),
+        None => return Ok(None),
+    };
+    let (strtab_offset, strtab_size) = match sections.get(link as usize) {
+        Some((_, _, offset, size)) => (*offset, *size),
+        None => return Ok(None),
+    };
+    // Do not trust sizes in a malformed file
+    if offset.saturating_add(size) > len ||
+        strtab_offset.saturating_add(strtab_size) > len
+    {
+        return Ok(None)
+    }
+    let mut dynamic = vec![0; size as usize];
+    read_at(&mut file, offset, &mut dynamic)?;
+    let mut strtab = vec![0; strtab_size as usize];
+    read_at(&mut file, strtab_offset, &mut strtab)?;
+    let entry_len = match reader.wide {
+        true => 16,
+        false => 8,
+    };
+    let half = entry_len / 2;
+    let mut needed = vec![];
+    for entry in dynamic.chunks_exact(entry_len) {
+        let tag = reader.word(entry);
+        if tag == DT_NULL {
+            break
+        }
+        if tag != DT_NEEDED {
+            continue
+        }
+        let start = reader.word(&entry[half..]) as usize;
+        if let Some(name) = strtab.get(start..) {
+            let end = name.iter().position(|byte| *byte == 0)
+                .unwrap_or(name.len());
+            needed.push(String::from_utf8_lossy(&name[..end]).into_owned())
+        }
+    }
+    Ok(Some(needed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal ELF with only a null section, .dynstr and .dynamic
+    fn elf(wide: bool, little: bool, needed: &[&str]) -> Vec<u8> {
+        let put = |bytes: &mut Vec<u8>, value: u64, len: usize| {
+            let value = value.to_le_bytes();
+            match little {
+                true => bytes.extend_from_slice(&value[..len]),
+                false => bytes.extend(value[..len].iter().rev()),
+            }
+        };
+        let word = if wide { 8 } else { 4 };
+        let (header_len, section_len) = if wide { (64, 64) } else { (52, 40) };
+        let mut strtab = vec![0];
+        let mut dynamic = vec![];
+        for name in needed {
+            put(&mut dynamic, DT_NEEDED, word);
+            put(&mut dynamic, strtab.len() as u64, word);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+        put(&mut dynamic, DT_NULL, word);
+        put(&mut dynamic, 0, word);
+        let strtab_offset = header_len as u64;
+        let dynamic_offset = strtab_offset + strtab.len() as u64;
+        let shoff = dynamic_offset + dynamic.len() as u64;
+
+        let mut bytes = b"\x7fELF".to_vec();
+        bytes.extend([if wide { 2 } else { 1 }, if little { 1 } else { 2 }, 1]);
+        bytes.resize(16, 0);
+        put(&mut bytes, 3, 2); // e_type, ET_DYN
+        put(&mut bytes, 62, 2); // e_machine
+        put(&mut bytes, 1, 4); // e_version
+        put(&mut bytes, 0, word); // e_entry
+        put(&mut bytes, 0, word); // e_phoff
+        put(&mut bytes, shoff, word);
+        put(&mut bytes, 0, 4); // e_flags
+        put(&mut bytes, header_len as u64, 2);
+        put(&mut bytes, 0, 2); // e_phentsize
+        put(&mut bytes, 0, 2); // e_phnum
+        put(&mut bytes, section_len as u64, 2);
+        put(&mut bytes, 3, 2); // e_shnum
+        put(&mut bytes, 0, 2); // e_shstrndx
+        assert_eq!(bytes.len(), header_len);
+        bytes.extend(&strtab);
+        bytes.extend(&dynamic);
+        // (type, offset, size, link)
+        for (section_type, offset, size, link) in [
+            (0, 0, 0, 0),
+            (3, strtab_offset, strtab.len() as u64, 0),
+            (SHT_DYNAMIC, dynamic_offset, dynamic.len() as u64, 1),
+        ] {
+            put(&mut bytes, 0, 4); // sh_name
+            put(&mut bytes, section_type as u64, 4);
+            put(&mut bytes, 0, word); // sh_flags
+            put(&mut bytes, 0, word); // sh_addr
+            put(&mut bytes, offset, word);
+            put(&mut bytes, size, word);
+            put(&mut bytes, link, 4);
+            put(&mut bytes, 0, 4); // sh_info
+            put(&mut bytes, 0, word); // sh_addralign
+            put(&mut bytes, 0, word); // sh_entsize
+        }
+        bytes
+    }
+
+    fn needed_in_bytes(bytes: &[u8]) -> Option<Vec<String>> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("elf");
+        std::fs::write(&path, bytes).unwrap();
+        needed_in_elf(&path).unwrap()
+    }
+
+    #[test]
+    fn needed_all_classes() {
+        let needed = ["libfoo.so.1", "libc.so.6"];
+        for (wide, little) in [
+            (false, true), (true, true), (false, false), (true, false)]
+        {
+            assert_eq!(needed_in_bytes(&elf(wide, little, &needed)),
+                Some(vec!["libfoo.so.1".to_string(), "libc.so.6".to_string()]));
+        }
+        assert_eq!(needed_in_bytes(&elf(true, true, &[])), Some(vec![]));
+    }
+
+    #[test]
+    fn not_elf() {
+        assert_eq!(needed_in_bytes(b""), None);
+        assert_eq!(needed_in_bytes(b"#!/bin/sh\necho hello\n"), None);
+        assert_eq!(needed_in_bytes(b"\x7fEL"), None);
+    }
+
+    #[test]
+    fn truncated() {
+        for wide in [false, true] {
+            let bytes = elf(wide, true, &["libfoo.so.1"]);
+            for len in 0..bytes.len() {
+                assert_eq!(needed_in_bytes(&bytes[..len]), None);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let bytes = elf(true, true, &["libfoo.so.1"]);
+        let shoff = bytes.len() - 3 * 64;
+        // Section headers past the end
+        let mut broken = bytes.clone();
+        broken[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(needed_in_bytes(&broken), None);
+        // Section header entries too small
+        let mut broken = bytes.clone();
+        broken[0x3a..0x3c].copy_from_slice(&8u16.to_le_bytes());
+        assert_eq!(needed_in_bytes(&broken), None);
+        // .dynamic larger than the file
+        let mut broken = bytes.clone();
+        let size = shoff + 2 * 64 + 0x20;
+        broken[size..size + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(needed_in_bytes(&broken), None);
+        // .dynamic linked to a section that does not exist
+        let mut broken = bytes.clone();
+        let link = shoff + 2 * 64 + 0x28;
+        broken[link..link + 4].copy_from_slice(&7u32.to_le_bytes());
+        assert_eq!(needed_in_bytes(&broken), None);
+        // Name offsets past the end of .dynstr are skipped
+        let mut broken = bytes;
+        let name = 64 + "\0libfoo.so.1\0".len() + 8;
+        broken[name..name + 8].copy_from_slice(&1000u64.to_le_bytes());
+        assert_eq!(needed_in_bytes(&broken), Some(vec![]));
+    }
+}
@@ -1,5 +1,8 @@
 use std::{
-        collections::HashMap,
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
         str::FromStr,
     };
 
@@ -43,6 +46,7 @@ pub(crate) use clean::{
     remove_unused,
 };
 pub(crate) use extract::extract;
+pub(crate) use netfile::download_file;
 pub(crate) use proxy::Proxy;
 
 #[derive(Clone)]
@@ -87,6 +91,50 @@ pub(crate) trait MapByDomain {
     }
 }
 
+impl Source {
+    pub(crate) fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub(crate) fn is_git(&self) -> bool {
+        matches!(self.protocol, Protocol::Vcs { protocol: VcsProtocol::Git })
+    }
+
+    pub(crate) fn is_netfile(&self) -> bool {
+        matches!(self.protocol, Protocol::Netfile { protocol: _ })
+    }
+
+    /// All integrity checksums of this source, keyed by the makepkg array name
+    pub(crate) fn cksums(&self) -> BTreeMap<String, String> {
+        let mut cksums = BTreeMap::new();
+        if let Some(ck) = &self.ck {
+            cksums.insert(String::from("cksums"), ck.to_string());
+        }
+        if let Some(md5) = &self.md5 {
+            cksums.insert(String::from("md5sums"), md5.to_string());
+        }
+        if let Some(sha1) = &self.sha1 {
+            cksums.insert(String::from("sha1sums"), sha1.to_string());
+        }
+        if let Some(sha224) = &self.sha224 {
+            cksums.insert(String::from("sha224sums"), sha224.to_string());
+        }
+        if let Some(sha256) = &self.sha256 {
+            cksums.insert(String::from("sha256sums"), sha256.to_string());
+        }
+        if let Some(sha384) = &self.sha384 {
+            cksums.insert(String::from("sha384sums"), sha384.to_string());
+        }
+        if let Some(sha512) = &self.sha512 {
+            cksums.insert(String::from("sha512sums"), sha512.to_string());
+        }
+        if let Some(b2) = &self.b2 {
+            cksums.insert(String::from("b2sums"), b2.to_string());
+        }
+        cksums
+    }
+}
+
 impl MapByDomain for Source {
     fn url(&self) -> &str {
         self.url.as_str()
@@ -58,6 +58,7 @@ pub(crate) fn cache_sources_mt(
     skipint: bool,
     proxy: Option<&Proxy>,
     gmr: Option<&super::git::Gmr>,
+    scheduler: &crate::scheduler::Scheduler,
     terminal: bool
 ) -> Result<()>
 {
@@ -71,7 +72,6 @@ pub(crate) fn cache_sources_mt(
         get_domain_threads_map(&git_sources_map)?;
     let mut git_repos_map =
         Source::to_repos_map(git_sources_map, "sources/git", gmr)?;
-    const MAX_THREADS: usize = 10;
     let mut bad = false;
     while netfile_sources_map.len() > 0 || git_repos_map.len() > 0 {
         for (domain, netfile_sources) in
@@ -79,8 +79,9 @@ pub(crate) fn cache_sources_mt(
         {
             let netfile_threads = 
                 get_domain_threads_from_map(domain, &mut netfile_threads_map)?;
+            let max_threads = scheduler.domain_threads(*domain);
             while netfile_sources.len() > 0 &&
-                netfile_threads.len() < MAX_THREADS
+                netfile_threads.len() < max_threads
             {
                 let netfile_source = netfile_sources
                     .pop()
@@ -104,8 +105,9 @@ pub(crate) fn cache_sources_mt(
         {
             let git_threads = 
                 get_domain_threads_from_map(domain, &mut git_threads_map)?;
+            let max_threads = scheduler.domain_threads(*domain);
             while git_repos.len() > 0 &&
-                git_threads.len() < MAX_THREADS
+                git_threads.len() < max_threads
             {
                 let git_repo = git_repos
                     .pop()
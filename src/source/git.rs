@@ -5,6 +5,7 @@ use git2::{
         Branch,
         build::CheckoutBuilder,
         Commit,
+        Diff,
         FetchOptions,
         Oid,
         Remote,
@@ -13,19 +14,32 @@ use git2::{
         Progress,
         ProxyOptions,
         Tree, AutotagOption, FetchPrune, ErrorClass, ErrorCode, BranchType,
+        FileMode, Signature, Time,
     };
 use url::Url;
 use std::{
-        collections::HashMap,
-        fs::metadata,
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        fmt::Display,
+        fs::{
+            metadata,
+            read_dir,
+            read_link,
+        },
         io::Write,
         path::{
             Path,
             PathBuf
         },
-        os::unix::fs::MetadataExt,
+        os::unix::{
+            ffi::OsStrExt,
+            fs::MetadataExt,
+        },
         str::FromStr,
         thread,
+        time::Instant,
     };
 
 use crate::{
@@ -33,6 +47,10 @@ use crate::{
             Error,
             Result
         },
+        lock::Lock,
+        logger,
+        metrics,
+        scheduler::Scheduler,
         source::{
             aur::AurResult,
             Proxy
@@ -76,20 +94,50 @@ fn optional_gmr(gmr: Option<&Gmr>, orig: &str) -> Option<String> {
     }
 }
 
+/// Where in a repo something should be taken from
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Reference {
+    Branch (String),
+    Tag (String),
+    Commit (String),
+}
+
+impl Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reference::Branch(branch) => write!(f, "branch:{}", branch),
+            Reference::Tag(tag) => write!(f, "tag:{}", tag),
+            Reference::Commit(commit) => write!(f, "commit:{}", commit),
+        }
+    }
+}
+
+impl Reference {
+    /// The ref to fetch for this reference, `None` means all refs are needed
+    pub(crate) fn refname(&self) -> Option<String> {
+        match self {
+            Reference::Branch(branch) =>
+                Some(format!("refs/heads/{}", branch)),
+            Reference::Tag(tag) => Some(format!("refs/tags/{}", tag)),
+            Reference::Commit(_) => None,
+        }
+    }
+}
+
 pub(crate) struct Repo {
     path: PathBuf,
     url: String,
     mirror: Option<String>,
     repo: Repository,
-    branches: Vec<String>,
+    refs: Vec<String>,
 }
 
 pub(crate) trait ToReposMap {
-    fn branch(&self) -> Option<String>;
+    fn refname(&self) -> Option<String>;
     fn url(&self) -> &str;
     fn hash_url(&self) -> u64;
     fn path(&self) -> Option<&Path>;
-    fn to_repo(&self, parent: &str, gmr: Option<&Gmr>, branch: Option<String>)
+    fn to_repo(&self, parent: &str, gmr: Option<&Gmr>, refname: Option<String>)
         -> Result<Repo>
     {
         let mut repo = match self.path() {
@@ -99,8 +147,8 @@ pub(crate) trait ToReposMap {
                                     parent, self.hash_url())),
                 self.url(), gmr),
         }?;
-        if let Some(branch) = branch {
-            repo.branches.push(branch)
+        if let Some(refname) = refname {
+            repo.refs.push(refname)
         }
         Ok(repo)
     }
@@ -121,34 +169,34 @@ pub(crate) trait ToReposMap {
                         existing = Some(repo)
                     }
                 }
-                let new_branch = source.branch();
+                let new_ref = source.refname();
                 if let Some(existing) = existing {
-                    let existing_branches =
-                        &mut existing.branches;
-                    if existing_branches.len() == 0 {
+                    let existing_refs =
+                        &mut existing.refs;
+                    if existing_refs.len() == 0 {
                         continue
                     }
-                    let new_branch = match new_branch {
-                        Some(branch) => branch,
+                    let new_ref = match new_ref {
+                        Some(refname) => refname,
                         None => {
-                            existing_branches.clear();
+                            existing_refs.clear();
                             continue
                         },
                     };
-                    let mut branch_found = false;
-                    for branch in existing_branches.iter() {
-                        if &new_branch == branch {
-                            branch_found = true;
+                    let mut ref_found = false;
+                    for refname in existing_refs.iter() {
+                        if &new_ref == refname {
+                            ref_found = true;
                             break
                         }
                     }
-                    if branch_found {
+                    if ref_found {
                         continue
                     }
-                    existing_branches.push(new_branch);
+                    existing_refs.push(new_ref);
                     continue
                 }
-                repos.push(source.to_repo(parent, gmr, new_branch)?);
+                repos.push(source.to_repo(parent, gmr, new_ref)?);
             }
             if let Some(_) = repos_map.insert(domain, repos) {
                 log::error!("Duplicated key for repos map");
@@ -173,7 +221,7 @@ impl ToReposMap for super::Source {
         None
     }
 
-    fn branch(&self) -> Option<String> {
+    fn refname(&self) -> Option<String> {
         None
     }
 }
@@ -187,6 +235,71 @@ pub(super) fn push_source(sources: &mut Vec<super::Source>, source: &super::Sour
     sources.push(source.clone())
 }
 
+/// Record the refs of all git sources, url => (ref => commit)
+pub(crate) fn lock_sources<'a, I>(sources: I)
+    -> Result<BTreeMap<String, BTreeMap<String, String>>>
+where
+    I: IntoIterator<Item = &'a super::Source>
+{
+    let mut gits = BTreeMap::new();
+    for source in sources {
+        if gits.contains_key(&source.url) {
+            continue
+        }
+        let repo = source.to_repo("sources/git", None, None)?;
+        gits.insert(source.url.clone(), repo.get_refs()?);
+    }
+    Ok(gits)
+}
+
+/// Pin all git sources to their locked refs, fetch only if the locked commits
+/// are missing
+pub(crate) fn pin_sources(
+    sources: &[super::Source], lock: &Lock, proxy: Option<&Proxy>,
+    gmr: Option<&Gmr>, terminal: bool
+) -> Result<()>
+{
+    let mut r = Ok(());
+    for source in sources.iter() {
+        let refs = match lock.get_git(&source.url) {
+            Ok(refs) => refs,
+            Err(e) => {
+                r = Err(e);
+                continue
+            },
+        };
+        let repo = match source.to_repo("sources/git", gmr, None) {
+            Ok(repo) => repo,
+            Err(e) => {
+                r = Err(e);
+                continue
+            },
+        };
+        if ! repo.has_refs_commits(refs) {
+            log::warn!("Locked commits missing for git source '{}', fetching",
+                source.url);
+            if let Err(e) = repo.sync(proxy, terminal) {
+                log::error!("Failed to fetch locked commits for git source \
+                    '{}'", source.url);
+                r = Err(e);
+                continue
+            }
+            if ! repo.has_refs_commits(refs) {
+                log::error!("Locked commits for git source '{}' are gone from \
+                    upstream", source.url);
+                r = Err(Error::GitObjectMissing);
+                continue
+            }
+        }
+        if let Err(e) = repo.set_refs(refs) {
+            r = Err(e)
+        } else {
+            log::info!("Pinned git source '{}' to locked refs", source.url)
+        }
+    }
+    r
+}
+
 fn gcb_transfer_progress(progress: Progress<'_>) -> bool {
     let network_pct =
         (100 * progress.received_objects()) / progress.total_objects();
@@ -323,7 +436,7 @@ impl Repo {
                     url: url.to_owned(),
                     mirror: optional_gmr(gmr, url),
                     repo,
-                    branches: vec![],
+                    refs: vec![],
                 };
                 repo.add_remote().and(Ok(repo))
             },
@@ -345,7 +458,7 @@ impl Repo {
                 url: url.to_owned(),
                 mirror: optional_gmr(gmr, url),
                 repo,
-                branches: vec![],
+                refs: vec![],
             }),
             Err(e) => {
                 if e.class() == ErrorClass::Os &&
@@ -409,13 +522,63 @@ impl Repo {
     pub(crate) fn sync(&self, proxy: Option<&Proxy>, terminal: bool)
         -> Result<()>
     {
+        let domain = self.get_domain();
+        let _scope = logger::domain(&domain);
+        let started = Instant::now();
+        let tags = self.drop_tags();
+        let r = self.sync_any(proxy, terminal);
+        if r.is_err() {
+            self.restore_tags(&tags)
+        }
+        metrics::record_fetch(&domain, started.elapsed());
+        r
+    }
+
+    /// Fetching with explicit refspecs never prunes, so the wanted tags are
+    /// dropped before fetching, otherwise one deleted upstream would linger
+    fn drop_tags(&self) -> Vec<(String, Oid)> {
+        let mut tags = vec![];
+        for refname in self.refs.iter() {
+            if ! refname.starts_with("refs/tags/") {
+                continue
+            }
+            let mut reference = match self.repo.find_reference(refname) {
+                Ok(reference) => reference,
+                Err(_) => continue,
+            };
+            let target = match reference.target() {
+                Some(target) => target,
+                None => continue,
+            };
+            match reference.delete() {
+                Ok(_) => tags.push((refname.clone(), target)),
+                Err(e) => log::warn!("Failed to drop tag '{}' of repo '{}' \
+                    before fetching: {}", refname, self.path.display(), e),
+            }
+        }
+        tags
+    }
+
+    /// Put back the tags dropped before a failed fetch
+    fn restore_tags(&self, tags: &[(String, Oid)]) {
+        for (refname, target) in tags.iter() {
+            if let Err(e) = self.repo.reference(
+                refname, *target, false, "restore after failed fetch")
+            {
+                log::warn!("Failed to restore tag '{}' of repo '{}': {}",
+                    refname, self.path.display(), e)
+            }
+        }
+    }
+
+    /// Through the mirror first if there's one, then the actual remote
+    fn sync_any(&self, proxy: Option<&Proxy>, terminal: bool) -> Result<()> {
         let mut refspecs_dynamic = vec![];
         let mut refspecs_ref = vec![];
         let mut refspecs = REFSPECS_HEADS_TAGS;
-        if self.branches.len() > 0 {
-            for branch in self.branches.iter() {
-                refspecs_dynamic.push(
-                    format!("+refs/heads/{}:refs/heads/{}", branch, branch));
+        if self.refs.len() > 0 {
+            for refname in self.refs.iter() {
+                refspecs_dynamic.push(format!("+{}:{}", refname, refname));
             }
             for refspec in refspecs_dynamic.iter() {
                 refspecs_ref.push(refspec.as_str())
@@ -441,12 +604,71 @@ impl Repo {
             Ok(branch) => Ok(branch),
             Err(e) => {
                 log::error!("Failed to find branch '{}': {}", branch, e);
-                Err(e.into())
+                if e.code() == ErrorCode::NotFound {
+                    Err(Error::ReferenceMissing(vec![
+                        format!("branch '{}' of '{}'", branch, self.url)]))
+                } else {
+                    Err(e.into())
+                }
             }
         }
     }
 
-    fn get_branch_commit<'a>(&'a self, branch: &str) -> Result<Commit<'a>> {
+    fn get_tag_commit<'a>(&'a self, tag: &str) -> Result<Commit<'a>> {
+        let reference = match self.repo.find_reference(
+            &format!("refs/tags/{}", tag))
+        {
+            Ok(reference) => reference,
+            Err(e) => {
+                if e.code() == ErrorCode::NotFound {
+                    log::error!("Tag '{}' does not exist in repo '{}', was it \
+                        deleted upstream at '{}'?", tag, self.path.display(),
+                        self.url);
+                    return Err(Error::ReferenceMissing(vec![
+                        format!("tag '{}' of '{}'", tag, self.url)]))
+                }
+                log::error!("Failed to find tag '{}': {}", tag, e);
+                return Err(e.into())
+            },
+        };
+        match reference.peel_to_commit() {
+            Ok(commit) => Ok(commit),
+            Err(e) => {
+                log::error!("Failed to peel tag '{}' to commit: {}", tag, e);
+                Err(e.into())
+            },
+        }
+    }
+
+    fn get_commit_by_prefix<'a>(&'a self, prefix: &str) -> Result<Commit<'a>> {
+        match self.repo.find_commit_by_prefix(prefix) {
+            Ok(commit) => Ok(commit),
+            Err(e) => {
+                log::error!("Failed to find commit '{}' in repo '{}': {}",
+                    prefix, self.path.display(), e);
+                if e.code() == ErrorCode::NotFound {
+                    Err(Error::ReferenceMissing(vec![
+                        format!("commit '{}' of '{}'", prefix, self.url)]))
+                } else {
+                    Err(e.into())
+                }
+            },
+        }
+    }
+
+    pub(crate) fn get_reference_commit<'a>(&'a self, reference: &Reference)
+        -> Result<Commit<'a>>
+    {
+        match reference {
+            Reference::Branch(branch) => self.get_branch_commit(branch),
+            Reference::Tag(tag) => self.get_tag_commit(tag),
+            Reference::Commit(commit) => self.get_commit_by_prefix(commit),
+        }
+    }
+
+    fn get_branch_commit<'a>(&'a self, branch: &str)
+        -> Result<Commit<'a>>
+    {
         let branch_gref = self.get_branch(branch)?;
         match branch_gref.get().peel_to_commit() {
             Ok(commit) => Ok(commit),
@@ -457,11 +679,22 @@ impl Repo {
         }
     }
 
+    pub(crate) fn get_commit<'a>(&'a self, id: Oid) -> Result<Commit<'a>> {
+        match self.repo.find_commit(id) {
+            Ok(commit) => Ok(commit),
+            Err(e) => {
+                log::error!("Failed to find commit '{}' in repo '{}': {}",
+                    id, self.path.display(), e);
+                Err(e.into())
+            },
+        }
+    }
+
     pub(crate) fn _get_branch_commit_id(&self, branch: &str) -> Result<Oid> {
         Ok(self.get_branch_commit(branch)?.id())
     }
 
-    fn get_commit_tree<'a>(&'a self, commit: &Commit<'a>, subtree: Option<&Path>
+    pub(crate) fn get_commit_tree<'a>(&'a self, commit: &Commit<'a>, subtree: Option<&Path>
     )   -> Result<Tree<'a>>
     {
         let tree = match commit.tree() {
@@ -498,22 +731,83 @@ impl Repo {
         }
     }
 
-    fn get_branch_tree<'a>(&'a self, branch: &str, subtree: Option<&Path>)
-        -> Result<Tree<'a>>
-    {
-        let commit = self.get_branch_commit(branch)?;
-        self.get_commit_tree(&commit, subtree)
-    }
-
-    pub(crate) fn get_branch_commit_or_subtree_id(&self,
-        branch: &str, subtree: Option<&Path>
+    pub(crate) fn get_commit_or_subtree_id(&self,
+        commit: &Commit, subtree: Option<&Path>
     ) -> Result<Oid>
     {
-        let commit = self.get_branch_commit(branch)?;
         if let None = subtree {
             return Ok(commit.id())
         }
-        Ok(self.get_commit_tree(&commit, subtree)?.id())
+        Ok(self.get_commit_tree(commit, subtree)?.id())
+    }
+
+    /// Id of the subtree in the commit, none if it's not there
+    fn get_subtree_id(commit: &Commit, subtree: &Path) -> Option<Oid> {
+        commit.tree().ok()?.get_path(subtree).ok().map(|entry| entry.id())
+    }
+
+    /// Commits reachable from `to` but not from `from`, newest first, only
+    /// those changing the subtree if it's set, at most `max` of them. Also
+    /// return whether there're more than listed, or `from` is not in the repo
+    /// so the listing could not stop there
+    pub(crate) fn commits_between<'a>(&'a self,
+        from: Option<Oid>, to: Oid, subtree: Option<&Path>, max: usize
+    ) -> Result<(Vec<Commit<'a>>, bool)>
+    {
+        let mut walk = match self.repo.revwalk() {
+            Ok(walk) => walk,
+            Err(e) => {
+                log::error!("Failed to create revwalk in repo '{}': {}",
+                    self.path.display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = walk.set_sorting(git2::Sort::TOPOLOGICAL |
+            git2::Sort::TIME)
+        {
+            log::error!("Failed to set revwalk sorting: {}", e);
+            return Err(e.into())
+        }
+        if let Err(e) = walk.push(to) {
+            log::error!("Failed to walk from commit '{}' in repo '{}': {}",
+                to, self.path.display(), e);
+            return Err(e.into())
+        }
+        let mut truncated = false;
+        if let Some(from) = from {
+            if walk.hide(from).is_err() {
+                log::warn!("Commit '{}' is no longer in repo '{}', can't \
+                    tell where the changes started", from, self.path.display());
+                truncated = true
+            }
+        }
+        let mut commits = vec![];
+        for id in walk {
+            let commit = match id {
+                Ok(id) => self.get_commit(id)?,
+                Err(e) => {
+                    log::error!("Failed to walk commits in repo '{}': {}",
+                        self.path.display(), e);
+                    return Err(e.into())
+                },
+            };
+            if let Some(subtree) = subtree {
+                let id = Self::get_subtree_id(&commit, subtree);
+                // Only compared with the first parent, merges bringing in the
+                // change count as changing it
+                let parent_id = commit.parent(0).ok().and_then(
+                    |parent| Self::get_subtree_id(&parent, subtree));
+                if id.is_none() || id == parent_id {
+                    continue
+                }
+            }
+            if commits.len() >= max {
+                truncated = true;
+                break
+            }
+            commits.push(commit)
+        }
+        Ok((commits, truncated))
     }
 
     fn get_tree_entry_blob<'a>(&'a self, tree: &Tree, name: &str)
@@ -544,19 +838,45 @@ impl Repo {
         }
     }
 
-    pub(crate) fn get_branch_entry_blob<'a>(&'a self,
-        branch: &str, subtree: Option<&Path>, name: &str
-    )
+    pub(crate) fn get_tree_pkgbuild_blob<'a>(&'a self, tree: &Tree<'a>)
         -> Result<Blob<'a>>
     {
-        let tree = self.get_branch_tree(branch, subtree)?;
-        self.get_tree_entry_blob(&tree, name)
+        self.get_tree_entry_blob(tree, "PKGBUILD")
     }
 
-    pub(crate) fn get_pkgbuild_blob(&self, branch: &str, subtree: Option<&Path>)
-        -> Result<Blob>
+    /// Apply a git-style patch on top of a tree, resulting in a new tree
+    pub(crate) fn apply_patch<'a>(&'a self, tree: &Tree<'a>, patch: &[u8])
+        -> Result<Tree<'a>>
     {
-        self.get_branch_entry_blob(branch, subtree, "PKGBUILD")
+        let diff = match Diff::from_buffer(patch) {
+            Ok(diff) => diff,
+            Err(e) => {
+                log::error!("Failed to parse patch: {}", e);
+                return Err(e.into())
+            },
+        };
+        let mut index = match self.repo.apply_to_tree(tree, &diff, None) {
+            Ok(index) => index,
+            Err(e) => {
+                log::error!("Failed to apply patch on tree '{}': {}",
+                    tree.id(), e);
+                return Err(e.into())
+            },
+        };
+        let id = match index.write_tree_to(&self.repo) {
+            Ok(id) => id,
+            Err(e) => {
+                log::error!("Failed to write patched tree: {}", e);
+                return Err(e.into())
+            },
+        };
+        match self.repo.find_tree(id) {
+            Ok(tree) => Ok(tree),
+            Err(e) => {
+                log::error!("Failed to find patched tree '{}': {}", id, e);
+                Err(e.into())
+            },
+        }
     }
 
     pub(crate) fn healthy(&self) -> bool {
@@ -578,12 +898,10 @@ impl Repo {
         };
     }
 
-    pub(crate) fn checkout<P>(&self, target: P, branch: &str, subtree: Option<&Path>)
-        -> Result<()>
+    pub(crate) fn checkout_tree<P>(&self, target: P, tree: &Tree) -> Result<()>
     where
         P: AsRef<Path>
     {
-        let tree = self.get_branch_tree(branch, subtree)?;
         self.repo.cleanup_state().map_err(Error::from)?;
         self.repo.set_workdir(
                     target.as_ref(),
@@ -595,6 +913,247 @@ impl Repo {
                 .map_err(Error::from)
     }
 
+    /// Local heads, tags peeled to commits and the symbolic target of HEAD, as
+    /// recorded in lockfile
+    pub(crate) fn get_refs(&self) -> Result<BTreeMap<String, String>> {
+        let mut refs = BTreeMap::new();
+        for glob in ["refs/heads/*", "refs/tags/*"] {
+            let references = match self.repo.references_glob(glob) {
+                Ok(references) => references,
+                Err(e) => {
+                    log::error!("Failed to list '{}' of repo '{}': {}",
+                        glob, self.path.display(), e);
+                    return Err(e.into())
+                },
+            };
+            for reference in references {
+                let reference = reference.map_err(Error::from)?;
+                let name = match reference.name() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                // Tags could point to anything, only those of commits matter
+                if let Ok(commit) = reference.peel_to_commit() {
+                    refs.insert(name.to_string(), commit.id().to_string());
+                }
+            }
+        }
+        if let Ok(head) = self.repo.find_reference("HEAD") {
+            if let Some(target) = head.symbolic_target() {
+                refs.insert(String::from("HEAD"), target.to_string());
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Whether all commits recorded in refs exist locally
+    pub(crate) fn has_refs_commits(&self, refs: &BTreeMap<String, String>)
+        -> bool
+    {
+        for (name, target) in refs.iter() {
+            if name == "HEAD" {
+                continue
+            }
+            let id = match Oid::from_str(target) {
+                Ok(id) => id,
+                Err(_) => return false,
+            };
+            if self.repo.find_commit(id).is_err() {
+                log::info!("Commit '{}' for '{}' missing in repo '{}'",
+                    target, name, self.path.display());
+                return false
+            }
+        }
+        true
+    }
+
+    /// Force local heads, tags and HEAD to the ones recorded, tags still
+    /// peeling to the recorded commits are kept as they are, e.g. annotated
+    pub(crate) fn set_refs(&self, refs: &BTreeMap<String, String>)
+        -> Result<()>
+    {
+        for (name, target) in refs.iter() {
+            if name == "HEAD" {
+                continue
+            }
+            let id = match Oid::from_str(target) {
+                Ok(id) => id,
+                Err(e) => {
+                    log::error!("Illegal commit id '{}' for '{}': {}",
+                        target, name, e);
+                    return Err(e.into())
+                },
+            };
+            if name.starts_with("refs/tags/") {
+                let kept = match self.repo.find_reference(name) {
+                    Ok(reference) => match reference.peel_to_commit() {
+                        Ok(commit) => commit.id() == id,
+                        Err(_) => false,
+                    },
+                    Err(_) => false,
+                };
+                if kept {
+                    continue
+                }
+                log::warn!("Tag '{}' of repo '{}' is not at locked commit \
+                    '{}', pinning it there", name, self.path.display(), target);
+            }
+            if let Err(e) = self.repo.reference(
+                name, id, true, "arb: pin to locked commit")
+            {
+                log::error!("Failed to pin '{}' to '{}' in repo '{}': {}",
+                    name, target, self.path.display(), e);
+                return Err(e.into())
+            }
+        }
+        if let Some(head) = refs.get("HEAD") {
+            if let Err(e) = self.repo.set_head(head) {
+                log::error!("Failed to set HEAD to '{}' in repo '{}': {}",
+                    head, self.path.display(), e);
+                return Err(e.into())
+            }
+        }
+        Ok(())
+    }
+
+    fn import_dir_tree(&self, dir: &Path, top: bool) -> Result<Oid> {
+        let mut builder = match self.repo.treebuilder(None) {
+            Ok(builder) => builder,
+            Err(e) => {
+                log::error!("Failed to create tree builder in repo '{}': {}",
+                    self.path.display(), e);
+                return Err(e.into())
+            },
+        };
+        let entries = match read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("Failed to read dir '{}': {}", dir.display(), e);
+                return Err(e.into())
+            },
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::error!("Failed to read entry from dir '{}': {}",
+                        dir.display(), e);
+                    return Err(e.into())
+                },
+            };
+            let name = entry.file_name();
+            // Leftovers of makepkg, and the git repo if it's a work tree
+            if name == ".git" || (top && (name == "src" || name == "pkg")) {
+                continue
+            }
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(e) => {
+                    log::error!("Failed to get file type of '{}': {}",
+                        path.display(), e);
+                    return Err(e.into())
+                },
+            };
+            let (id, mode) = if file_type.is_dir() {
+                (self.import_dir_tree(&path, false)?, FileMode::Tree)
+            } else if file_type.is_symlink() {
+                let target = match read_link(&path) {
+                    Ok(target) => target,
+                    Err(e) => {
+                        log::error!("Failed to read link '{}': {}",
+                            path.display(), e);
+                        return Err(e.into())
+                    },
+                };
+                match self.repo.blob(target.as_os_str().as_bytes()) {
+                    Ok(id) => (id, FileMode::Link),
+                    Err(e) => {
+                        log::error!("Failed to write link '{}' as blob: {}",
+                            path.display(), e);
+                        return Err(e.into())
+                    },
+                }
+            } else {
+                let executable = match entry.metadata() {
+                    Ok(metadata) => metadata.mode() & 0o111 != 0,
+                    Err(e) => {
+                        log::error!("Failed to get metadata of '{}': {}",
+                            path.display(), e);
+                        return Err(e.into())
+                    },
+                };
+                match self.repo.blob_path(&path) {
+                    Ok(id) => (id, match executable {
+                        true => FileMode::BlobExecutable,
+                        false => FileMode::Blob,
+                    }),
+                    Err(e) => {
+                        log::error!("Failed to write file '{}' as blob: {}",
+                            path.display(), e);
+                        return Err(e.into())
+                    },
+                }
+            };
+            if let Err(e) = builder.insert(&name, id, mode.into()) {
+                log::error!("Failed to insert '{}' into tree: {}",
+                    path.display(), e);
+                return Err(e.into())
+            }
+        }
+        match builder.write() {
+            Ok(id) => Ok(id),
+            Err(e) => {
+                log::error!("Failed to write tree for dir '{}': {}",
+                    dir.display(), e);
+                Err(e.into())
+            },
+        }
+    }
+
+    /// Import the content of a plain dir as a parentless commit with fixed
+    /// author and time, so the same content always results in the same
+    /// commit, and point refs/heads/master to it
+    pub(crate) fn import_dir(&self, dir: &Path) -> Result<Oid> {
+        let tree_id = self.import_dir_tree(dir, true)?;
+        let tree = match self.repo.find_tree(tree_id) {
+            Ok(tree) => tree,
+            Err(e) => {
+                log::error!("Failed to find imported tree '{}': {}", tree_id, e);
+                return Err(e.into())
+            },
+        };
+        let signature = match Signature::new(
+            "arb", "arb@localhost", &Time::new(0, 0))
+        {
+            Ok(signature) => signature,
+            Err(e) => {
+                log::error!("Failed to create signature: {}", e);
+                return Err(e.into())
+            },
+        };
+        let commit = match self.repo.commit(None, &signature, &signature,
+            "Imported by arb", &tree, &[])
+        {
+            Ok(commit) => commit,
+            Err(e) => {
+                log::error!("Failed to commit imported tree '{}': {}",
+                    tree_id, e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = self.repo.reference(
+            "refs/heads/master", commit, true, "import")
+        {
+            log::error!("Failed to set master of repo '{}' to '{}': {}",
+                self.path.display(), commit, e);
+            return Err(e.into())
+        }
+        log::info!("Imported '{}' into repo '{}' as commit '{}'",
+            dir.display(), self.path.display(), commit);
+        Ok(commit)
+    }
+
     fn get_domain(&self) -> String {
         if let Ok(url) = Url::parse(&self.url) {
             if let Some(domain) = url.domain() {
@@ -698,6 +1257,7 @@ impl Repo {
 
     fn sync_for_aur(
         mut repos: Vec<Self>,
+        max_threads: usize,
         hold: bool,
         proxy: Option<&Proxy>,
         terminal: bool
@@ -709,7 +1269,7 @@ impl Repo {
         if repos.is_empty() {
             return Ok(())
         }
-        Self::sync_for_domain(repos, 1, hold, proxy, terminal)
+        Self::sync_for_domain(repos, max_threads, hold, proxy, terminal)
     }
 
     fn last_fetch(&self) -> i64 {
@@ -837,6 +1397,7 @@ impl Repo {
         repos_map: HashMap<u64, Vec<Self>>,
         hold: bool,
         proxy: Option<&Proxy>,
+        scheduler: &Scheduler,
         terminal: bool
     ) -> Result<()>
     {
@@ -845,15 +1406,16 @@ impl Repo {
         for (domain, repos) in repos_map {
             let proxy_thread = proxy.and_then(
                 |proxy_actual|Some(proxy_actual.to_owned()));
+            let max_threads = scheduler.domain_threads(domain);
             if domain == 0xb463cbdec08d6265 {
                 threads.push(thread::spawn(move || {
-                    Self::sync_for_aur(
-                        repos, hold, proxy_thread.as_ref(), terminal)}))
+                    Self::sync_for_aur(repos, max_threads, hold,
+                        proxy_thread.as_ref(), terminal)}))
 
             } else {
                 threads.push(thread::spawn(move || {
                     Self::sync_for_domain(
-                        repos, 10, hold,
+                        repos, max_threads, hold,
                         proxy_thread.as_ref(), terminal)}))
             }
         }
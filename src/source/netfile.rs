@@ -3,6 +3,8 @@ use crate::{
             Error,
             Result
         },
+        logger,
+        metrics,
         source::{
             download,
             protocol::{
@@ -157,6 +159,46 @@ pub(super) fn download_source(
     return Err(Error::IntegrityError)
 }
 
+/// Download a plain file that's not a PKGBUILD source, so no integrity check,
+/// only file, http and https are supported
+pub(crate) fn download_file(url: &str, path: &std::path::Path,
+    proxy: Option<&Proxy>
+) -> Result<()>
+{
+    const MAX_TRIES: usize = 3;
+    let mut proxy_actual = None;
+    let mut max_tries = MAX_TRIES;
+    let mut enable_proxy_at = MAX_TRIES;
+    if let Some(proxy) = proxy {
+        max_tries += proxy.after;
+        enable_proxy_at = proxy.after
+    };
+    let mut r = Ok(());
+    for i in 0..max_tries {
+        if i == enable_proxy_at {
+            if i > 0 {
+                log::info!("Failed to download for {} times, using proxy", i);
+            }
+            proxy_actual = proxy.map(|proxy| proxy.url.as_str());
+        }
+        log::info!("Downloading '{}' to '{}', try {} of {}",
+            url, path.display(), i + 1, max_tries);
+        r = if url.starts_with("file://") {
+            download::file(url, path)
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            download::http(url, path, proxy_actual)
+        } else {
+            log::error!("URL '{}' is neither file, http nor https", url);
+            return Err(Error::InvalidConfig)
+        };
+        if r.is_ok() {
+            return Ok(())
+        }
+    }
+    log::error!("Failed to download '{}'", url);
+    r
+}
+
 pub(super) fn cache_source(
     source: &Source,
     integ_files: &Vec<super::cksums::IntegFile>,
@@ -166,6 +208,9 @@ pub(super) fn cache_source(
 ) -> Result<()>
 {
     assert!(integ_files.len() > 0, "No integ files");
+    let domain = url::Url::parse(&source.url).ok().and_then(
+        |url| url.host_str().map(String::from)).unwrap_or_default();
+    let _scope = logger::domain(&domain);
     let mut good_files = vec![];
     let mut bad_files = vec![];
     for integ_file in integ_files.iter() {
@@ -179,6 +224,7 @@ pub(super) fn cache_source(
         }
     }
     let bad_count = bad_files.len();
+    metrics::record_cache(&domain, bad_count == 0);
     if bad_count > 0 {
         log::info!("Missing integ files for '{}': {}",
                 source.url, bad_count);
@@ -192,7 +238,10 @@ pub(super) fn cache_source(
             Some(good_file) =>
                 bad_file.clone_file_from(good_file),
             None => download_source(
-                source, bad_file, actual_identity, skipint, proxy),
+                source, bad_file, actual_identity, skipint, proxy).map(|_|
+                    if let Ok(metadata) = bad_file.get_path().metadata() {
+                        metrics::record_download(&domain, metadata.len())
+                    }),
         };
         match r {
             Ok(_) => good_files.push(bad_file),
@@ -80,7 +80,8 @@ fn push_source(
     Err(Error::BrokenPKGBUILDs(vec![]))
 }
 
-pub(crate) fn get_sources<P> (pkgbuild: P) -> Result<Vec<Source>>
+/// Sources for the target arch, as CARCH on host is always the host one
+pub(crate) fn get_sources<P> (pkgbuild: P, carch: &str) -> Result<Vec<Source>>
 where
     P: AsRef<Path>
 {
@@ -90,6 +91,7 @@ where
         .arg(SCRIPT)
         .arg("Source reader")
         .arg(pkgbuild.as_ref())
+        .arg(carch)
         .output()
         .expect("Failed to run script");
     let mut name = None;